@@ -0,0 +1,708 @@
+use crate::api::key_extractor::ClientIp;
+use crate::audit::{AuditEntry, AuditEvent, AuditRecord, AUDIT_LOG};
+use crate::error::Error;
+use crate::jobs::{Approval, InvoiceState, Job, JobQueue, JobView, Mail, MailView};
+use crate::local_time;
+use crate::mailgun::MailgunClient;
+use crate::outbound::{OutboundInvoice, OutboundInvoiceRequest, OutboundInvoices};
+use crate::pdfgen::agenda::AgendaBuilder;
+use crate::signing::Signer;
+use crate::webhooks::{Delivery, Webhooks, DELIVERIES};
+use crate::CONFIG;
+
+use axum::{
+    extract::{FromRequestParts, Path, Query},
+    http::{
+        header::{AUTHORIZATION, CONTENT_DISPOSITION, CONTENT_TYPE},
+        request::Parts,
+        HeaderName, StatusCode,
+    },
+};
+use axum_valid::Garde;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::net::IpAddr;
+use utoipa::{IntoParams, ToSchema};
+
+/// An administrator, authenticated with `Authorization: Bearer <ADMIN_TOKEN>`.
+/// Every request is rejected if no admin token is configured.
+pub struct Admin;
+
+impl<S> FromRequestParts<S> for Admin
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+
+        match (&CONFIG.admin_token, token) {
+            // Compare the digests so that the time taken doesn't reveal the token
+            (Some(expected), Some(token))
+                if Sha256::digest(expected.as_bytes()) == Sha256::digest(token.as_bytes()) =>
+            {
+                Ok(Admin)
+            }
+            _ => Err(Error::Unauthorized),
+        }
+    }
+}
+
+/// Starts the audit record of an admin action
+fn admin_action(ip: Option<IpAddr>) -> AuditRecord {
+    AuditRecord::new(AuditEvent::AdminAction)
+        .actor("admin")
+        .ip(ip)
+}
+
+/// Records an admin action once it has run, so that the log doesn't claim actions that didn't
+/// happen. A failed action is recorded as [`AuditEvent::AdminActionFailed`] with the error.
+async fn record<T>(record: AuditRecord, result: &Result<T, Error>) {
+    let record = match result {
+        Ok(_) => record,
+        Err(e) => AuditRecord {
+            event: AuditEvent::AdminActionFailed,
+            details: Some(match record.details {
+                Some(details) => format!("{details}: {e}"),
+                None => e.to_string(),
+            }),
+            ..record
+        },
+    };
+    AUDIT_LOG.record(record).await;
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditLogQuery {
+    /// Only return the entries of this invoice
+    invoice_id: Option<String>,
+    /// Only return the entries of this event
+    #[param(inline)]
+    event: Option<AuditEvent>,
+    /// Only return the entries after this sequence number
+    after: Option<u64>,
+    /// The maximum number of entries to return, 100 by default
+    limit: Option<usize>,
+}
+
+/// Returns the entries of the audit log, oldest first
+#[utoipa::path(get, path = "/admin/audit-log",
+    params(AuditLogQuery),
+    responses(
+        (status = 200, body = Vec<AuditEntry>),
+        (status = 401, description = "Invalid or missing admin token")
+    )
+)]
+pub async fn audit_log(
+    _admin: Admin,
+    ClientIp(ip): ClientIp,
+    Query(query): Query<AuditLogQuery>,
+) -> Result<axum::Json<Vec<AuditEntry>>, Error> {
+    let entries = tokio::task::spawn_blocking(move || {
+        let matches = |entry: &AuditEntry| {
+            query
+                .invoice_id
+                .as_ref()
+                .is_none_or(|id| entry.record.invoice_id.as_ref() == Some(id))
+                && query.event.is_none_or(|event| entry.record.event == event)
+        };
+        AUDIT_LOG.query(query.after, matches, query.limit.unwrap_or(100))
+    })
+    .await?;
+    record(admin_action(ip).details("Queried the audit log"), &entries).await;
+
+    Ok(axum::Json(entries?))
+}
+
+/// Returns the invoices that couldn't be sent, oldest first
+#[utoipa::path(get, path = "/admin/outbox",
+    responses(
+        (status = 200, body = Vec<JobView>),
+        (status = 401, description = "Invalid or missing admin token")
+    )
+)]
+pub async fn outbox(
+    _admin: Admin,
+    ClientIp(ip): ClientIp,
+    jobs: JobQueue,
+) -> Result<axum::Json<Vec<JobView>>, Error> {
+    let failed = tokio::task::spawn_blocking(move || jobs.outbox()).await?;
+    record(admin_action(ip).details("Listed the outbox"), &failed).await;
+
+    Ok(axum::Json(failed?.into_iter().map(JobView::from).collect()))
+}
+
+/// Queues an invoice in the outbox to be sent again
+#[utoipa::path(post, path = "/admin/outbox/{id}/resend",
+    params(("id" = String, Path, description = "The ID of the job")),
+    responses(
+        (status = 202, body = JobView),
+        (status = 401, description = "Invalid or missing admin token"),
+        (status = 404, description = "No job with the ID"),
+        (status = 409, description = "The job hasn't failed")
+    )
+)]
+pub async fn resend(
+    _admin: Admin,
+    ClientIp(ip): ClientIp,
+    jobs: JobQueue,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, axum::Json<JobView>), Error> {
+    let job_id = id.clone();
+    let job = tokio::task::spawn_blocking(move || jobs.resend(&job_id)).await?;
+    record(
+        admin_action(ip)
+            .invoice_id(id)
+            .details("Resent an invoice from the outbox"),
+        &job,
+    )
+    .await;
+
+    Ok((StatusCode::ACCEPTED, axum::Json(job?.into())))
+}
+
+/// Returns the emails that couldn't be sent, e.g. the notifications of the submitters, oldest
+/// first
+#[utoipa::path(get, path = "/admin/outbox/mail",
+    responses(
+        (status = 200, body = Vec<MailView>),
+        (status = 401, description = "Invalid or missing admin token")
+    )
+)]
+pub async fn mail_outbox(
+    _admin: Admin,
+    ClientIp(ip): ClientIp,
+    jobs: JobQueue,
+) -> Result<axum::Json<Vec<MailView>>, Error> {
+    let failed = tokio::task::spawn_blocking(move || jobs.mail_outbox()).await?;
+    record(
+        admin_action(ip).details("Listed the emails in the outbox"),
+        &failed,
+    )
+    .await;
+
+    Ok(axum::Json(
+        failed?.into_iter().map(MailView::from).collect(),
+    ))
+}
+
+/// Queues an email in the outbox to be sent again
+#[utoipa::path(post, path = "/admin/outbox/mail/{id}/resend",
+    params(("id" = String, Path, description = "The ID of the email")),
+    responses(
+        (status = 202, body = MailView),
+        (status = 401, description = "Invalid or missing admin token"),
+        (status = 404, description = "No email with the ID"),
+        (status = 409, description = "The email hasn't failed")
+    )
+)]
+pub async fn resend_mail(
+    _admin: Admin,
+    ClientIp(ip): ClientIp,
+    jobs: JobQueue,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, axum::Json<MailView>), Error> {
+    let details = format!("Resent email {id} from the outbox");
+    let mail = tokio::task::spawn_blocking(move || jobs.resend_mail(&id)).await?;
+    record(admin_action(ip).details(details), &mail).await;
+
+    Ok((StatusCode::ACCEPTED, axum::Json(mail?.into())))
+}
+
+/// Marks an invoice paid or rejected and notifies the submitter by email and the webhook
+/// endpoints. Invoices are approved with a voucher at `/admin/invoices/approve`.
+#[utoipa::path(post, path = "/admin/invoices/{id}/state",
+    params(("id" = String, Path, description = "The ID of the invoice")),
+    request_body = InvoiceState,
+    responses(
+        (status = 200, body = JobView),
+        (status = 400, description = "Invalid state, or an approval without a voucher"),
+        (status = 401, description = "Invalid or missing admin token"),
+        (status = 404, description = "No invoice with the ID"),
+        (status = 409, description = "The invoice can't be moved to the state")
+    )
+)]
+pub async fn set_state(
+    _admin: Admin,
+    ClientIp(ip): ClientIp,
+    jobs: JobQueue,
+    client: Option<MailgunClient>,
+    webhooks: Option<Webhooks>,
+    Path(id): Path<String>,
+    axum::Json(state): axum::Json<InvoiceState>,
+) -> Result<axum::Json<JobView>, Error> {
+    let details = match &state {
+        InvoiceState::Submitted => "Marked the invoice submitted".to_string(),
+        InvoiceState::Approved { meeting } => format!("Approved the invoice at {meeting}"),
+        InvoiceState::Paid { date } => format!("Marked the invoice paid on {date}"),
+        InvoiceState::Rejected { reason } => format!("Rejected the invoice: {reason}"),
+    };
+    let (updating, job_id) = (jobs.clone(), id.clone());
+    let job = tokio::task::spawn_blocking(move || updating.set_state(&job_id, state)).await?;
+    record(admin_action(ip).invoice_id(id).details(details), &job).await;
+    let job = job?;
+
+    announce(ip, &jobs, &job, webhooks.as_ref(), client.as_ref()).await;
+
+    Ok(axum::Json(job.into()))
+}
+
+/// Tells the webhook endpoints and the submitter about the new state of the invoice. The email
+/// is queued, so that a failure to send it ends up in the outbox.
+async fn announce(
+    ip: Option<IpAddr>,
+    jobs: &JobQueue,
+    job: &Job,
+    webhooks: Option<&Webhooks>,
+    client: Option<&MailgunClient>,
+) {
+    if let Some(webhooks) = webhooks {
+        webhooks.send(job.invoice(), job.state(), job.approval());
+    }
+
+    if client.is_none() {
+        return;
+    }
+
+    let mail = Mail::Notification {
+        invoice_id: job.id.clone(),
+        state: job.state().clone(),
+    };
+    let jobs = jobs.clone();
+    // The state has changed even if the submitter can't be told about it
+    match tokio::task::spawn_blocking(move || jobs.enqueue_mail(mail, ip)).await {
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) => error!("Failed to queue the notification of {}: {e}", job.id),
+        Err(e) => error!("Failed to queue the notification of {}: {e}", job.id),
+    }
+}
+
+/// The invoices approved at a board meeting
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct BatchApproval {
+    /// The date of the meeting in `YYYY-MM-DD` format
+    date: String,
+    /// The number of the meeting in its year, e.g. 5 for the meeting 5/2025
+    meeting: u32,
+    invoices: Vec<InvoiceApproval>,
+}
+
+/// The markings of an approved invoice
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct InvoiceApproval {
+    /// The ID of the invoice
+    id: String,
+    /// The account the invoice is booked to
+    account: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ApprovalFailure {
+    /// The ID of the invoice
+    id: String,
+    error: String,
+}
+
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct BatchApprovalResult {
+    /// The approved invoices, whose vouchers can be downloaded
+    approved: Vec<JobView>,
+    /// The invoices that couldn't be approved, e.g. because they were already handled
+    failed: Vec<ApprovalFailure>,
+}
+
+/// Approves the invoices handled at a board meeting and stamps the approval on the cover page of
+/// each, making them accounting vouchers. The vouchers are numbered in the order of the request
+/// from the next free number of the fiscal year. The submitters and the webhook endpoints are
+/// notified as when setting the state.
+#[utoipa::path(post, path = "/admin/invoices/approve",
+    request_body = BatchApproval,
+    responses(
+        (status = 200, body = BatchApprovalResult),
+        (status = 400, description = "Invalid approval"),
+        (status = 401, description = "Invalid or missing admin token")
+    )
+)]
+pub async fn approve(
+    _admin: Admin,
+    ClientIp(ip): ClientIp,
+    jobs: JobQueue,
+    signer: Option<Signer>,
+    client: Option<MailgunClient>,
+    webhooks: Option<Webhooks>,
+    axum::Json(batch): axum::Json<BatchApproval>,
+) -> Result<axum::Json<BatchApprovalResult>, Error> {
+    let approvals = batch
+        .invoices
+        .into_iter()
+        .map(|invoice| {
+            let approval = Approval {
+                date: batch.date.clone(),
+                meeting: batch.meeting,
+                account: invoice.account,
+                voucher: String::new(),
+            };
+            approval.validate()?;
+            Ok((invoice.id, approval))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let mut result = BatchApprovalResult::default();
+    for (id, approval) in approvals {
+        let details = match approval.state() {
+            InvoiceState::Approved { meeting } => format!("Approved the invoice at {meeting}"),
+            _ => "Approved the invoice".to_string(),
+        };
+        let (approving, signer, job_id) = (jobs.clone(), signer.clone(), id.clone());
+
+        // Rendering the voucher is heavily blocking
+        let approved =
+            tokio::task::spawn_blocking(move || approving.approve(&job_id, approval, signer))
+                .await?;
+        let action = admin_action(ip).invoice_id(&id);
+        let action = match &approved {
+            Ok((job, voucher)) => match job.approval() {
+                Some(approval) => action
+                    .pdf(voucher)
+                    .details(format!("{details} with voucher {}", approval.voucher)),
+                None => action.pdf(voucher).details(details),
+            },
+            Err(_) => action.details(details),
+        };
+        record(action, &approved).await;
+
+        match approved {
+            Ok((job, _)) => {
+                announce(ip, &jobs, &job, webhooks.as_ref(), client.as_ref()).await;
+                result.approved.push(job.into());
+            }
+            Err(e) => {
+                warn!("Failed to approve invoice {id}: {e}");
+                result.failed.push(ApprovalFailure {
+                    id,
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
+
+    Ok(axum::Json(result))
+}
+
+/// Returns the voucher of an approved invoice, i.e. the invoice with the approval stamped on it
+#[utoipa::path(get, path = "/admin/invoices/{id}/voucher",
+    params(("id" = String, Path, description = "The ID of the invoice")),
+    responses(
+        (status = 200, content_type = "application/pdf", body = Vec<u8>),
+        (status = 401, description = "Invalid or missing admin token"),
+        (status = 404, description = "No invoice with the ID or it has no voucher")
+    )
+)]
+pub async fn voucher(
+    _admin: Admin,
+    ClientIp(ip): ClientIp,
+    jobs: JobQueue,
+    Path(id): Path<String>,
+) -> Result<([(HeaderName, &'static str); 2], Vec<u8>), Error> {
+    let job_id = id.clone();
+    let voucher = tokio::task::spawn_blocking(move || jobs.voucher(&job_id)).await?;
+    record(
+        admin_action(ip)
+            .invoice_id(id)
+            .details("Downloaded the voucher"),
+        &voucher,
+    )
+    .await;
+
+    Ok((
+        [
+            (CONTENT_TYPE, "application/pdf"),
+            (CONTENT_DISPOSITION, "inline; filename=\"tosite.pdf\""),
+        ],
+        voucher?,
+    ))
+}
+
+/// A sent invoice that hasn't been paid or rejected by its due date
+#[derive(Debug, Serialize, ToSchema)]
+pub struct OverdueInvoice {
+    /// The number of days since the due date
+    days_overdue: i64,
+    #[serde(flatten)]
+    job: JobView,
+}
+
+/// Returns the sent invoices that haven't been paid or rejected by their due date, most overdue
+/// first
+#[utoipa::path(get, path = "/admin/invoices/overdue",
+    responses(
+        (status = 200, body = Vec<OverdueInvoice>),
+        (status = 401, description = "Invalid or missing admin token")
+    )
+)]
+pub async fn overdue(
+    _admin: Admin,
+    ClientIp(ip): ClientIp,
+    jobs: JobQueue,
+) -> Result<axum::Json<Vec<OverdueInvoice>>, Error> {
+    let today = local_time::today();
+    let overdue = tokio::task::spawn_blocking(move || jobs.overdue(today)).await?;
+    record(
+        admin_action(ip).details("Listed the overdue invoices"),
+        &overdue,
+    )
+    .await;
+
+    Ok(axum::Json(
+        overdue?
+            .into_iter()
+            .map(|job| OverdueInvoice {
+                days_overdue: job
+                    .invoice()
+                    .due()
+                    .map(|due| (today - due).num_days())
+                    .unwrap_or_default(),
+                job: job.into(),
+            })
+            .collect(),
+    ))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct WebhookDeliveriesQuery {
+    /// Only return the deliveries of this invoice
+    invoice_id: Option<String>,
+    /// Only return the failed attempts
+    failed: Option<bool>,
+}
+
+/// Returns the attempts to deliver the webhooks, oldest first
+#[utoipa::path(get, path = "/admin/webhooks/deliveries",
+    params(WebhookDeliveriesQuery),
+    responses(
+        (status = 200, body = Vec<Delivery>),
+        (status = 401, description = "Invalid or missing admin token")
+    )
+)]
+pub async fn webhook_deliveries(
+    _admin: Admin,
+    ClientIp(ip): ClientIp,
+    Query(query): Query<WebhookDeliveriesQuery>,
+) -> Result<axum::Json<Vec<Delivery>>, Error> {
+    let deliveries = tokio::task::spawn_blocking(|| DELIVERIES.entries()).await?;
+    record(
+        admin_action(ip).details("Queried the webhook deliveries"),
+        &deliveries,
+    )
+    .await;
+
+    let deliveries = deliveries?
+        .into_iter()
+        .filter(|delivery| {
+            query
+                .invoice_id
+                .as_ref()
+                .is_none_or(|id| &delivery.invoice_id == id)
+        })
+        .filter(|delivery| {
+            query
+                .failed
+                .is_none_or(|failed| failed != delivery.delivered)
+        })
+        .collect();
+
+    Ok(axum::Json(deliveries))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AgendaQuery {
+    /// The meeting shown in the heading, e.g. "Hallituksen kokous 5/2025"
+    meeting: Option<String>,
+}
+
+/// Renders the sent invoices waiting for approval as a PDF appendix to the minutes of a board
+/// meeting
+#[utoipa::path(get, path = "/admin/agenda",
+    params(AgendaQuery),
+    responses(
+        (status = 200, content_type = "application/pdf", body = Vec<u8>),
+        (status = 401, description = "Invalid or missing admin token")
+    )
+)]
+pub async fn agenda(
+    _admin: Admin,
+    ClientIp(ip): ClientIp,
+    jobs: JobQueue,
+    Query(query): Query<AgendaQuery>,
+) -> Result<([(HeaderName, &'static str); 2], Vec<u8>), Error> {
+    // Rendering the PDF is heavily blocking
+    let pdf = tokio::task::spawn_blocking(move || {
+        let pending = jobs.pending()?;
+        AgendaBuilder::new(pending.iter().map(|job| job.invoice()))
+            .meeting(query.meeting)
+            .pdf_a(CONFIG.pdf_a)
+            .build_pdf()
+    })
+    .await?;
+    record(admin_action(ip).details("Rendered the agenda"), &pdf).await;
+
+    Ok((
+        [
+            (CONTENT_TYPE, "application/pdf"),
+            (CONTENT_DISPOSITION, "inline; filename=\"laskut.pdf\""),
+        ],
+        pdf?,
+    ))
+}
+
+/// Queues the outbound invoice to be emailed to the customer. The worker records when it was
+/// sent, and an email that can't be sent is kept in the outbox.
+async fn send_outbound(ip: Option<IpAddr>, jobs: JobQueue, number: String) -> Result<(), Error> {
+    let mail = Mail::Outbound { number };
+    tokio::task::spawn_blocking(move || jobs.enqueue_mail(mail, ip)).await??;
+    Ok(())
+}
+
+/// Issues an invoice from the guild to an external organisation and queues it to be emailed to
+/// the customer. Without Mailgun the invoice is left unsent, to be delivered by hand.
+#[utoipa::path(post, path = "/admin/outbound-invoices",
+    request_body = OutboundInvoiceRequest,
+    responses(
+        (status = 201, body = OutboundInvoice),
+        (status = 401, description = "Invalid or missing admin token"),
+        (status = 422, description = "Invalid invoice"),
+        (status = 501, description = "Outbound invoices are not configured")
+    )
+)]
+pub async fn issue_outbound(
+    _admin: Admin,
+    ClientIp(ip): ClientIp,
+    outbound: Option<OutboundInvoices>,
+    jobs: JobQueue,
+    client: Option<MailgunClient>,
+    Garde(axum::Json(request)): Garde<axum::Json<OutboundInvoiceRequest>>,
+) -> Result<(StatusCode, axum::Json<OutboundInvoice>), Error> {
+    let outbound = outbound.ok_or(Error::OutboundDisabled)?;
+
+    // Rendering the invoice is heavily blocking
+    let customer = request.customer.name.clone();
+    let issued = tokio::task::spawn_blocking(move || outbound.issue(request)).await?;
+    let action = match &issued {
+        Ok((invoice, pdf)) => admin_action(ip).pdf(pdf).details(format!(
+            "Issued outbound invoice {} to {customer}",
+            invoice.number
+        )),
+        Err(_) => admin_action(ip).details(format!("Issued an outbound invoice to {customer}")),
+    };
+    record(action, &issued).await;
+    let (invoice, _) = issued?;
+
+    // The number has been taken, so the invoice is issued even if it can't be queued
+    if client.is_some() {
+        if let Err(e) = send_outbound(ip, jobs, invoice.number.clone()).await {
+            error!("Failed to queue outbound invoice {}: {e}", invoice.number);
+        }
+    }
+
+    Ok((StatusCode::CREATED, axum::Json(invoice)))
+}
+
+/// Returns the issued outbound invoices in the order of their numbers
+#[utoipa::path(get, path = "/admin/outbound-invoices",
+    responses(
+        (status = 200, body = Vec<OutboundInvoice>),
+        (status = 401, description = "Invalid or missing admin token"),
+        (status = 501, description = "Outbound invoices are not configured")
+    )
+)]
+pub async fn outbound_invoices(
+    _admin: Admin,
+    ClientIp(ip): ClientIp,
+    outbound: Option<OutboundInvoices>,
+) -> Result<axum::Json<Vec<OutboundInvoice>>, Error> {
+    let outbound = outbound.ok_or(Error::OutboundDisabled)?;
+    let invoices = tokio::task::spawn_blocking(move || outbound.all()).await?;
+    record(
+        admin_action(ip).details("Listed the outbound invoices"),
+        &invoices,
+    )
+    .await;
+
+    Ok(axum::Json(invoices?))
+}
+
+/// Returns the PDF of an outbound invoice
+#[utoipa::path(get, path = "/admin/outbound-invoices/{number}/pdf",
+    params(("number" = String, Path, description = "The number of the invoice")),
+    responses(
+        (status = 200, content_type = "application/pdf", body = Vec<u8>),
+        (status = 401, description = "Invalid or missing admin token"),
+        (status = 404, description = "No outbound invoice with the number"),
+        (status = 501, description = "Outbound invoices are not configured")
+    )
+)]
+pub async fn outbound_pdf(
+    _admin: Admin,
+    ClientIp(ip): ClientIp,
+    outbound: Option<OutboundInvoices>,
+    Path(number): Path<String>,
+) -> Result<([(HeaderName, String); 2], Vec<u8>), Error> {
+    let outbound = outbound.ok_or(Error::OutboundDisabled)?;
+    let details = format!("Downloaded outbound invoice {number}");
+    let downloaded = tokio::task::spawn_blocking(move || outbound.with_pdf(&number)).await?;
+    record(admin_action(ip).details(details), &downloaded).await;
+    let (invoice, pdf) = downloaded?;
+
+    Ok((
+        [
+            (CONTENT_TYPE, "application/pdf".to_string()),
+            (
+                CONTENT_DISPOSITION,
+                format!("inline; filename=\"lasku-{}.pdf\"", invoice.number),
+            ),
+        ],
+        pdf,
+    ))
+}
+
+/// Queues an outbound invoice to be emailed to the customer again
+#[utoipa::path(post, path = "/admin/outbound-invoices/{number}/send",
+    params(("number" = String, Path, description = "The number of the invoice")),
+    responses(
+        (status = 202, body = OutboundInvoice),
+        (status = 401, description = "Invalid or missing admin token"),
+        (status = 404, description = "No outbound invoice with the number"),
+        (status = 501, description = "Outbound invoices or email are not configured")
+    )
+)]
+pub async fn send_outbound_invoice(
+    _admin: Admin,
+    ClientIp(ip): ClientIp,
+    outbound: Option<OutboundInvoices>,
+    jobs: JobQueue,
+    client: Option<MailgunClient>,
+    Path(number): Path<String>,
+) -> Result<(StatusCode, axum::Json<OutboundInvoice>), Error> {
+    let outbound = outbound.ok_or(Error::OutboundDisabled)?;
+    client.ok_or(Error::MailDisabled)?;
+    let details = format!("Resent outbound invoice {number}");
+    let sent = async {
+        let invoice = tokio::task::spawn_blocking(move || outbound.get(&number))
+            .await??
+            .ok_or(Error::OutboundInvoiceNotFound)?;
+        send_outbound(ip, jobs, invoice.number.clone()).await?;
+        Ok(invoice)
+    }
+    .await;
+    record(admin_action(ip).details(details), &sent).await;
+
+    Ok((StatusCode::ACCEPTED, axum::Json(sent?)))
+}
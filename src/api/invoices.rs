@@ -1,9 +1,21 @@
 use std::sync::LazyLock;
 
+use crate::api::key_extractor::ClientIp;
+use crate::audit::{AuditEvent, AuditRecord, AUDIT_LOG};
+use crate::bank;
+use crate::duplicates::{PossibleDuplicate, ReceiptEntry, RECEIPTS};
 use crate::error::Error;
-use crate::mailgun::MailgunClient;
+use crate::idempotency::{Idempotency, IdempotencyKey, IdempotencyStore};
+use crate::jobs::{InvoiceState, JobQueue};
+use crate::local_time;
+use crate::util::hex;
+use crate::webhooks::Webhooks;
+use crate::CONFIG;
 
-use axum::{body::Bytes, http::StatusCode};
+use axum::{
+    body::Bytes,
+    http::{header::LOCATION, HeaderName, StatusCode},
+};
 use axum_typed_multipart::{
     FieldData, FieldMetadata, TryFromChunks, TryFromMultipart, TypedMultipart, TypedMultipartError,
 };
@@ -14,11 +26,28 @@ use garde::Validate;
 use iban::Iban;
 use regex::Regex;
 use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use utoipa::ToSchema;
 
 static ALLOWED_FILENAME: LazyLock<Regex> =
     LazyLock::new(|| Regex::new(r"(?i)\.(jpg|jpeg|png|gif|svg|pdf)$").unwrap());
 
+/// A Finnish business ID ("Y-tunnus"): seven digits, a hyphen and a check digit
+static BUSINESS_ID: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^(\d{7})-(\d)$").unwrap());
+
+/// The EU VAT numbers without spaces, each a country code followed by the national number in the
+/// format of the country
+static VAT_NUMBER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(concat!(
+        r"^(ATU\d{8}|BE[01]\d{9}|BG\d{9,10}|CY\d{8}[A-Z]|CZ\d{8,10}|DE\d{9}|DK\d{8}|",
+        r"EE\d{9}|EL\d{9}|ES[A-Z0-9]\d{7}[A-Z0-9]|FI\d{8}|FR[A-HJ-NP-Z0-9]{2}\d{9}|",
+        r"HR\d{11}|HU\d{8}|IE(\d{7}[A-W][A-IW]?|\d[A-Z+*]\d{5}[A-W])|IT\d{11}|",
+        r"LT(\d{9}|\d{12})|LU\d{8}|LV\d{11}|MT\d{8}|NL\d{9}B\d{2}|PL\d{10}|PT\d{9}|",
+        r"RO\d{2,10}|SE\d{12}|SI\d{8}|SK\d{10}|XI(\d{9}|\d{12}|GD\d{3}|HA\d{3}))$"
+    ))
+    .unwrap()
+});
+
 #[axum_typed_multipart::async_trait]
 impl TryFromChunks for Invoice {
     async fn try_from_chunks(
@@ -38,6 +67,98 @@ fn is_valid_iban(value: &str, _: &()) -> garde::Result {
     }
 }
 
+/// A BIC is required for accounts outside SEPA, and one given for any account must be formatted
+/// as a BIC
+fn is_valid_bic(iban: &str) -> impl FnOnce(&Option<String>, &()) -> garde::Result + '_ {
+    move |value, _| match (value, iban.parse::<Iban>()) {
+        (Some(bic), _) if !bank::is_bic(bic) => {
+            Err(garde::Error::new("not a valid BIC, e.g. NDEAFIHH"))
+        }
+        (None, Ok(iban)) if !bank::is_sepa(&iban) => Err(garde::Error::new(
+            "the BIC is required for accounts outside SEPA",
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// The weights of the digits of a Finnish business ID, whose check digit is 11 minus the remainder
+/// of the weighted sum divided by 11, or 0 if the remainder is 0. No valid ID has the remainder 1.
+const BUSINESS_ID_WEIGHTS: [u32; 7] = [7, 9, 10, 5, 8, 4, 2];
+
+/// Whether the seven digits and the check digit make up a valid Finnish business ID
+fn has_business_id_check_digit(digits: &str, check: &str) -> bool {
+    let sum: u32 = digits
+        .chars()
+        .filter_map(|digit| digit.to_digit(10))
+        .zip(BUSINESS_ID_WEIGHTS)
+        .map(|(digit, weight)| digit * weight)
+        .sum();
+    let expected = match sum % 11 {
+        0 => 0,
+        1 => return false,
+        remainder => 11 - remainder,
+    };
+    check.parse() == Ok(expected)
+}
+
+pub(crate) fn is_valid_business_id(value: &Option<String>, _: &()) -> garde::Result {
+    let Some(value) = value else {
+        return Ok(());
+    };
+
+    match BUSINESS_ID.captures(value) {
+        None => Err(garde::Error::new(
+            "the business ID must be seven digits, a hyphen and a check digit",
+        )),
+        Some(id) if !has_business_id_check_digit(&id[1], &id[2]) => Err(garde::Error::new(
+            "the check digit of the business ID is invalid",
+        )),
+        Some(_) => Ok(()),
+    }
+}
+
+/// An EU VAT number, which may be written with spaces. Finnish VAT numbers are the business ID
+/// without the hyphen, so their check digit is verified as well.
+pub(crate) fn is_valid_vat_number(value: &Option<String>, _: &()) -> garde::Result {
+    let Some(value) = value else {
+        return Ok(());
+    };
+
+    let vat_number: String = value
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_uppercase();
+    if !VAT_NUMBER.is_match(&vat_number) {
+        return Err(garde::Error::new(
+            "not a valid EU VAT number, e.g. FI12345671",
+        ));
+    }
+
+    match vat_number.strip_prefix("FI") {
+        Some(digits) if !has_business_id_check_digit(&digits[..7], &digits[7..]) => Err(
+            garde::Error::new("the check digit of the VAT number is invalid"),
+        ),
+        _ => Ok(()),
+    }
+}
+
+pub(crate) fn is_valid_due_date(value: &Option<String>, _: &()) -> garde::Result {
+    let Some(value) = value else {
+        return Ok(());
+    };
+
+    match chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        Err(_) => Err(garde::Error::new(
+            "the due date must be in YYYY-MM-DD format",
+        )),
+        Ok(date) if date < local_time::today() => {
+            Err(garde::Error::new("the due date is in the past"))
+        }
+        Ok(_) => Ok(()),
+    }
+}
+
 fn is_valid_phone_number(value: &str, _: &()) -> garde::Result {
     use phonenumber::country::Id;
     // Works if number is in international format
@@ -81,9 +202,23 @@ pub struct Invoice {
     /// The recipient's address
     #[garde(dive)]
     pub address: Address,
+    /// The business ID ("Y-tunnus") of a company or a freelancer billing the guild, e.g.
+    /// `1234567-1`
+    #[garde(custom(is_valid_business_id))]
+    #[serde(default)]
+    pub business_id: Option<String>,
+    /// The EU VAT number of a company or a freelancer billing the guild, e.g. `FI12345671`
+    #[garde(custom(is_valid_vat_number))]
+    #[serde(default)]
+    pub vat_number: Option<String>,
     /// The recipient's bank account number, must be a valid iban bank account number
     #[garde(length(chars, max = 128), custom(is_valid_iban))]
     pub bank_account_number: String,
+    /// The BIC of the recipient's bank, required for accounts outside SEPA. Derived by the service
+    /// for Finnish accounts if not given.
+    #[garde(custom(is_valid_bic(&self.bank_account_number)))]
+    #[serde(default)]
+    pub bic: Option<String>,
     /// The subject of the invoice, at least 1 character and at most 128 characters long
     #[garde(length(chars, min = 1, max = 128))]
     pub subject: String,
@@ -101,10 +236,70 @@ pub struct Invoice {
     /// The rows of the invoice
     #[garde(length(min = 1), dive)]
     pub rows: Vec<InvoiceRow>,
+    /// The requested due date in `YYYY-MM-DD` format, which can't be in the past. By default the
+    /// invoice is due after the payment terms given by `PAYMENT_TERMS_DAYS`.
+    #[garde(custom(is_valid_due_date))]
+    #[serde(default)]
+    pub due_date: Option<String>,
     // NOTE: We get the attachments from the multipart form
     #[garde(skip)]
     #[serde(skip_deserializing)]
     pub attachments: Vec<InvoiceAttachment>,
+    /// The ID of the invoice, assigned by the service
+    #[garde(skip)]
+    #[serde(skip_deserializing)]
+    pub id: String,
+    /// The time the invoice was submitted in RFC 3339 format in Finnish time, assigned by the
+    /// service. The invoice is dated by it however late it is rendered.
+    #[garde(skip)]
+    #[serde(skip_deserializing)]
+    pub issued_at: String,
+    /// Earlier invoices this one may be a duplicate of, e.g. because of a receipt attached to
+    /// both. Filled in by the service.
+    #[garde(skip)]
+    #[serde(skip_deserializing)]
+    pub possible_duplicates: Vec<PossibleDuplicate>,
+    /// Why the invoice has no bank barcode, which only Finnish accounts have. Filled in by the
+    /// service.
+    #[garde(skip)]
+    #[serde(skip_deserializing)]
+    pub barcode_note: Option<String>,
+}
+
+impl Invoice {
+    /// The time the invoice was issued in Finnish time
+    pub fn issued(&self) -> Option<chrono::DateTime<chrono::FixedOffset>> {
+        chrono::DateTime::parse_from_rfc3339(&self.issued_at).ok()
+    }
+
+    /// The date the invoice is due, or `None` for invoices submitted before due dates
+    pub fn due(&self) -> Option<chrono::NaiveDate> {
+        self.due_date
+            .as_deref()
+            .and_then(|date| chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").ok())
+    }
+}
+
+#[cfg(test)]
+impl Invoice {
+    /// A valid invoice with a new ID for the unit tests, which change the fields they test, like
+    /// `tests/common/fixtures.rs` does for the integration tests
+    pub(crate) fn test_fixture() -> Self {
+        let mut invoice: Invoice = serde_json::from_value(serde_json::json!({
+            "recipient_name": "Test User",
+            "recipient_email": "test@example.com",
+            "address": { "street": "Test Street 1", "city": "Helsinki", "zip": "00100" },
+            "bank_account_number": "FI21 1234 5600 0007 85",
+            "subject": "Test Invoice",
+            "description": "",
+            "phone_number": "+358401234567",
+            "attachment_descriptions": [],
+            "rows": [{ "product": "Test Product", "unit_price": 1000 }]
+        }))
+        .expect("Invalid test invoice");
+        invoice.id = uuid::Uuid::new_v4().to_string();
+        invoice
+    }
 }
 
 #[derive(TryFromMultipart, Validate, ToSchema)]
@@ -129,6 +324,12 @@ pub struct InvoiceRow {
     /// must be positive
     #[garde(range(min = 1))]
     pub unit_price: i32,
+    /// The optional accounting category of the row, e.g. a budget item, maximum length of 128
+    /// characters. The categories are listed in the board meeting agenda and added as keywords
+    /// to the PDF metadata.
+    #[garde(length(chars, max = 128))]
+    #[serde(default)]
+    pub category: Option<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
@@ -155,19 +356,36 @@ fn try_handle_file(field: FieldData<Bytes>) -> Result<InvoiceAttachment, Error>
     })
 }
 
-/// Creates an invoice with the given data and attachments and sends it by email to the treasurer
+/// Digest of the contents of a submission, which unlike the raw multipart body doesn't depend on
+/// the boundary chosen by the client
+fn fingerprint(invoice: &Invoice, attachments: &[InvoiceAttachment]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(serde_json::to_vec(invoice).unwrap_or_default());
+    for attachment in attachments {
+        hasher.update((attachment.bytes.len() as u64).to_be_bytes());
+        hasher.update(&attachment.bytes);
+    }
+
+    hex(&hasher.finalize())
+}
+
+/// Validates an invoice with the given data and attachments and queues it to be rendered and sent
+/// by email to the treasurer. The ID of the returned invoice is the ID of the job, whose progress
+/// can be polled from `/jobs/{id}`.
 #[utoipa::path(post, path = "/invoices", 
     request_body(content_type = "multipart/form-data", content = InvoiceForm), 
     responses(
-        (status = 201, body = Invoice)
+        (status = 202, body = Invoice, headers(("Location" = String, description = "The URL of the job status")))
     )
 )]
 pub async fn create(
-    client: Option<MailgunClient>,
+    jobs: JobQueue,
+    ClientIp(ip): ClientIp,
+    idempotency: IdempotencyStore,
+    IdempotencyKey(idempotency_key): IdempotencyKey,
+    webhooks: Option<Webhooks>,
     Garde(TypedMultipart(mut multipart)): Garde<TypedMultipart<InvoiceForm>>,
-) -> Result<(StatusCode, axum::Json<Invoice>), Error> {
-    use crate::pdfgen::DocumentBuilder;
-
+) -> Result<(StatusCode, [(HeaderName, String); 1], axum::Json<Invoice>), Error> {
     let attachments: Vec<InvoiceAttachment> =
         Result::from_iter(multipart.attachments.into_iter().map(try_handle_file))?;
 
@@ -179,43 +397,74 @@ pub async fn create(
         })
         .collect();
 
-    let inner_data = multipart.data.clone();
+    let pending = match idempotency_key {
+        Some(key) => match idempotency.begin(key, fingerprint(&multipart.data, &attachments))? {
+            Idempotency::Replay(invoice) => return Ok(accepted(*invoice)),
+            Idempotency::New(pending) => Some(pending),
+        },
+        None => None,
+    };
 
-    // PDF compilation is heavily blocking
-    let pdf = tokio::task::spawn_blocking(move || -> Result<_, Error> {
-        let (document, attached_pdfs) =
-            DocumentBuilder::new(inner_data, attachments).build_with_pdfs()?;
+    let issued_at = local_time::now();
+    multipart.data.id = uuid::Uuid::new_v4().to_string();
+    multipart.data.issued_at = issued_at.to_rfc3339();
+    multipart.data.due_date.get_or_insert_with(|| {
+        (issued_at.date_naive() + chrono::Days::new(CONFIG.payment_terms_days.into()))
+            .format("%Y-%m-%d")
+            .to_string()
+    });
+    if let Ok(iban) = multipart.data.bank_account_number.parse::<Iban>() {
+        if multipart.data.bic.is_none() {
+            multipart.data.bic = bank::finnish_bic(&iban).map(str::to_string);
+        }
+        multipart.data.barcode_note = bank::barcode_note(&iban).map(str::to_string);
+    }
 
-        let pdf = typst_pdf::pdf(&document, &typst_pdf::PdfOptions::default()).unwrap();
+    let mut inner_data = multipart.data;
 
-        let mut pdfs = vec![pdf];
-        pdfs.extend_from_slice(
-            attached_pdfs
-                .into_iter()
-                .map(|a| a.bytes)
-                .collect::<Vec<_>>()
-                .as_slice(),
-        );
+    // Reject broken PDFs while the client is still waiting, as retrying the job won't fix them.
+    // The idempotency key is completed as soon as the job is queued, as the blocking task runs
+    // to the end even if the client disconnects and a retry must not queue the invoice again.
+    let invoice = tokio::task::spawn_blocking(move || {
+        for attachment in attachments.iter().filter(|a| crate::pdfgen::is_pdf(a)) {
+            crate::merge::load_pdf(attachment)?;
+        }
 
-        let pdf = crate::merge::merge_pdf(pdfs)?;
-        Ok(pdf)
-    })
-    .await??;
+        // Hashing the images is heavily blocking
+        inner_data.possible_duplicates =
+            RECEIPTS.check(ReceiptEntry::new(&inner_data, &attachments));
 
-    if let Some(client) = client {
-        client.send_mail(&multipart.data, pdf).await?;
-    } else {
-        use tempfile::NamedTempFile;
-        use tokio::fs::File;
-        use tokio::io::AsyncWriteExt;
+        let job = jobs.enqueue(inner_data, attachments, ip)?;
+        if let Some(pending) = pending {
+            pending.complete(job.invoice().clone());
+        }
 
-        let tmp = NamedTempFile::with_suffix(".pdf")?;
-        let (file, path) = tmp.keep().unwrap();
-        let mut file = File::from_std(file);
-        file.write_all(&pdf).await?;
+        // Rendering and sending the invoice are recorded by the worker
+        AUDIT_LOG.record_blocking(
+            AuditRecord::new(AuditEvent::Submission)
+                .actor(format!(
+                    "{} <{}>",
+                    job.invoice().recipient_name,
+                    job.invoice().recipient_email
+                ))
+                .ip(ip)
+                .invoice_id(&job.id),
+        );
+        Ok::<_, Error>(job.invoice().clone())
+    })
+    .await??;
 
-        info!("Wrote invoice to {:?}", path);
+    if let Some(webhooks) = webhooks {
+        webhooks.send(&invoice, &InvoiceState::Submitted, None);
     }
 
-    Ok((StatusCode::CREATED, axum::Json(multipart.data)))
+    Ok(accepted(invoice))
+}
+
+fn accepted(invoice: Invoice) -> (StatusCode, [(HeaderName, String); 1], axum::Json<Invoice>) {
+    (
+        StatusCode::ACCEPTED,
+        [(LOCATION, format!("/jobs/{}", invoice.id))],
+        axum::Json(invoice),
+    )
 }
@@ -0,0 +1,18 @@
+use crate::error::Error;
+use crate::jobs::{JobQueue, JobView};
+
+use axum::extract::Path;
+
+/// Returns the progress of the job rendering and sending an invoice
+#[utoipa::path(get, path = "/jobs/{id}",
+    params(("id" = String, Path, description = "The ID of the job, as returned when submitting the invoice")),
+    responses(
+        (status = 200, body = JobView),
+        (status = 404, description = "No job with the ID")
+    )
+)]
+pub async fn status(jobs: JobQueue, Path(id): Path<String>) -> Result<axum::Json<JobView>, Error> {
+    let job = tokio::task::spawn_blocking(move || jobs.get(&id)).await??;
+
+    Ok(axum::Json(job.ok_or(Error::JobNotFound)?.into()))
+}
@@ -1,5 +1,9 @@
-use std::net::IpAddr;
+use std::{convert::Infallible, net::IpAddr};
 
+use axum::{
+    extract::FromRequestParts,
+    http::{request::Parts, Request},
+};
 use tower_governor::{
     key_extractor::{KeyExtractor, PeerIpKeyExtractor},
     GovernorError,
@@ -15,6 +19,34 @@ impl IpExtractor {
     pub fn header_extractor(header_name: &'static str) -> Self {
         Self::HeaderKeyExtractor { header_name }
     }
+
+    /// The extractor configured with `IP_EXTRACTOR_HEADER`, or the peer IP if not set
+    pub fn from_config() -> Self {
+        crate::CONFIG
+            .ip_extractor_header
+            .as_ref()
+            .map(|ip_header| Self::header_extractor(ip_header))
+            .unwrap_or(Self::PeerIpKeyExtractor)
+    }
+}
+
+/// The IP address of the client as seen by the rate limiter, if it can be extracted
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl<S> FromRequestParts<S> for ClientIp
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        // The extractor works on requests, so build one with the headers and the connection info
+        let mut request = Request::new(());
+        *request.headers_mut() = parts.headers.clone();
+        *request.extensions_mut() = parts.extensions.clone();
+
+        Ok(Self(IpExtractor::from_config().extract(&request).ok()))
+    }
 }
 
 impl KeyExtractor for IpExtractor {
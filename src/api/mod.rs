@@ -1,6 +1,9 @@
 use axum::{
+    body::Body,
     extract::DefaultBodyLimit,
-    http::{HeaderValue, Method, Request},
+    http::{HeaderValue, Method, Request, StatusCode},
+    middleware::{self, Next},
+    response::Response,
     Router,
 };
 use std::sync::Arc;
@@ -11,10 +14,18 @@ use utoipa::openapi::{ContactBuilder, InfoBuilder, OpenApiBuilder};
 use utoipa_axum::{router::OpenApiRouter, routes};
 use utoipa_swagger_ui::SwaggerUi;
 
-use crate::{api::key_extractor::IpExtractor, CONFIG};
+use crate::{
+    api::key_extractor::IpExtractor,
+    audit::{AuditEvent, AuditRecord, AUDIT_LOG},
+    CONFIG,
+};
 
+pub mod admin;
 pub mod invoices;
+pub mod jobs;
 mod key_extractor;
+pub mod signatures;
+pub mod unsubscribe;
 
 pub fn app() -> Router<crate::state::State> {
     let cors_layer = CorsLayer::new().allow_origin(
@@ -25,11 +36,7 @@ pub fn app() -> Router<crate::state::State> {
             .collect::<Vec<_>>(),
     );
 
-    let extractor = CONFIG
-        .ip_extractor_header
-        .as_ref()
-        .map(|ip_header| IpExtractor::header_extractor(ip_header))
-        .unwrap_or(IpExtractor::PeerIpKeyExtractor);
+    let extractor = IpExtractor::from_config();
 
     let governor_config = Arc::new(
         GovernorConfigBuilder::default()
@@ -69,6 +76,23 @@ pub fn app() -> Router<crate::state::State> {
             .build(),
     )
     .routes(routes!(health, invoices::create))
+    .routes(routes!(jobs::status))
+    .routes(routes!(signatures::verify))
+    .routes(routes!(admin::audit_log))
+    .routes(routes!(admin::outbox))
+    .routes(routes!(admin::resend))
+    .routes(routes!(admin::mail_outbox))
+    .routes(routes!(admin::resend_mail))
+    .routes(routes!(admin::set_state))
+    .routes(routes!(admin::approve))
+    .routes(routes!(admin::voucher))
+    .routes(routes!(admin::overdue))
+    .routes(routes!(admin::webhook_deliveries))
+    .routes(routes!(admin::agenda))
+    .routes(routes!(admin::issue_outbound, admin::outbound_invoices))
+    .routes(routes!(admin::outbound_pdf))
+    .routes(routes!(admin::send_outbound_invoice))
+    .routes(routes!(unsubscribe::confirm, unsubscribe::unsubscribe))
     .split_for_parts();
 
     Router::new()
@@ -79,6 +103,9 @@ pub fn app() -> Router<crate::state::State> {
         // Limit the body to 24 MiB since the email is limited to 25 MiB
         .layer(RequestBodyLimitLayer::new(24 * 1024 * 1024))
         .layer(GovernorLayer::new(governor_config))
+        .layer(middleware::from_fn(move |request, next| {
+            audit_rate_limit(extractor, request, next)
+        }))
         .layer(
             TraceLayer::new_for_http().make_span_with(move |req: &Request<_>| {
                 let ip = extractor
@@ -96,6 +123,25 @@ pub fn app() -> Router<crate::state::State> {
         )
 }
 
+/// Records the requests rejected by the rate limiter in the audit log
+async fn audit_rate_limit(extractor: IpExtractor, request: Request<Body>, next: Next) -> Response {
+    let ip = extractor.extract(&request).ok();
+    let details = format!("{} {}", request.method(), request.uri());
+
+    let response = next.run(request).await;
+    if response.status() == StatusCode::TOO_MANY_REQUESTS {
+        AUDIT_LOG
+            .record(
+                AuditRecord::new(AuditEvent::RateLimited)
+                    .ip(ip)
+                    .details(details),
+            )
+            .await;
+    }
+
+    response
+}
+
 /// Checks the health of the service and returns build information
 #[utoipa::path(get, path = "/health", responses((status = 200, body = String)))]
 async fn health() -> String {
@@ -0,0 +1,43 @@
+use crate::api::invoices::InvoiceAttachment;
+use crate::error::Error;
+use crate::signing::{Signer, Verification};
+
+use axum::body::Bytes;
+use axum_typed_multipart::{FieldData, TryFromMultipart, TypedMultipart};
+use utoipa::ToSchema;
+
+#[derive(TryFromMultipart, ToSchema)]
+pub struct VerificationForm {
+    /// The signed PDF to verify
+    #[form_data(limit = "unlimited")]
+    #[schema(value_type = Vec<u8>)]
+    pub file: FieldData<Bytes>,
+}
+
+/// Verifies that a PDF was signed by this service and hasn't been modified after signing
+#[utoipa::path(post, path = "/signatures/verify",
+    request_body(content_type = "multipart/form-data", content = VerificationForm),
+    responses(
+        (status = 200, body = Verification),
+        (status = 501, description = "Signing is not configured")
+    )
+)]
+pub async fn verify(
+    signer: Option<Signer>,
+    TypedMultipart(form): TypedMultipart<VerificationForm>,
+) -> Result<axum::Json<Verification>, Error> {
+    let signer = signer.ok_or(Error::SigningDisabled)?;
+
+    let file = InvoiceAttachment {
+        filename: form
+            .file
+            .metadata
+            .file_name
+            .unwrap_or_else(|| "document.pdf".to_string()),
+        bytes: form.file.contents.to_vec(),
+    };
+
+    let verification = tokio::task::spawn_blocking(move || signer.verify_pdf(&file)).await??;
+
+    Ok(axum::Json(verification))
+}
@@ -0,0 +1,64 @@
+use crate::error::Error;
+use crate::notifications::{verify_unsubscribe_token, UNSUBSCRIBED};
+
+use axum::{extract::Query, response::Html};
+use serde_derive::Deserialize;
+use utoipa::IntoParams;
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UnsubscribeQuery {
+    /// The email address of the submitter
+    email: String,
+    /// The signature of the email address from the unsubscribe link
+    token: String,
+}
+
+/// Asks the submitter to confirm stopping the notifications. The link is included in every
+/// notification, and opening it changes nothing, as e.g. link scanners open the links in emails.
+#[utoipa::path(get, path = "/unsubscribe",
+    params(UnsubscribeQuery),
+    responses(
+        (status = 200, body = String, content_type = "text/html"),
+        (status = 400, description = "Invalid unsubscribe link")
+    )
+)]
+pub async fn confirm(Query(query): Query<UnsubscribeQuery>) -> Result<Html<&'static str>, Error> {
+    if !verify_unsubscribe_token(&query.email, &query.token) {
+        return Err(Error::InvalidUnsubscribeLink);
+    }
+
+    // Without an action the form is posted to the same URL, including the query
+    Ok(Html(
+        "<form method=\"post\">\
+         <p>Haluatko lopettaa sähköposti-ilmoitukset laskujesi käsittelystä?</p>\
+         <p>Do you want to stop the emails about the handling of your invoices?</p>\
+         <button type=\"submit\">Lopeta ilmoitukset / Unsubscribe</button>\
+         </form>",
+    ))
+}
+
+/// Stops the notifications about the handling of the invoices sent to the email address. Posted
+/// by the confirmation page, and by email clients supporting one-click unsubscribe (RFC 8058)
+/// with the body `List-Unsubscribe=One-Click`.
+#[utoipa::path(post, path = "/unsubscribe",
+    params(UnsubscribeQuery),
+    responses(
+        (status = 200, body = String, content_type = "text/html"),
+        (status = 400, description = "Invalid unsubscribe link")
+    )
+)]
+pub async fn unsubscribe(
+    Query(query): Query<UnsubscribeQuery>,
+) -> Result<Html<&'static str>, Error> {
+    if !verify_unsubscribe_token(&query.email, &query.token) {
+        return Err(Error::InvalidUnsubscribeLink);
+    }
+
+    tokio::task::spawn_blocking(move || UNSUBSCRIBED.add(&query.email)).await??;
+
+    Ok(Html(
+        "<p>Et saa enää sähköposti-ilmoituksia laskujesi käsittelystä.</p>\
+         <p>You will no longer receive emails about the handling of your invoices.</p>",
+    ))
+}
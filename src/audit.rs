@@ -0,0 +1,443 @@
+use crate::error::Error;
+use crate::util::{read_lines, sha256_hex};
+
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex, PoisonError},
+};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use utoipa::ToSchema;
+
+/// The audit log of the service, stored in the file given by `AUDIT_LOG`
+pub static AUDIT_LOG: LazyLock<AuditLog> =
+    LazyLock::new(|| AuditLog::new(crate::CONFIG.audit_log.clone()));
+
+/// The previous hash of the first entry of the chain
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// What happened
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEvent {
+    /// An invoice was submitted and queued to be rendered and sent
+    Submission,
+    /// The PDF of a submitted invoice was generated
+    Rendered,
+    /// Generating the PDF of a submitted invoice failed
+    RenderFailed,
+    /// The invoice was sent by email to the treasurer
+    EmailSent,
+    /// Sending the invoice by email failed
+    EmailFailed,
+    /// A request was rejected by the rate limiter
+    RateLimited,
+    /// An administrator used an admin endpoint
+    AdminAction,
+    /// An action on an admin endpoint failed, with the error in the details
+    AdminActionFailed,
+}
+
+/// The details of an event to be recorded in the audit log
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct AuditRecord {
+    pub event: AuditEvent,
+    /// Who caused the event, e.g. the submitter of an invoice
+    pub actor: Option<String>,
+    /// The IP address of the client
+    #[schema(value_type = Option<String>)]
+    pub ip: Option<IpAddr>,
+    /// The ID of the invoice the event concerns
+    pub invoice_id: Option<String>,
+    /// The hexadecimal SHA-256 digest of the generated PDF
+    pub pdf_sha256: Option<String>,
+    /// Free-form details, e.g. an error message
+    pub details: Option<String>,
+}
+
+impl AuditRecord {
+    pub fn new(event: AuditEvent) -> Self {
+        Self {
+            event,
+            actor: None,
+            ip: None,
+            invoice_id: None,
+            pdf_sha256: None,
+            details: None,
+        }
+    }
+
+    pub fn actor(mut self, actor: impl Into<String>) -> Self {
+        self.actor = Some(actor.into());
+        self
+    }
+
+    pub fn ip(mut self, ip: Option<IpAddr>) -> Self {
+        self.ip = ip;
+        self
+    }
+
+    pub fn invoice_id(mut self, invoice_id: impl Into<String>) -> Self {
+        self.invoice_id = Some(invoice_id.into());
+        self
+    }
+
+    pub fn pdf(mut self, pdf: &[u8]) -> Self {
+        self.pdf_sha256 = Some(sha256_hex(pdf));
+        self
+    }
+
+    pub fn details(mut self, details: impl Into<String>) -> Self {
+        self.details = Some(details.into());
+        self
+    }
+}
+
+/// An entry of the audit log, chained to the previous entry by its hash
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct AuditEntry {
+    /// The position of the entry in the log, starting from 0
+    pub sequence: u64,
+    /// The time of the event in RFC 3339 format
+    pub timestamp: String,
+    #[serde(flatten)]
+    pub record: AuditRecord,
+    /// The hash of the previous entry
+    pub previous_hash: String,
+    /// The hexadecimal SHA-256 digest of the JSON of this entry with an empty hash
+    pub hash: String,
+}
+
+impl AuditEntry {
+    fn new(sequence: u64, previous_hash: String, record: AuditRecord) -> Self {
+        let timestamp = OffsetDateTime::now_utc()
+            .format(&Rfc3339)
+            .unwrap_or_default();
+
+        let mut entry = Self {
+            sequence,
+            timestamp,
+            record,
+            previous_hash,
+            hash: String::new(),
+        };
+        entry.hash = entry.compute_hash();
+        entry
+    }
+
+    fn compute_hash(&self) -> String {
+        let unhashed = Self {
+            hash: String::new(),
+            ..self.clone()
+        };
+        sha256_hex(&serde_json::to_vec(&unhashed).expect("Audit entries are serializable"))
+    }
+}
+
+/// An append-only log of events, hash-chained so that modifying, removing or reordering
+/// entries is detected by [`verify`]
+pub struct AuditLog {
+    path: PathBuf,
+    /// The entries written so far, read from the file on first use and kept in memory so that
+    /// neither appending nor querying reads the whole file again
+    entries: Mutex<Option<Vec<AuditEntry>>>,
+}
+
+impl AuditLog {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            entries: Mutex::new(None),
+        }
+    }
+
+    fn read_entries(&self) -> Result<Vec<AuditEntry>, Error> {
+        read_lines(&self.path)?
+            .iter()
+            .map(|line| {
+                serde_json::from_str(line)
+                    .map_err(|e| Error::AuditLog(format!("failed to parse entry: {e}")))
+            })
+            .collect()
+    }
+
+    /// Runs the function on the entries of the log, reading them on first use
+    fn with_entries<T>(&self, f: impl FnOnce(&mut Vec<AuditEntry>) -> T) -> Result<T, Error> {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        if entries.is_none() {
+            *entries = Some(self.read_entries()?);
+        }
+
+        Ok(f(entries.get_or_insert_default()))
+    }
+
+    /// Appends the record to the log and returns the new entry
+    pub fn append(&self, record: AuditRecord) -> Result<AuditEntry, Error> {
+        self.with_entries(|entries| {
+            let (sequence, previous_hash) = match entries.last() {
+                Some(last) => (last.sequence + 1, last.hash.clone()),
+                None => (0, GENESIS_HASH.to_string()),
+            };
+
+            let entry = AuditEntry::new(sequence, previous_hash, record);
+            let mut line = serde_json::to_vec(&entry)
+                .map_err(|e| Error::AuditLog(format!("failed to serialize entry: {e}")))?;
+            line.push(b'\n');
+
+            // An entry that couldn't be written isn't kept, so the next one is chained to the
+            // last entry that was
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .and_then(|mut file| file.write_all(&line))
+                .map_err(|e| {
+                    Error::AuditLog(format!("failed to write {}: {e}", self.path.display()))
+                })?;
+
+            entries.push(entry.clone());
+            Ok(entry)
+        })?
+    }
+
+    /// Appends the record to the log, logging instead of returning an error so that a failure
+    /// to write the audit log doesn't fail the request being audited. Writing the file is
+    /// blocking, so on the async runtime use [`AuditLog::record`].
+    pub fn record_blocking(&self, record: AuditRecord) {
+        if let Err(e) = self.append(record) {
+            error!("Failed to record audit event: {e}");
+        }
+    }
+
+    /// Appends the record to the log on the blocking thread pool, see
+    /// [`AuditLog::record_blocking`]
+    pub async fn record(&'static self, record: AuditRecord) {
+        if let Err(e) = tokio::task::spawn_blocking(move || self.record_blocking(record)).await {
+            error!("Failed to record audit event: {e}");
+        }
+    }
+
+    /// Returns all entries of the log without verifying the chain
+    pub fn entries(&self) -> Result<Vec<AuditEntry>, Error> {
+        self.with_entries(|entries| entries.clone())
+    }
+
+    /// Returns at most `limit` entries matching the filter, oldest first, starting after the
+    /// entry with the sequence number `after`
+    pub fn query(
+        &self,
+        after: Option<u64>,
+        filter: impl Fn(&AuditEntry) -> bool,
+        limit: usize,
+    ) -> Result<Vec<AuditEntry>, Error> {
+        self.with_entries(|entries| {
+            // The sequence numbers follow the order of the entries
+            let start = after.map_or(0, |after| {
+                entries.partition_point(|entry| entry.sequence <= after)
+            });
+            entries[start..]
+                .iter()
+                .filter(|entry| filter(entry))
+                .take(limit)
+                .cloned()
+                .collect()
+        })
+    }
+}
+
+/// Verifies the hash chain of the audit log at the path and returns the number of entries
+pub fn verify(path: &Path) -> Result<u64, String> {
+    let file = File::open(path).map_err(|e| format!("failed to open {}: {e}", path.display()))?;
+
+    let mut count = 0;
+    let mut previous_hash = GENESIS_HASH.to_string();
+
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let line_number = i + 1;
+        let entry: AuditEntry = serde_json::from_str(&line)
+            .map_err(|e| format!("line {line_number} is not a valid entry: {e}"))?;
+
+        if entry.sequence != count {
+            return Err(format!(
+                "line {line_number} has sequence number {}, expected {count}",
+                entry.sequence
+            ));
+        }
+        if entry.previous_hash != previous_hash {
+            return Err(format!(
+                "line {line_number} is not chained to the previous entry"
+            ));
+        }
+        if entry.hash != entry.compute_hash() {
+            return Err(format!(
+                "line {line_number} has been modified after it was written"
+            ));
+        }
+
+        previous_hash = entry.hash;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn test_log() -> (TempDir, AuditLog) {
+        let dir = TempDir::new().expect("Failed to create temporary directory");
+        let log = AuditLog::new(dir.path().join("audit.jsonl"));
+        (dir, log)
+    }
+
+    fn fill(log: &AuditLog) {
+        log.append(
+            AuditRecord::new(AuditEvent::Submission)
+                .actor("Test User <test@example.com>")
+                .ip("127.0.0.1".parse().ok())
+                .invoice_id("1")
+                .pdf(b"%PDF-1.7"),
+        )
+        .expect("Failed to append entry");
+        log.append(AuditRecord::new(AuditEvent::EmailSent).invoice_id("1"))
+            .expect("Failed to append entry");
+        log.append(AuditRecord::new(AuditEvent::RateLimited).ip("127.0.0.1".parse().ok()))
+            .expect("Failed to append entry");
+    }
+
+    fn rewrite_lines(log: &AuditLog, f: impl FnOnce(&mut Vec<String>)) {
+        let content = std::fs::read_to_string(&log.path).expect("Failed to read audit log");
+        let mut lines = content.lines().map(str::to_string).collect::<Vec<_>>();
+        f(&mut lines);
+        std::fs::write(&log.path, lines.join("\n") + "\n").expect("Failed to write audit log");
+    }
+
+    #[test]
+    fn test_appended_entries_are_chained() {
+        let (_dir, log) = test_log();
+        fill(&log);
+
+        let entries = log.entries().expect("Failed to read audit log");
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].previous_hash, GENESIS_HASH);
+        assert_eq!(entries[1].previous_hash, entries[0].hash);
+        assert_eq!(entries[2].previous_hash, entries[1].hash);
+        assert_eq!(
+            entries[0].record.pdf_sha256.as_deref(),
+            Some(sha256_hex(b"%PDF-1.7").as_str())
+        );
+
+        assert_eq!(verify(&log.path), Ok(3));
+    }
+
+    #[test]
+    fn test_chain_continues_after_restart() {
+        let (_dir, log) = test_log();
+        fill(&log);
+
+        let log = AuditLog::new(log.path.clone());
+        let entry = log
+            .append(AuditRecord::new(AuditEvent::AdminAction).actor("admin"))
+            .expect("Failed to append entry");
+        assert_eq!(entry.sequence, 3);
+
+        assert_eq!(verify(&log.path), Ok(4));
+    }
+
+    #[test]
+    fn test_query_starts_after_sequence() {
+        let (_dir, log) = test_log();
+        fill(&log);
+
+        let after = |after, limit| {
+            log.query(
+                after,
+                |entry| entry.record.event != AuditEvent::RateLimited,
+                limit,
+            )
+            .expect("Failed to query audit log")
+            .iter()
+            .map(|entry| entry.sequence)
+            .collect::<Vec<_>>()
+        };
+        assert_eq!(after(None, 100), [0, 1]);
+        assert_eq!(after(None, 1), [0]);
+        assert_eq!(after(Some(0), 100), [1]);
+        assert_eq!(after(Some(1), 100), Vec::<u64>::new());
+
+        // The log isn't read again for each query
+        std::fs::remove_file(&log.path).expect("Failed to remove audit log");
+        assert_eq!(after(None, 100), [0, 1]);
+    }
+
+    #[test]
+    fn test_empty_log_is_valid() {
+        let (_dir, log) = test_log();
+        std::fs::write(&log.path, "").expect("Failed to write audit log");
+
+        assert_eq!(verify(&log.path), Ok(0));
+    }
+
+    #[test]
+    fn test_modified_entry_is_detected() {
+        let (_dir, log) = test_log();
+        fill(&log);
+        rewrite_lines(&log, |lines| {
+            lines[1] = lines[1].replace("email_sent", "email_failed");
+        });
+
+        assert_eq!(
+            verify(&log.path),
+            Err("line 2 has been modified after it was written".to_string())
+        );
+    }
+
+    #[test]
+    fn test_removed_entry_is_detected() {
+        let (_dir, log) = test_log();
+        fill(&log);
+        rewrite_lines(&log, |lines| {
+            lines.remove(1);
+        });
+
+        assert!(verify(&log.path).is_err());
+    }
+
+    #[test]
+    fn test_reordered_entries_are_detected() {
+        let (_dir, log) = test_log();
+        fill(&log);
+        rewrite_lines(&log, |lines| lines.swap(0, 1));
+
+        assert!(verify(&log.path).is_err());
+    }
+
+    #[test]
+    fn test_rehashed_entry_is_detected() {
+        let (_dir, log) = test_log();
+        fill(&log);
+
+        // Recomputing the hash of a modified entry breaks the link to the next one
+        rewrite_lines(&log, |lines| {
+            let mut entry: AuditEntry = serde_json::from_str(&lines[0]).unwrap();
+            entry.record.actor = Some("Someone else".to_string());
+            entry.hash = entry.compute_hash();
+            lines[0] = serde_json::to_string(&entry).unwrap();
+        });
+
+        assert_eq!(
+            verify(&log.path),
+            Err("line 2 is not chained to the previous entry".to_string())
+        );
+    }
+}
@@ -0,0 +1,134 @@
+use iban::{Iban, IbanLike};
+
+/// The countries of the Single Euro Payments Area, whose accounts can be paid without a BIC
+const SEPA_COUNTRIES: [&str; 37] = [
+    "AD", "AT", "BE", "BG", "CH", "CY", "CZ", "DE", "DK", "EE", "ES", "FI", "FR", "GB", "GI", "GR",
+    "HR", "HU", "IE", "IS", "IT", "LI", "LT", "LU", "LV", "MC", "MT", "NL", "NO", "PL", "PT", "RO",
+    "SE", "SI", "SK", "SM", "VA",
+];
+
+/// Told in the API response of an invoice to a foreign account
+pub const FOREIGN_ACCOUNT_NOTE: &str = "barcode not available for foreign account";
+
+/// Whether the account is Finnish. Only Finnish accounts have a bank barcode.
+pub fn is_finnish(iban: &Iban) -> bool {
+    iban.country_code() == "FI"
+}
+
+/// Why an invoice to the account has no bank barcode, or `None` for Finnish accounts
+pub fn barcode_note(iban: &Iban) -> Option<&'static str> {
+    (!is_finnish(iban)).then_some(FOREIGN_ACCOUNT_NOTE)
+}
+
+/// Whether the account is in the Single Euro Payments Area
+pub fn is_sepa(iban: &Iban) -> bool {
+    SEPA_COUNTRIES.contains(&iban.country_code())
+}
+
+/// The BIC of a Finnish account, given by the bank code in the first digits of the account
+/// number as listed by Finance Finland, or `None` for foreign accounts and unknown codes
+pub fn finnish_bic(iban: &Iban) -> Option<&'static str> {
+    if !is_finnish(iban) {
+        return None;
+    }
+    let code: u16 = iban.electronic_str().get(4..7)?.parse().ok()?;
+
+    Some(match code {
+        100..=299 => "NDEAFIHH",
+        310..=319 => "HANDFIHH",
+        330..=339 => "ESSEFIHX",
+        340..=349 => "DABAFIHX",
+        360..=369 | 390..=399 => "SBANFIHH",
+        370..=379 => "DNBAFIHX",
+        380..=389 => "SWEDFIHH",
+        405 | 497 => "HELSFIHH",
+        470..=479 => "POPFFI22",
+        400
+        | 402
+        | 403
+        | 406..=408
+        | 410..=412
+        | 414..=421
+        | 423..=432
+        | 435..=452
+        | 454..=464
+        | 483..=493
+        | 495
+        | 496
+        | 715 => "ITELFIHH",
+        500..=599 => "OKOYFIHH",
+        600..=699 => "AABAFI22",
+        713 => "CITIFIHX",
+        717 => "BIGKFIH1",
+        799 => "HOLVFIHH",
+        800..=899 => "DABAFIHH",
+        _ => return None,
+    })
+}
+
+/// Whether the value is formatted as a BIC: a bank code, a country code, a location code and an
+/// optional branch code, e.g. `NDEAFIHH`
+pub fn is_bic(value: &str) -> bool {
+    let bytes = value.as_bytes();
+    matches!(bytes.len(), 8 | 11)
+        && bytes[..6].iter().all(u8::is_ascii_uppercase)
+        && bytes[6..]
+            .iter()
+            .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn iban(value: &str) -> Iban {
+        value.parse().expect("Invalid test IBAN")
+    }
+
+    #[test]
+    fn test_finnish_bic() {
+        assert_eq!(
+            finnish_bic(&iban("FI21 1234 5600 0007 85")),
+            Some("NDEAFIHH")
+        );
+        assert_eq!(
+            finnish_bic(&iban("FI14 1009 3000 1234 58")),
+            Some("NDEAFIHH")
+        );
+        assert_eq!(
+            finnish_bic(&iban("FI27 5000 0120 2514 17")),
+            Some("OKOYFIHH")
+        );
+        assert_eq!(
+            finnish_bic(&iban("FI79 7990 0000 0123 45")),
+            Some("HOLVFIHH")
+        );
+        assert_eq!(finnish_bic(&iban("DE89 3704 0044 0532 0130 00")), None);
+    }
+
+    #[test]
+    fn test_barcode_note() {
+        assert_eq!(barcode_note(&iban("FI21 1234 5600 0007 85")), None);
+        assert_eq!(
+            barcode_note(&iban("DE89 3704 0044 0532 0130 00")),
+            Some(FOREIGN_ACCOUNT_NOTE)
+        );
+    }
+
+    #[test]
+    fn test_sepa_countries() {
+        assert!(is_sepa(&iban("FI21 1234 5600 0007 85")));
+        assert!(is_sepa(&iban("DE89 3704 0044 0532 0130 00")));
+        assert!(!is_finnish(&iban("DE89 3704 0044 0532 0130 00")));
+        assert!(!is_sepa(&iban("TR33 0006 1005 1978 6457 8413 26")));
+    }
+
+    #[test]
+    fn test_bic_format() {
+        assert!(is_bic("NDEAFIHH"));
+        assert!(is_bic("DEUTDEFF500"));
+        assert!(!is_bic("ndeafihh"));
+        assert!(!is_bic("NDEA1IHH"));
+        assert!(!is_bic("NDEAFI"));
+    }
+}
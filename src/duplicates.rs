@@ -0,0 +1,411 @@
+use crate::api::invoices::{Invoice, InvoiceAttachment};
+use crate::error::Error;
+use crate::util::{read_lines, sha256_hex};
+
+use image::{DynamicImage, ImageDecoder, ImageReader};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    fs::OpenOptions,
+    io::{Cursor, Write},
+    path::PathBuf,
+    sync::{LazyLock, Mutex, PoisonError},
+};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use utoipa::ToSchema;
+
+/// The receipts of earlier submissions, stored in the file given by `RECEIPT_INDEX`
+pub static RECEIPTS: LazyLock<ReceiptIndex> =
+    LazyLock::new(|| ReceiptIndex::new(crate::CONFIG.receipt_index.clone()));
+
+/// The maximum number of differing bits for two perceptual hashes to be considered the same
+/// image, which tolerates re-encoding, scaling and small edits
+const MAX_HASH_DISTANCE: u32 = 6;
+
+/// Why an invoice may be a duplicate of an earlier one
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateReason {
+    /// An attachment is byte for byte the same as an earlier attachment
+    IdenticalAttachment,
+    /// An image attachment looks like an earlier one, e.g. the same photo re-encoded
+    SimilarImage,
+    /// The earlier invoice has the same bank account, total and subject
+    SameDetails,
+}
+
+/// An earlier invoice the submitted invoice may be a duplicate of. Possible duplicates are
+/// flagged for the treasurer, not rejected.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct PossibleDuplicate {
+    pub reason: DuplicateReason,
+    /// The attachment of the submitted invoice that matched, if any
+    pub filename: Option<String>,
+    /// The ID of the earlier invoice
+    pub previous_invoice_id: String,
+    /// The attachment of the earlier invoice that matched, if any
+    pub previous_filename: Option<String>,
+    /// The time the earlier invoice was submitted in RFC 3339 format
+    pub previous_submitted_at: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct AttachmentHashes {
+    filename: String,
+    sha256: String,
+    /// The difference hash of the image, if the attachment is an image
+    perceptual: Option<u64>,
+}
+
+/// The hashes of a submission, compared against the earlier ones
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReceiptEntry {
+    invoice_id: String,
+    submitted_at: String,
+    bank_account_number: String,
+    total: i64,
+    subject: String,
+    attachments: Vec<AttachmentHashes>,
+}
+
+impl ReceiptEntry {
+    /// Hashes the attachments of an invoice. Decoding the images is slow, so this should not be
+    /// called on the async runtime.
+    pub fn new(invoice: &Invoice, attachments: &[InvoiceAttachment]) -> Self {
+        Self {
+            invoice_id: invoice.id.clone(),
+            submitted_at: OffsetDateTime::now_utc()
+                .format(&Rfc3339)
+                .unwrap_or_default(),
+            bank_account_number: invoice
+                .bank_account_number
+                .chars()
+                .filter(|c| !c.is_whitespace())
+                .collect::<String>()
+                .to_uppercase(),
+            total: invoice.rows.iter().map(|row| row.unit_price as i64).sum(),
+            subject: invoice.subject.trim().to_lowercase(),
+            attachments: attachments
+                .iter()
+                .map(|attachment| AttachmentHashes {
+                    filename: attachment.filename.clone(),
+                    sha256: sha256_hex(&attachment.bytes),
+                    perceptual: perceptual_hash(&attachment.bytes),
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns why this submission may be a duplicate of an earlier one
+    fn duplicates_of(&self, earlier: &ReceiptEntry) -> Vec<PossibleDuplicate> {
+        let duplicate =
+            |reason, filename: Option<&str>, previous_filename: Option<&str>| PossibleDuplicate {
+                reason,
+                filename: filename.map(str::to_string),
+                previous_invoice_id: earlier.invoice_id.clone(),
+                previous_filename: previous_filename.map(str::to_string),
+                previous_submitted_at: earlier.submitted_at.clone(),
+            };
+
+        let mut duplicates = vec![];
+
+        for attachment in &self.attachments {
+            for previous in &earlier.attachments {
+                let reason = if attachment.sha256 == previous.sha256 {
+                    DuplicateReason::IdenticalAttachment
+                } else if let (Some(hash), Some(previous_hash)) =
+                    (attachment.perceptual, previous.perceptual)
+                {
+                    if (hash ^ previous_hash).count_ones() > MAX_HASH_DISTANCE {
+                        continue;
+                    }
+                    DuplicateReason::SimilarImage
+                } else {
+                    continue;
+                };
+
+                duplicates.push(duplicate(
+                    reason,
+                    Some(&attachment.filename),
+                    Some(&previous.filename),
+                ));
+            }
+        }
+
+        if self.bank_account_number == earlier.bank_account_number
+            && self.total == earlier.total
+            && self.subject == earlier.subject
+        {
+            duplicates.push(duplicate(DuplicateReason::SameDetails, None, None));
+        }
+
+        duplicates
+    }
+}
+
+/// Computes a 64-bit difference hash of an image, which changes little when the image is
+/// re-encoded or scaled. Returns `None` if the bytes are not a supported image.
+fn perceptual_hash(bytes: &[u8]) -> Option<u64> {
+    let mut decoder = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .ok()?
+        .into_decoder()
+        .ok()?;
+    let orientation = decoder.orientation().ok();
+
+    let mut image = DynamicImage::from_decoder(decoder).ok()?;
+    if let Some(orientation) = orientation {
+        image.apply_orientation(orientation);
+    }
+
+    // Compare each pixel to its right neighbour on a 9x8 grayscale thumbnail
+    let thumbnail = image.thumbnail_exact(9, 8).into_luma8();
+    let mut hash = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            let brighter = thumbnail.get_pixel(x, y)[0] < thumbnail.get_pixel(x + 1, y)[0];
+            hash = (hash << 1) | u64::from(brighter);
+        }
+    }
+
+    Some(hash)
+}
+
+/// An append-only index of the hashes of the submitted invoices
+pub struct ReceiptIndex {
+    path: PathBuf,
+    /// Read from the file on first use
+    entries: Mutex<Option<Vec<ReceiptEntry>>>,
+}
+
+impl ReceiptIndex {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            entries: Mutex::new(None),
+        }
+    }
+
+    fn read_entries(&self) -> Result<Vec<ReceiptEntry>, Error> {
+        let mut entries = vec![];
+        for line in read_lines(&self.path)? {
+            match serde_json::from_str(&line) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => warn!("Skipping invalid entry in {}: {e}", self.path.display()),
+            }
+        }
+
+        Ok(entries)
+    }
+
+    fn with_entries<T>(
+        &self,
+        f: impl FnOnce(&mut Vec<ReceiptEntry>) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        if entries.is_none() {
+            *entries = Some(self.read_entries()?);
+        }
+
+        f(entries.get_or_insert_with(Vec::new))
+    }
+
+    /// Returns the earlier submissions the given one may be a duplicate of and adds it to the
+    /// index. Both happen under the same lock, so that submissions made at the same time are
+    /// checked against each other. Errors are logged, as failing to check for duplicates
+    /// shouldn't fail the submission. Reading and writing the file is blocking, so this should not
+    /// be called on the async runtime.
+    pub fn check(&self, entry: ReceiptEntry) -> Vec<PossibleDuplicate> {
+        let duplicates = self.with_entries(|entries| {
+            let duplicates = entries
+                .iter()
+                .flat_map(|earlier| entry.duplicates_of(earlier))
+                .collect();
+
+            match self.append(&entry) {
+                Ok(()) => entries.push(entry),
+                Err(e) => error!("Failed to add receipts to the index: {e}"),
+            }
+            Ok(duplicates)
+        });
+
+        duplicates.unwrap_or_else(|e| {
+            error!("Failed to check for duplicate receipts: {e}");
+            vec![]
+        })
+    }
+
+    fn append(&self, entry: &ReceiptEntry) -> Result<(), Error> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(&line)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn test_invoice(id: &str, subject: &str) -> Invoice {
+        Invoice {
+            id: id.to_string(),
+            subject: subject.to_string(),
+            ..Invoice::test_fixture()
+        }
+    }
+
+    fn read_attachment(filename: &str) -> InvoiceAttachment {
+        InvoiceAttachment {
+            filename: filename.to_string(),
+            bytes: fs::read(format!("testdata/{filename}")).expect("Failed to read attachment"),
+        }
+    }
+
+    fn test_index() -> (TempDir, ReceiptIndex) {
+        let dir = TempDir::new().expect("Failed to create temporary directory");
+        let index = ReceiptIndex::new(dir.path().join("receipts.jsonl"));
+        (dir, index)
+    }
+
+    fn reasons(duplicates: &[PossibleDuplicate]) -> Vec<DuplicateReason> {
+        duplicates
+            .iter()
+            .map(|duplicate| duplicate.reason)
+            .collect()
+    }
+
+    #[test]
+    fn test_identical_attachment_is_flagged() {
+        let (_dir, index) = test_index();
+        index.check(ReceiptEntry::new(
+            &test_invoice("1", "Sitsit"),
+            &[read_attachment("test.pdf")],
+        ));
+
+        let mut attachment = read_attachment("test.pdf");
+        attachment.filename = "kuitti.pdf".to_string();
+        let duplicates = index.check(ReceiptEntry::new(
+            &test_invoice("2", "Fuksiaiset"),
+            &[attachment],
+        ));
+
+        assert_eq!(
+            duplicates,
+            vec![PossibleDuplicate {
+                reason: DuplicateReason::IdenticalAttachment,
+                filename: Some("kuitti.pdf".to_string()),
+                previous_invoice_id: "1".to_string(),
+                previous_filename: Some("test.pdf".to_string()),
+                previous_submitted_at: duplicates[0].previous_submitted_at.clone(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_reencoded_image_is_flagged() {
+        let (_dir, index) = test_index();
+        index.check(ReceiptEntry::new(
+            &test_invoice("1", "Sitsit"),
+            &[read_attachment("test.jpg")],
+        ));
+
+        // The same photo scaled down and saved as PNG
+        let image = image::load_from_memory(&read_attachment("test.jpg").bytes)
+            .expect("Failed to decode image");
+        let mut bytes = Cursor::new(vec![]);
+        image
+            .thumbnail(image.width() / 2, image.height() / 2)
+            .write_to(&mut bytes, image::ImageFormat::Png)
+            .expect("Failed to encode image");
+        let attachment = InvoiceAttachment {
+            filename: "kuitti.png".to_string(),
+            bytes: bytes.into_inner(),
+        };
+
+        let duplicates = index.check(ReceiptEntry::new(
+            &test_invoice("2", "Fuksiaiset"),
+            &[attachment],
+        ));
+        assert_eq!(reasons(&duplicates), vec![DuplicateReason::SimilarImage]);
+    }
+
+    #[test]
+    fn test_same_details_are_flagged() {
+        let (_dir, index) = test_index();
+        index.check(ReceiptEntry::new(&test_invoice("1", "Sitsit"), &[]));
+
+        let mut invoice = test_invoice("2", " SITSIT ");
+        invoice.bank_account_number = "FI2112345600000785".to_string();
+        let duplicates = index.check(ReceiptEntry::new(&invoice, &[]));
+
+        assert_eq!(reasons(&duplicates), vec![DuplicateReason::SameDetails]);
+        assert_eq!(duplicates[0].previous_invoice_id, "1");
+    }
+
+    #[test]
+    fn test_different_submission_is_not_flagged() {
+        let (_dir, index) = test_index();
+        index.check(ReceiptEntry::new(
+            &test_invoice("1", "Sitsit"),
+            &[read_attachment("test.jpg")],
+        ));
+
+        let duplicates = index.check(ReceiptEntry::new(
+            &test_invoice("2", "Fuksiaiset"),
+            &[read_attachment("test.pdf")],
+        ));
+        assert!(duplicates.is_empty(), "{duplicates:?}");
+    }
+
+    #[test]
+    fn test_index_is_persisted() {
+        let (_dir, index) = test_index();
+        index.check(ReceiptEntry::new(
+            &test_invoice("1", "Sitsit"),
+            &[read_attachment("test.pdf")],
+        ));
+
+        let index = ReceiptIndex::new(index.path.clone());
+        let duplicates = index.check(ReceiptEntry::new(
+            &test_invoice("2", "Fuksiaiset"),
+            &[read_attachment("test.pdf")],
+        ));
+        assert_eq!(
+            reasons(&duplicates),
+            vec![DuplicateReason::IdenticalAttachment]
+        );
+    }
+
+    #[test]
+    fn test_submissions_at_the_same_time_are_flagged() {
+        let (_dir, index) = test_index();
+
+        let duplicates: Vec<Vec<PossibleDuplicate>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = ["1", "2"]
+                .map(|id| {
+                    let index = &index;
+                    scope.spawn(move || {
+                        index.check(ReceiptEntry::new(
+                            &test_invoice(id, "Sitsit"),
+                            &[read_attachment("test.pdf")],
+                        ))
+                    })
+                })
+                .into_iter()
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        // Whichever was checked later is flagged as a duplicate of the other
+        let mut flagged: Vec<usize> = duplicates.iter().map(Vec::len).collect();
+        flagged.sort();
+        assert_eq!(flagged, vec![0, 2]);
+    }
+}
@@ -18,6 +18,10 @@ pub enum Error {
     MissingFilename,
     #[error("Unsupported file format: {0}. Supported file formats are (jpg|jpeg|png|gif|svg|pdf)")]
     UnsupportedFileFormat(String),
+    #[error("Attachment {0} is not a valid PDF file")]
+    InvalidPdf(String),
+    #[error("Attachment {0} is password protected, remove the password and try again")]
+    EncryptedPdf(String),
     #[error("Error in handling json value")]
     JsonRejection(#[from] axum::extract::rejection::JsonRejection),
     #[error("Error while parsing json")]
@@ -26,10 +30,76 @@ pub enum Error {
     InternalServerError(#[from] std::io::Error),
     #[error("Typst error: {0}")]
     TypstError(String),
+    #[error("Failed to export PDF: {0}")]
+    PdfExport(String),
+    #[error("Failed to merge PDF files: {0}")]
+    PdfMerge(String),
+    #[error("Failed to sign PDF: {0}")]
+    Signing(String),
+    #[error("Signing is not configured")]
+    SigningDisabled,
+    #[error("Invalid or missing admin token")]
+    Unauthorized,
+    #[error("Failed to write audit log: {0}")]
+    AuditLog(String),
+    #[error("Idempotency-Key must be 1 to 255 visible ASCII characters")]
+    InvalidIdempotencyKey,
+    #[error("Idempotency-Key has already been used for a different request")]
+    IdempotencyKeyReused,
+    #[error("A request with this Idempotency-Key is still being processed")]
+    IdempotencyKeyInProgress,
+    #[error("Job not found")]
+    JobNotFound,
+    #[error("Only failed jobs can be resent")]
+    JobNotFailed,
+    #[error("The invoice hasn't been sent yet")]
+    JobNotCompleted,
+    #[error("The invoice hasn't been approved with a voucher")]
+    VoucherNotFound,
+    #[error("Outbound invoices are not configured")]
+    OutboundDisabled,
+    #[error("Sending email is not configured")]
+    MailDisabled,
+    #[error("Outbound invoice not found")]
+    OutboundInvoiceNotFound,
+    #[error("Invalid invoice state: {0}")]
+    InvalidState(String),
+    #[error("An invoice that is {from} can't be {to}")]
+    InvalidTransition {
+        from: &'static str,
+        to: &'static str,
+    },
+    #[error("Invalid unsubscribe link")]
+    InvalidUnsubscribeLink,
+    #[error("Mailgun is unavailable: {message}")]
+    MailUnavailable {
+        message: String,
+        /// How long Mailgun asked to wait before retrying
+        retry_after: Option<std::time::Duration>,
+    },
+    #[error("Mailgun rejected the email: {0}")]
+    MailRejected(String),
+    #[error("Failed to render email: {0}")]
+    EmailTemplate(String),
     #[error("Failed to join task")]
     JoinError(#[from] tokio::task::JoinError),
 }
 
+impl Error {
+    /// Whether the operation may succeed if retried later, e.g. because a service was
+    /// temporarily unavailable. Other errors, such as an invoice that can't be rendered or an
+    /// email rejected by Mailgun, fail the same way every time.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Error::MailUnavailable { .. }
+                | Error::ReqwestError(_)
+                | Error::InternalServerError(_)
+                | Error::JoinError(_)
+        )
+    }
+}
+
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
         #[derive(Serialize)]
@@ -40,16 +110,38 @@ impl IntoResponse for Error {
         error!(%self);
 
         let status = match self {
-            Error::InternalServerError(_) | Error::TypstError(_) | Error::JoinError(_) => {
+            Error::InternalServerError(_)
+            | Error::TypstError(_)
+            | Error::PdfExport(_)
+            | Error::Signing(_)
+            | Error::AuditLog(_)
+            | Error::JoinError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::ReqwestError(_) | Error::MailRejected(_) | Error::EmailTemplate(_) => {
                 StatusCode::INTERNAL_SERVER_ERROR
             }
-            Error::ReqwestError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::MailUnavailable { .. } => StatusCode::BAD_GATEWAY,
             Error::JsonError(_)
             | Error::MissingFilename
             | Error::MultipartError(_)
             | Error::MultipartRejection(_)
             | Error::JsonRejection(_)
-            | Error::UnsupportedFileFormat(_) => StatusCode::BAD_REQUEST,
+            | Error::UnsupportedFileFormat(_)
+            | Error::InvalidPdf(_)
+            | Error::EncryptedPdf(_)
+            | Error::InvalidIdempotencyKey => StatusCode::BAD_REQUEST,
+            Error::PdfMerge(_) | Error::IdempotencyKeyReused => StatusCode::UNPROCESSABLE_ENTITY,
+            Error::IdempotencyKeyInProgress => StatusCode::CONFLICT,
+            Error::SigningDisabled | Error::OutboundDisabled | Error::MailDisabled => {
+                StatusCode::NOT_IMPLEMENTED
+            }
+            Error::Unauthorized => StatusCode::UNAUTHORIZED,
+            Error::JobNotFound | Error::VoucherNotFound | Error::OutboundInvoiceNotFound => {
+                StatusCode::NOT_FOUND
+            }
+            Error::JobNotFailed | Error::JobNotCompleted | Error::InvalidTransition { .. } => {
+                StatusCode::CONFLICT
+            }
+            Error::InvalidState(_) | Error::InvalidUnsubscribeLink => StatusCode::BAD_REQUEST,
         };
 
         (
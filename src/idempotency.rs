@@ -0,0 +1,248 @@
+use crate::api::invoices::Invoice;
+use crate::error::Error;
+use crate::state::State;
+
+use axum::{
+    extract::{FromRef, FromRequestParts},
+    http::request::Parts,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// The header the client sets to make retries of a request safe
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// The maximum length of an idempotency key
+const MAX_KEY_LENGTH: usize = 255;
+
+/// The value of the `Idempotency-Key` header, if set
+pub struct IdempotencyKey(pub Option<String>);
+
+impl<S> FromRequestParts<S> for IdempotencyKey
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(value) = parts.headers.get(IDEMPOTENCY_KEY_HEADER) else {
+            return Ok(Self(None));
+        };
+
+        match value.to_str() {
+            Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LENGTH => {
+                Ok(Self(Some(key.to_string())))
+            }
+            _ => Err(Error::InvalidIdempotencyKey),
+        }
+    }
+}
+
+enum Status {
+    InProgress,
+    Completed(Box<Invoice>),
+}
+
+struct Entry {
+    /// Digest of the request, so that reusing a key for a different request is detected
+    fingerprint: String,
+    created: Instant,
+    status: Status,
+}
+
+/// The responses of the requests made with an idempotency key, kept for a window of time so that
+/// retries get the original response instead of being processed again
+#[derive(Clone)]
+pub struct IdempotencyStore {
+    window: Duration,
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+/// What to do with a request that has an idempotency key
+pub enum Idempotency {
+    /// The key hasn't been seen, so the request must be processed
+    New(Pending),
+    /// The request has already been processed with this response
+    Replay(Box<Invoice>),
+}
+
+impl IdempotencyStore {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            entries: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Claims the key for a request with the given fingerprint, or returns the response of the
+    /// earlier request with the same key
+    pub fn begin(&self, key: String, fingerprint: String) -> Result<Idempotency, Error> {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        entries.retain(|_, entry| entry.created.elapsed() < self.window);
+
+        match entries.get(&key) {
+            Some(entry) if entry.fingerprint != fingerprint => Err(Error::IdempotencyKeyReused),
+            Some(Entry {
+                status: Status::InProgress,
+                ..
+            }) => Err(Error::IdempotencyKeyInProgress),
+            Some(Entry {
+                status: Status::Completed(invoice),
+                ..
+            }) => Ok(Idempotency::Replay(invoice.clone())),
+            None => {
+                entries.insert(
+                    key.clone(),
+                    Entry {
+                        fingerprint,
+                        created: Instant::now(),
+                        status: Status::InProgress,
+                    },
+                );
+
+                Ok(Idempotency::New(Pending {
+                    store: self.clone(),
+                    key,
+                    completed: false,
+                }))
+            }
+        }
+    }
+}
+
+/// A request being processed with an idempotency key. If it is dropped without completing, e.g.
+/// because the request failed, the key is released so that the request can be retried.
+pub struct Pending {
+    store: IdempotencyStore,
+    key: String,
+    completed: bool,
+}
+
+impl Pending {
+    /// Stores the response for the retries of the request
+    pub fn complete(mut self, invoice: Invoice) {
+        let mut entries = self
+            .store
+            .entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        if let Some(entry) = entries.get_mut(&self.key) {
+            entry.status = Status::Completed(Box::new(invoice));
+        }
+        self.completed = true;
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        if self.completed {
+            return;
+        }
+
+        self.store
+            .entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.key);
+    }
+}
+
+impl<S> FromRequestParts<S> for IdempotencyStore
+where
+    S: Send + Sync,
+    State: FromRef<S>,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(_parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let state = State::from_ref(state);
+        Ok(state.idempotency)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_invoice(id: &str) -> Invoice {
+        Invoice {
+            id: id.to_string(),
+            ..Invoice::test_fixture()
+        }
+    }
+
+    fn begin(store: &IdempotencyStore, key: &str, fingerprint: &str) -> Result<Idempotency, Error> {
+        store.begin(key.to_string(), fingerprint.to_string())
+    }
+
+    #[test]
+    fn test_completed_request_is_replayed() {
+        let store = IdempotencyStore::new(Duration::from_secs(60));
+
+        let Ok(Idempotency::New(pending)) = begin(&store, "key", "body") else {
+            panic!("The first request should be processed");
+        };
+        pending.complete(test_invoice("1"));
+
+        match begin(&store, "key", "body") {
+            Ok(Idempotency::Replay(invoice)) => assert_eq!(invoice.id, "1"),
+            _ => panic!("The retry should get the original response"),
+        }
+    }
+
+    #[test]
+    fn test_key_with_different_body_is_rejected() {
+        let store = IdempotencyStore::new(Duration::from_secs(60));
+
+        let Ok(Idempotency::New(pending)) = begin(&store, "key", "body") else {
+            panic!("The first request should be processed");
+        };
+        pending.complete(test_invoice("1"));
+
+        assert!(matches!(
+            begin(&store, "key", "other body"),
+            Err(Error::IdempotencyKeyReused)
+        ));
+    }
+
+    #[test]
+    fn test_concurrent_retry_is_rejected() {
+        let store = IdempotencyStore::new(Duration::from_secs(60));
+
+        let _pending = begin(&store, "key", "body");
+        assert!(matches!(
+            begin(&store, "key", "body"),
+            Err(Error::IdempotencyKeyInProgress)
+        ));
+    }
+
+    #[test]
+    fn test_failed_request_releases_key() {
+        let store = IdempotencyStore::new(Duration::from_secs(60));
+
+        drop(begin(&store, "key", "body"));
+        assert!(matches!(
+            begin(&store, "key", "other body"),
+            Ok(Idempotency::New(_))
+        ));
+    }
+
+    #[test]
+    fn test_key_expires_after_window() {
+        let store = IdempotencyStore::new(Duration::ZERO);
+
+        let Ok(Idempotency::New(pending)) = begin(&store, "key", "body") else {
+            panic!("The first request should be processed");
+        };
+        pending.complete(test_invoice("1"));
+
+        assert!(matches!(
+            begin(&store, "key", "other body"),
+            Ok(Idempotency::New(_))
+        ));
+    }
+}
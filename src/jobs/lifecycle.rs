@@ -0,0 +1,203 @@
+use crate::error::Error;
+
+use chrono::Datelike;
+use serde_derive::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Where an invoice is in its handling by the treasurer
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum InvoiceState {
+    /// Waiting to be handled at a board meeting
+    #[default]
+    Submitted,
+    /// Approved at a board meeting
+    Approved {
+        /// The meeting, e.g. "Hallituksen kokous 5/2025"
+        meeting: String,
+    },
+    /// Paid to the submitter
+    Paid {
+        /// The date of the payment in `YYYY-MM-DD` format
+        date: String,
+    },
+    /// Not going to be paid
+    Rejected {
+        /// Why the invoice was rejected, shown to the submitter
+        reason: String,
+    },
+}
+
+/// The treasurer's markings of an approved invoice, stamped on its cover page to make it an
+/// accounting voucher
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Approval {
+    /// The date of the meeting in `YYYY-MM-DD` format
+    pub date: String,
+    /// The number of the meeting in its year, e.g. 5 for the meeting 5/2025
+    pub meeting: u32,
+    /// The account the invoice is booked to
+    pub account: String,
+    /// The number of the voucher ("tosite"), assigned when the invoice is approved
+    #[serde(default)]
+    pub voucher: String,
+}
+
+impl Approval {
+    pub fn validate(&self) -> Result<(), Error> {
+        let invalid = |message: &str| Err(Error::InvalidState(message.to_string()));
+
+        if self.date().is_none() {
+            invalid("the date must be in YYYY-MM-DD format")
+        } else if self.meeting == 0 {
+            invalid("the meeting number must be positive")
+        } else if self.account.trim().is_empty() {
+            invalid("the account is required")
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn date(&self) -> Option<chrono::NaiveDate> {
+        chrono::NaiveDate::parse_from_str(&self.date, "%Y-%m-%d").ok()
+    }
+
+    /// The state of the invoice once approved, e.g. "Hallituksen kokous 5/2025"
+    pub fn state(&self) -> InvoiceState {
+        let year = self.date().map(|date| date.year()).unwrap_or_default();
+        InvoiceState::Approved {
+            meeting: format!("Hallituksen kokous {}/{year}", self.meeting),
+        }
+    }
+}
+
+impl InvoiceState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            InvoiceState::Submitted => "submitted",
+            InvoiceState::Approved { .. } => "approved",
+            InvoiceState::Paid { .. } => "paid",
+            InvoiceState::Rejected { .. } => "rejected",
+        }
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        let invalid = |message: &str| Err(Error::InvalidState(message.to_string()));
+
+        match self {
+            InvoiceState::Approved { meeting } if meeting.trim().is_empty() => {
+                invalid("the meeting is required")
+            }
+            InvoiceState::Paid { date }
+                if chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").is_err() =>
+            {
+                invalid("the date must be in YYYY-MM-DD format")
+            }
+            InvoiceState::Rejected { reason } if reason.trim().is_empty() => {
+                invalid("the reason is required")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks that the invoice can move from this state to the next one. Invoices are approved
+    /// or rejected, and approved invoices are paid or rejected.
+    pub fn transition(&self, next: InvoiceState) -> Result<InvoiceState, Error> {
+        next.validate()?;
+
+        let allowed = matches!(
+            (self, &next),
+            (
+                InvoiceState::Submitted,
+                InvoiceState::Approved { .. } | InvoiceState::Rejected { .. }
+            ) | (
+                InvoiceState::Approved { .. },
+                InvoiceState::Paid { .. } | InvoiceState::Rejected { .. }
+            )
+        );
+
+        if allowed {
+            Ok(next)
+        } else {
+            Err(Error::InvalidTransition {
+                from: self.name(),
+                to: next.name(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn approved() -> InvoiceState {
+        InvoiceState::Approved {
+            meeting: "Hallituksen kokous 5/2025".to_string(),
+        }
+    }
+
+    fn paid(date: &str) -> InvoiceState {
+        InvoiceState::Paid {
+            date: date.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_invoice_is_approved_and_paid() {
+        let state = InvoiceState::Submitted.transition(approved()).unwrap();
+        let state = state.transition(paid("2025-05-20")).unwrap();
+        assert_eq!(state, paid("2025-05-20"));
+    }
+
+    #[test]
+    fn test_invoice_is_not_paid_before_approval() {
+        assert!(matches!(
+            InvoiceState::Submitted.transition(paid("2025-05-20")),
+            Err(Error::InvalidTransition {
+                from: "submitted",
+                to: "paid"
+            })
+        ));
+    }
+
+    #[test]
+    fn test_paid_invoice_is_final() {
+        let rejected = InvoiceState::Rejected {
+            reason: "Duplicate".to_string(),
+        };
+        assert!(paid("2025-05-20").transition(rejected).is_err());
+    }
+
+    #[test]
+    fn test_approval_names_meeting() {
+        let approval = Approval {
+            date: "2025-05-20".to_string(),
+            meeting: 5,
+            account: "4000".to_string(),
+            voucher: "12".to_string(),
+        };
+        assert!(approval.validate().is_ok());
+        assert_eq!(approval.state(), approved());
+
+        let approval = Approval {
+            account: " ".to_string(),
+            ..approval
+        };
+        assert!(matches!(approval.validate(), Err(Error::InvalidState(_))));
+    }
+
+    #[test]
+    fn test_invalid_state_is_rejected() {
+        assert!(matches!(
+            approved().transition(paid("20.5.2025")),
+            Err(Error::InvalidState(_))
+        ));
+        assert!(matches!(
+            InvoiceState::Submitted.transition(InvoiceState::Rejected {
+                reason: " ".to_string()
+            }),
+            Err(Error::InvalidState(_))
+        ));
+    }
+}
@@ -0,0 +1,269 @@
+use super::{format_timestamp, now, InvoiceState, JobQueue, JobStatus};
+use crate::error::Error;
+
+use serde_derive::{Deserialize, Serialize};
+use std::{fs, io::ErrorKind, net::IpAddr, path::PathBuf, sync::PoisonError};
+use utoipa::ToSchema;
+
+/// What an email in the queue is about. The invoice is loaded when the email is sent.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Mail {
+    /// Confirms to the submitter that the invoice was received
+    Confirmation { invoice_id: String },
+    /// Tells the submitter that the invoice moved to a new state
+    Notification {
+        invoice_id: String,
+        state: InvoiceState,
+    },
+    /// Sends an outbound invoice to the customer
+    Outbound { number: String },
+}
+
+impl Mail {
+    /// The submitted invoice the email is about, if any
+    pub fn invoice_id(&self) -> Option<&str> {
+        match self {
+            Mail::Confirmation { invoice_id } | Mail::Notification { invoice_id, .. } => {
+                Some(invoice_id)
+            }
+            Mail::Outbound { .. } => None,
+        }
+    }
+}
+
+/// An email waiting to be sent, stored as `<id>.json` in the `mail` directory of the queue. Sent
+/// emails are removed, while the ones that couldn't be sent are kept in the outbox.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MailJob {
+    pub id: String,
+    pub status: JobStatus,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    /// Unix timestamps
+    created_at: i64,
+    updated_at: i64,
+    pub(super) next_attempt_at: i64,
+    pub mail: Mail,
+    /// The address of the submitter or the admin whose request queued the email
+    pub(super) ip: Option<IpAddr>,
+}
+
+/// The status of an email as returned by the API
+#[derive(Debug, Serialize, ToSchema)]
+pub struct MailView {
+    pub id: String,
+    pub status: JobStatus,
+    /// The number of attempts made so far
+    pub attempts: u32,
+    /// The error of the last failed attempt
+    pub last_error: Option<String>,
+    /// The time the email was queued in RFC 3339 format
+    pub created_at: String,
+    /// The time the email was last updated in RFC 3339 format
+    pub updated_at: String,
+    /// The time of the next attempt in RFC 3339 format, if the email is waiting for one
+    pub next_attempt_at: Option<String>,
+    pub mail: Mail,
+}
+
+impl From<MailJob> for MailView {
+    fn from(job: MailJob) -> Self {
+        let waiting = matches!(job.status, JobStatus::Queued | JobStatus::Retrying);
+
+        Self {
+            id: job.id,
+            status: job.status,
+            attempts: job.attempts,
+            last_error: job.last_error,
+            created_at: format_timestamp(job.created_at),
+            updated_at: format_timestamp(job.updated_at),
+            next_attempt_at: waiting.then(|| format_timestamp(job.next_attempt_at)),
+            mail: job.mail,
+        }
+    }
+}
+
+impl JobQueue {
+    fn mail_dir(&self) -> PathBuf {
+        self.dir.join("mail")
+    }
+
+    fn mail_path(&self, id: &str) -> PathBuf {
+        self.mail_dir().join(format!("{id}.json"))
+    }
+
+    /// Queues the email to be sent by the worker. Writing the file is blocking, so this should
+    /// not be called on the async runtime.
+    pub fn enqueue_mail(&self, mail: Mail, ip: Option<IpAddr>) -> Result<MailJob, Error> {
+        fs::create_dir_all(self.mail_dir())?;
+
+        let now = now();
+        let job = MailJob {
+            id: uuid::Uuid::new_v4().to_string(),
+            status: JobStatus::Queued,
+            attempts: 0,
+            last_error: None,
+            created_at: now,
+            updated_at: now,
+            next_attempt_at: now,
+            mail,
+            ip,
+        };
+        self.save_mail(&job)?;
+        self.notify.notify_one();
+
+        Ok(job)
+    }
+
+    /// Stores the email, or removes it once it has been sent
+    pub fn save_mail(&self, job: &MailJob) -> Result<(), Error> {
+        if job.status == JobStatus::Completed {
+            return match fs::remove_file(self.mail_path(&job.id)) {
+                Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            };
+        }
+
+        let mut job = job.clone();
+        job.updated_at = now();
+        Self::write_atomic(&self.mail_path(&job.id), &serde_json::to_vec(&job)?)
+    }
+
+    /// Returns the email with the ID, or `None` if it has been sent or there is no such email
+    pub fn get_mail(&self, id: &str) -> Result<Option<MailJob>, Error> {
+        // The ID is used as a path, so only accept the IDs the service generates
+        if uuid::Uuid::parse_str(id).is_err() {
+            return Ok(None);
+        }
+
+        match fs::read(self.mail_path(id)) {
+            Ok(contents) => Ok(Some(serde_json::from_slice(&contents)?)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Returns the emails that haven't been sent, skipping the ones that can't be read. Only
+    /// these are kept, so reading them all is cheap.
+    fn mails(&self) -> Result<Vec<MailJob>, Error> {
+        let entries = match fs::read_dir(self.mail_dir()) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut mails = vec![];
+        for entry in entries {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            let Some(id) = name.strip_suffix(".json") else {
+                continue;
+            };
+            match self.get_mail(id) {
+                Ok(Some(mail)) => mails.push(mail),
+                Ok(None) => {}
+                Err(e) => warn!("Skipping invalid email {id}: {e}"),
+            }
+        }
+
+        mails.sort_by_key(|mail| mail.created_at);
+        Ok(mails)
+    }
+
+    /// Returns the IDs of the emails due for an attempt, oldest first
+    pub(super) fn due_mail(&self) -> Result<Vec<String>, Error> {
+        let now = now();
+        Ok(self
+            .mails()?
+            .into_iter()
+            .filter(|mail| mail.status.is_waiting() && mail.next_attempt_at <= now)
+            .map(|mail| mail.id)
+            .collect())
+    }
+
+    /// Returns the emails that couldn't be sent, oldest first
+    pub fn mail_outbox(&self) -> Result<Vec<MailJob>, Error> {
+        Ok(self
+            .mails()?
+            .into_iter()
+            .filter(|mail| mail.status == JobStatus::Failed)
+            .collect())
+    }
+
+    /// Queues a failed email to be attempted again
+    pub fn resend_mail(&self, id: &str) -> Result<MailJob, Error> {
+        let _updates = self.updates.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut job = self.get_mail(id)?.ok_or(Error::JobNotFound)?;
+        if job.status != JobStatus::Failed {
+            return Err(Error::JobNotFailed);
+        }
+
+        job.status = JobStatus::Queued;
+        job.attempts = 0;
+        job.next_attempt_at = now();
+        self.save_mail(&job)?;
+        self.notify.notify_one();
+
+        Ok(job)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notification() -> Mail {
+        Mail::Notification {
+            invoice_id: uuid::Uuid::new_v4().to_string(),
+            state: InvoiceState::Rejected {
+                reason: "No receipt".to_string(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_mail_is_removed_once_sent() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let queue = JobQueue::new(dir.path().to_path_buf());
+
+        let mut job = queue.enqueue_mail(notification(), None).unwrap();
+        assert_eq!(queue.due_mail().unwrap(), vec![job.id.clone()]);
+
+        // The queue of the invoices doesn't see the emails
+        assert!(queue.due().unwrap().is_empty());
+
+        job.status = JobStatus::Completed;
+        queue.save_mail(&job).unwrap();
+        assert!(queue.get_mail(&job.id).unwrap().is_none());
+        assert!(queue.due_mail().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_failed_mail_can_be_resent() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let queue = JobQueue::new(dir.path().to_path_buf());
+
+        let mut job = queue.enqueue_mail(notification(), None).unwrap();
+        assert!(matches!(
+            queue.resend_mail(&job.id),
+            Err(Error::JobNotFailed)
+        ));
+
+        job.status = JobStatus::Failed;
+        job.attempts = 3;
+        job.last_error = Some("Mailgun rejected the email".to_string());
+        queue.save_mail(&job).unwrap();
+
+        let queue = JobQueue::new(dir.path().to_path_buf());
+        let outbox = queue.mail_outbox().unwrap();
+        assert_eq!(outbox.len(), 1);
+        assert_eq!(outbox[0].mail, job.mail);
+        assert!(queue.due_mail().unwrap().is_empty());
+
+        let resent = queue.resend_mail(&job.id).unwrap();
+        assert_eq!(resent.status, JobStatus::Queued);
+        assert_eq!(resent.attempts, 0);
+        assert!(queue.mail_outbox().unwrap().is_empty());
+        assert_eq!(queue.due_mail().unwrap(), vec![job.id]);
+    }
+}
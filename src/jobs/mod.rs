@@ -0,0 +1,1117 @@
+use crate::api::invoices::{Invoice, InvoiceAttachment};
+use crate::bank;
+use crate::duplicates::PossibleDuplicate;
+use crate::error::Error;
+use crate::local_time;
+use crate::pdfgen::DocumentBuilder;
+use crate::signing::Signer;
+use crate::state::State;
+use crate::CONFIG;
+
+use axum::{
+    extract::{FromRef, FromRequestParts},
+    http::request::Parts,
+};
+use chrono::Datelike;
+use iban::Iban;
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fs,
+    io::ErrorKind,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, LazyLock, Mutex, PoisonError,
+    },
+    time::Duration,
+};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tokio::sync::Notify;
+use utoipa::ToSchema;
+
+pub mod lifecycle;
+pub mod mail;
+pub mod vouchers;
+pub mod worker;
+
+pub use lifecycle::{Approval, InvoiceState};
+pub use mail::{Mail, MailJob, MailView};
+pub use vouchers::VoucherNumbering;
+
+/// The longest time to wait between two attempts of a job
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// The jobs being processed in this process, so that they aren't picked up twice
+static CLAIMED: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(|| Mutex::new(HashSet::new()));
+
+/// Held while handing out a voucher number, so that no two approvals in this process get the
+/// same number even if they go through different queues
+static NUMBERING: LazyLock<Mutex<()>> = LazyLock::new(|| Mutex::new(()));
+
+/// The progress of a job
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    /// Waiting for a worker
+    Queued,
+    /// The invoice is being rendered or sent, or the email is being sent
+    Processing,
+    /// The last attempt failed and the job will be retried
+    Retrying,
+    /// The invoice or the email has been sent
+    Completed,
+    /// The invoice or the email couldn't be sent and the job was moved to the outbox
+    Failed,
+}
+
+impl JobStatus {
+    /// Whether the job is waiting for an attempt. Jobs left processing by a previous run of the
+    /// service are picked up again.
+    pub fn is_waiting(&self) -> bool {
+        matches!(
+            self,
+            JobStatus::Queued | JobStatus::Processing | JobStatus::Retrying
+        )
+    }
+}
+
+/// A submitted invoice waiting to be rendered and sent, stored as `job.json` in the directory of
+/// the job alongside the attachments and, once rendered, the PDF. The files are kept until the
+/// invoice has been handled, and the stamped voucher of an approved invoice is kept for good.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Job {
+    pub id: String,
+    pub status: JobStatus,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    /// Unix timestamps
+    created_at: i64,
+    updated_at: i64,
+    next_attempt_at: i64,
+    /// The invoice fields assigned by the service aren't deserialized, so they are stored
+    /// separately and restored when the job is loaded
+    invoice: Invoice,
+    attachment_filenames: Vec<String>,
+    possible_duplicates: Vec<PossibleDuplicate>,
+    /// Empty for the jobs created before the invoices were dated, which are dated by
+    /// `created_at` instead
+    #[serde(default)]
+    issued_at: String,
+    /// Whether the invoice has been sent to the treasurer, so that a retry only queues the
+    /// confirmation to the submitter
+    #[serde(default)]
+    treasurer_sent: bool,
+    /// The handling of the invoice by the treasurer once it has been sent
+    #[serde(default)]
+    state: InvoiceState,
+    /// The treasurer's markings stamped on the voucher, if the invoice was approved with them
+    #[serde(default)]
+    approval: Option<Approval>,
+    ip: Option<IpAddr>,
+}
+
+impl Job {
+    pub fn invoice(&self) -> &Invoice {
+        &self.invoice
+    }
+
+    pub fn state(&self) -> &InvoiceState {
+        &self.state
+    }
+
+    pub fn approval(&self) -> Option<&Approval> {
+        self.approval.as_ref()
+    }
+}
+
+/// The status of a job as returned by the API
+#[derive(Debug, Serialize, ToSchema)]
+pub struct JobView {
+    /// The ID of the job, which is also the ID of the invoice
+    pub id: String,
+    pub status: JobStatus,
+    /// The number of attempts made so far
+    pub attempts: u32,
+    /// The error of the last failed attempt
+    pub last_error: Option<String>,
+    /// The time the invoice was submitted in RFC 3339 format
+    pub created_at: String,
+    /// The time the job was last updated in RFC 3339 format
+    pub updated_at: String,
+    /// The time of the next attempt in RFC 3339 format, if the job is waiting for one
+    pub next_attempt_at: Option<String>,
+    /// The handling of the invoice by the treasurer
+    pub state: InvoiceState,
+    /// The markings stamped on the voucher of an approved invoice
+    pub approval: Option<Approval>,
+    /// The submitted invoice, including the possible duplicates once it has been rendered
+    pub invoice: Invoice,
+}
+
+fn format_timestamp(timestamp: i64) -> String {
+    OffsetDateTime::from_unix_timestamp(timestamp)
+        .ok()
+        .and_then(|time| time.format(&Rfc3339).ok())
+        .unwrap_or_default()
+}
+
+impl From<Job> for JobView {
+    fn from(job: Job) -> Self {
+        let waiting = matches!(job.status, JobStatus::Queued | JobStatus::Retrying);
+
+        Self {
+            id: job.id,
+            status: job.status,
+            attempts: job.attempts,
+            last_error: job.last_error,
+            created_at: format_timestamp(job.created_at),
+            updated_at: format_timestamp(job.updated_at),
+            next_attempt_at: waiting.then(|| format_timestamp(job.next_attempt_at)),
+            state: job.state,
+            approval: job.approval,
+            invoice: job.invoice,
+        }
+    }
+}
+
+fn now() -> i64 {
+    OffsetDateTime::now_utc().unix_timestamp()
+}
+
+/// The delay before the next attempt after the given number of failed attempts, doubling with
+/// each attempt
+pub fn retry_delay(base: Duration, attempts: u32) -> Duration {
+    base.saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+        .min(MAX_RETRY_DELAY)
+}
+
+/// A job claimed by a worker, released when dropped
+pub struct Claim(String);
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        CLAIMED
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.0);
+    }
+}
+
+/// A persistent queue of jobs stored in the directory given by `JOB_DIR`, so that the submitted
+/// invoices survive restarts
+#[derive(Clone)]
+pub struct JobQueue {
+    dir: PathBuf,
+    /// Wakes up the worker when a job is added
+    notify: Arc<Notify>,
+    /// Held while updating a job on behalf of an admin, so that concurrent updates aren't lost
+    updates: Arc<Mutex<()>>,
+    numbering: Arc<VoucherNumbering>,
+    /// Whether the index of the waiting jobs has been rebuilt
+    indexed: Arc<AtomicBool>,
+}
+
+impl JobQueue {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            notify: Arc::new(Notify::new()),
+            updates: Arc::new(Mutex::new(())),
+            numbering: Arc::new(VoucherNumbering::default()),
+            indexed: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Sets how the vouchers of the approved invoices are numbered
+    pub fn numbering(mut self, numbering: VoucherNumbering) -> Self {
+        self.numbering = Arc::new(numbering);
+        self
+    }
+
+    fn job_dir(&self, id: &str) -> PathBuf {
+        self.dir.join(id)
+    }
+
+    fn attachment_path(&self, id: &str, index: usize) -> PathBuf {
+        self.job_dir(id).join(format!("attachment-{index}"))
+    }
+
+    fn pdf_path(&self, id: &str) -> PathBuf {
+        self.job_dir(id).join("invoice.pdf")
+    }
+
+    fn voucher_path(&self, id: &str) -> PathBuf {
+        self.job_dir(id).join("voucher.pdf")
+    }
+
+    fn counter_path(&self) -> PathBuf {
+        self.dir.join("vouchers.json")
+    }
+
+    /// The index of the jobs waiting for an attempt, with a file per job containing the time of
+    /// its next attempt, so that polling the queue doesn't read the jobs that have finished
+    fn pending_dir(&self) -> PathBuf {
+        self.dir.join("pending")
+    }
+
+    /// Writes the file by renaming a temporary file over it, so that a crash never leaves a
+    /// partially written file behind
+    pub(crate) fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), Error> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Stores the invoice and its attachments as a new job. The job is identified by the ID of
+    /// the invoice. Writing the files is blocking, so this should not be called on the async
+    /// runtime.
+    pub fn enqueue(
+        &self,
+        invoice: Invoice,
+        attachments: Vec<InvoiceAttachment>,
+        ip: Option<IpAddr>,
+    ) -> Result<Job, Error> {
+        let dir = self.job_dir(&invoice.id);
+        fs::create_dir_all(&dir)?;
+
+        for (i, attachment) in attachments.iter().enumerate() {
+            Self::write_atomic(&self.attachment_path(&invoice.id, i), &attachment.bytes)?;
+        }
+
+        let now = now();
+        let job = Job {
+            id: invoice.id.clone(),
+            status: JobStatus::Queued,
+            attempts: 0,
+            last_error: None,
+            created_at: now,
+            updated_at: now,
+            next_attempt_at: now,
+            attachment_filenames: attachments.into_iter().map(|a| a.filename).collect(),
+            possible_duplicates: invoice.possible_duplicates.clone(),
+            issued_at: invoice.issued_at.clone(),
+            treasurer_sent: false,
+            state: InvoiceState::Submitted,
+            approval: None,
+            ip,
+            invoice,
+        };
+        self.save(&job)?;
+        self.notify.notify_one();
+
+        Ok(job)
+    }
+
+    /// Stores the job and updates the index of the waiting jobs. A waiting job is indexed before
+    /// it is saved and a finished one removed from the index after, so that a crash in between
+    /// never loses a job.
+    pub fn save(&self, job: &Job) -> Result<(), Error> {
+        let mut job = job.clone();
+        job.updated_at = now();
+
+        if job.status.is_waiting() {
+            self.index(&job)?;
+        }
+        Self::write_atomic(
+            &self.job_dir(&job.id).join("job.json"),
+            &serde_json::to_vec(&job)?,
+        )?;
+        if !job.status.is_waiting() {
+            self.index(&job)?;
+        }
+
+        Ok(())
+    }
+
+    /// Adds a waiting job to the index with the time of its next attempt, or removes a finished
+    /// one from it
+    fn index(&self, job: &Job) -> Result<(), Error> {
+        let path = self.pending_dir().join(&job.id);
+        if job.status.is_waiting() {
+            fs::create_dir_all(self.pending_dir())?;
+            return Self::write_atomic(&path, job.next_attempt_at.to_string().as_bytes());
+        }
+
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the job with the ID, or `None` if there is no such job
+    pub fn get(&self, id: &str) -> Result<Option<Job>, Error> {
+        // The ID is used as a path, so only accept the IDs the service generates
+        if uuid::Uuid::parse_str(id).is_err() {
+            return Ok(None);
+        }
+
+        let contents = match fs::read(self.job_dir(id).join("job.json")) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut job: Job = serde_json::from_slice(&contents)?;
+        job.invoice.id = job.id.clone();
+        job.invoice.attachments = job
+            .attachment_filenames
+            .iter()
+            .map(|filename| InvoiceAttachment {
+                filename: filename.clone(),
+                bytes: vec![],
+            })
+            .collect();
+        job.invoice.possible_duplicates = job.possible_duplicates.clone();
+        job.invoice.issued_at = if job.issued_at.is_empty() {
+            chrono::DateTime::from_timestamp(job.created_at, 0)
+                .map(|time| local_time::at(time).to_rfc3339())
+                .unwrap_or_default()
+        } else {
+            job.issued_at.clone()
+        };
+        // Not stored, as it follows from the account
+        job.invoice.barcode_note = job
+            .invoice
+            .bank_account_number
+            .parse::<Iban>()
+            .ok()
+            .and_then(|iban| bank::barcode_note(&iban))
+            .map(str::to_string);
+
+        Ok(Some(job))
+    }
+
+    /// Returns all jobs in the queue, skipping the ones that can't be read
+    fn all(&self) -> Result<Vec<Job>, Error> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut jobs = vec![];
+        for entry in entries {
+            let id = entry?.file_name().to_string_lossy().into_owned();
+            match self.get(&id) {
+                Ok(Some(job)) => jobs.push(job),
+                Ok(None) => {}
+                Err(e) => warn!("Skipping invalid job {id}: {e}"),
+            }
+        }
+
+        Ok(jobs)
+    }
+
+    /// Returns the IDs of the jobs due for an attempt, read from the index of the waiting jobs.
+    /// The index is rebuilt from the jobs on the first call, e.g. after an upgrade.
+    fn due(&self) -> Result<Vec<String>, Error> {
+        if !self.indexed.load(Ordering::Acquire) {
+            for job in self.all()?.iter().filter(|job| job.status.is_waiting()) {
+                self.index(job)?;
+            }
+            self.indexed.store(true, Ordering::Release);
+        }
+
+        let entries = match fs::read_dir(self.pending_dir()) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+
+        let now = now();
+        let mut due = vec![];
+        for entry in entries {
+            let entry = entry?;
+            let id = entry.file_name().to_string_lossy().into_owned();
+            // Skip the temporary files of interrupted writes
+            if uuid::Uuid::parse_str(&id).is_err() {
+                continue;
+            }
+
+            match fs::read_to_string(entry.path()).map(|time| time.trim().parse::<i64>()) {
+                Ok(Ok(next_attempt_at)) if next_attempt_at <= now => {
+                    due.push((next_attempt_at, id))
+                }
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => warn!("Skipping invalid index entry of job {id}: {e}"),
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        due.sort();
+        Ok(due.into_iter().map(|(_, id)| id).collect())
+    }
+
+    /// Returns the dead letters, i.e. the failed jobs whose invoices were never sent, oldest
+    /// first. Their attachments and PDF are kept so that they can be resent.
+    pub fn outbox(&self) -> Result<Vec<Job>, Error> {
+        let mut failed: Vec<Job> = self
+            .all()?
+            .into_iter()
+            .filter(|job| job.status == JobStatus::Failed)
+            .collect();
+
+        failed.sort_by_key(|job| job.created_at);
+        Ok(failed)
+    }
+
+    /// Returns the sent invoices waiting to be handled at a board meeting, oldest first
+    pub fn pending(&self) -> Result<Vec<Job>, Error> {
+        let mut pending: Vec<Job> = self
+            .all()?
+            .into_iter()
+            .filter(|job| {
+                job.status == JobStatus::Completed && job.state == InvoiceState::Submitted
+            })
+            .collect();
+
+        pending.sort_by_key(|job| job.created_at);
+        Ok(pending)
+    }
+
+    /// Returns the sent invoices that haven't been paid or rejected by their due date, most
+    /// overdue first
+    pub fn overdue(&self, today: chrono::NaiveDate) -> Result<Vec<Job>, Error> {
+        let mut overdue: Vec<Job> = self
+            .all()?
+            .into_iter()
+            .filter(|job| {
+                job.status == JobStatus::Completed
+                    && matches!(
+                        job.state,
+                        InvoiceState::Submitted | InvoiceState::Approved { .. }
+                    )
+                    && job.invoice.due().is_some_and(|due| due < today)
+            })
+            .collect();
+
+        overdue.sort_by_key(|job| job.invoice.due());
+        Ok(overdue)
+    }
+
+    /// Queues a failed job to be attempted again. The PDF rendered by the earlier attempts is
+    /// reused.
+    pub fn resend(&self, id: &str) -> Result<Job, Error> {
+        let _updates = self.updates.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut job = self.get(id)?.ok_or(Error::JobNotFound)?;
+        if job.status != JobStatus::Failed {
+            return Err(Error::JobNotFailed);
+        }
+
+        job.status = JobStatus::Queued;
+        job.attempts = 0;
+        job.next_attempt_at = now();
+        self.save(&job)?;
+        self.notify.notify_one();
+
+        Ok(job)
+    }
+
+    /// Moves the invoice to the next state of its handling. Only the invoices that have been
+    /// sent can be handled, which also keeps the worker from updating the job at the same time.
+    ///
+    /// Invoices are approved only with [`JobQueue::approve`], which gives them a voucher number
+    /// and stamps the voucher. An invoice approved without them could never get a voucher.
+    pub fn set_state(&self, id: &str, next: InvoiceState) -> Result<Job, Error> {
+        if matches!(next, InvoiceState::Approved { .. }) {
+            return Err(Error::InvalidState(
+                "invoices are approved with a voucher at /admin/invoices/approve".to_string(),
+            ));
+        }
+
+        let _updates = self.updates.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut job = self.get(id)?.ok_or(Error::JobNotFound)?;
+        if job.status != JobStatus::Completed {
+            return Err(Error::JobNotCompleted);
+        }
+
+        job.state = job.state.transition(next)?;
+        self.save(&job)?;
+
+        if matches!(
+            job.state,
+            InvoiceState::Paid { .. } | InvoiceState::Rejected { .. }
+        ) {
+            self.remove_files(&job);
+        }
+
+        Ok(job)
+    }
+
+    /// Approves the invoice with the next voucher number of its fiscal year and renders it again
+    /// with the approval stamped on the cover page, returning the job and the voucher. The
+    /// rendering is heavily blocking, so the job is only locked for updates to check it again and
+    /// save it once the voucher has been rendered.
+    ///
+    /// The number is taken only once the voucher has been rendered and is given back if the job
+    /// can't be saved, so that the numbering has no gaps.
+    pub fn approve(
+        &self,
+        id: &str,
+        mut approval: Approval,
+        signer: Option<Signer>,
+    ) -> Result<(Job, Vec<u8>), Error> {
+        approval.validate()?;
+        let year = approval.date().map(|date| date.year()).unwrap_or_default();
+
+        // Don't render the invoices that can't be approved
+        let job = self.get(id)?.ok_or(Error::JobNotFound)?;
+        Self::approved_state(&job, &approval)?;
+
+        let _numbering = NUMBERING.lock().unwrap_or_else(PoisonError::into_inner);
+        let counter = vouchers::VoucherCounter::load(&self.counter_path())?;
+        let mut taken = counter.clone();
+        approval.voucher = self
+            .numbering
+            .format(year, taken.next(&self.numbering, year));
+
+        let voucher = DocumentBuilder::new(job.invoice.clone(), self.attachments(&job)?)
+            .pdf_a(CONFIG.pdf_a)
+            .signer(signer)
+            .approval(Some(approval.clone()))
+            .build_pdf()?;
+
+        let _updates = self.updates.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut job = self.get(id)?.ok_or(Error::JobNotFound)?;
+        let state = Self::approved_state(&job, &approval)?;
+        Self::write_atomic(&self.voucher_path(id), &voucher)?;
+        Self::write_atomic(&self.counter_path(), &serde_json::to_vec(&taken)?)?;
+
+        job.state = state;
+        job.approval = Some(approval);
+        if let Err(e) = self.save(&job) {
+            if let Err(e) = Self::write_atomic(&self.counter_path(), &serde_json::to_vec(&counter)?)
+            {
+                error!("Failed to give back voucher number of {id}: {e}");
+            }
+            return Err(e);
+        }
+        self.remove_files(&job);
+
+        Ok((job, voucher))
+    }
+
+    /// Returns the state the job moves to when approved, or why it can't be approved
+    fn approved_state(job: &Job, approval: &Approval) -> Result<InvoiceState, Error> {
+        if job.status != JobStatus::Completed {
+            return Err(Error::JobNotCompleted);
+        }
+        job.state.transition(approval.state())
+    }
+
+    /// Returns the stamped voucher of an approved invoice
+    pub fn voucher(&self, id: &str) -> Result<Vec<u8>, Error> {
+        if self.get(id)?.is_none() {
+            return Err(Error::JobNotFound);
+        }
+
+        match fs::read(self.voucher_path(id)) {
+            Ok(voucher) => Ok(voucher),
+            Err(e) if e.kind() == ErrorKind::NotFound => Err(Error::VoucherNotFound),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Claims the job for this process, or returns `None` if it's already being processed
+    fn claim(id: &str) -> Option<Claim> {
+        CLAIMED
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(id.to_string())
+            .then(|| Claim(id.to_string()))
+    }
+
+    fn attachments(&self, job: &Job) -> Result<Vec<InvoiceAttachment>, Error> {
+        job.attachment_filenames
+            .iter()
+            .enumerate()
+            .map(|(i, filename)| {
+                Ok(InvoiceAttachment {
+                    filename: filename.clone(),
+                    bytes: fs::read(self.attachment_path(&job.id, i))?,
+                })
+            })
+            .collect()
+    }
+
+    fn pdf(&self, id: &str) -> Result<Option<Vec<u8>>, Error> {
+        match fs::read(self.pdf_path(id)) {
+            Ok(pdf) => Ok(Some(pdf)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn save_pdf(&self, id: &str, pdf: &[u8]) -> Result<(), Error> {
+        Self::write_atomic(&self.pdf_path(id), pdf)
+    }
+
+    /// Removes the attachments and the PDF of a handled invoice, keeping its status and voucher.
+    /// The files of a sent invoice are kept until it's approved, paid or rejected, as the
+    /// approval renders the invoice again.
+    fn remove_files(&self, job: &Job) {
+        let paths = (0..job.attachment_filenames.len())
+            .map(|i| self.attachment_path(&job.id, i))
+            .chain([self.pdf_path(&job.id)]);
+
+        for path in paths {
+            if let Err(e) = fs::remove_file(&path) {
+                if e.kind() != ErrorKind::NotFound {
+                    warn!("Failed to remove {}: {e}", path.display());
+                }
+            }
+        }
+    }
+}
+
+impl<S> FromRequestParts<S> for JobQueue
+where
+    S: Send + Sync,
+    State: FromRef<S>,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(_parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let state = State::from_ref(state);
+        Ok(state.jobs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attachment(filename: &str, bytes: &[u8]) -> InvoiceAttachment {
+        InvoiceAttachment {
+            filename: filename.to_string(),
+            bytes: bytes.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_enqueued_job_is_persisted() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let queue = JobQueue::new(dir.path().to_path_buf());
+
+        let mut invoice = Invoice::test_fixture();
+        invoice.issued_at = "2025-05-20T00:15:00+03:00".to_string();
+        let job = queue
+            .enqueue(
+                invoice.clone(),
+                vec![attachment("receipt.png", b"png")],
+                None,
+            )
+            .unwrap();
+
+        // A new queue, e.g. after a restart, sees the same job
+        let queue = JobQueue::new(dir.path().to_path_buf());
+        let loaded = queue.get(&job.id).unwrap().unwrap();
+        assert_eq!(loaded.status, JobStatus::Queued);
+        assert_eq!(loaded.invoice().id, invoice.id);
+        assert_eq!(loaded.invoice().attachments[0].filename, "receipt.png");
+        assert_eq!(loaded.invoice().issued_at, invoice.issued_at);
+
+        let attachments = queue.attachments(&loaded).unwrap();
+        assert_eq!(attachments[0].bytes, b"png");
+        assert_eq!(queue.due().unwrap(), vec![job.id]);
+    }
+
+    #[test]
+    fn test_reloaded_foreign_job_has_barcode_note() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let queue = JobQueue::new(dir.path().to_path_buf());
+
+        let mut invoice = Invoice::test_fixture();
+        invoice.bank_account_number = "DE89 3704 0044 0532 0130 00".to_string();
+        invoice.barcode_note = Some(bank::FOREIGN_ACCOUNT_NOTE.to_string());
+        let job = queue.enqueue(invoice, vec![], None).unwrap();
+
+        let loaded = queue.get(&job.id).unwrap().unwrap();
+        assert_eq!(
+            loaded.invoice().barcode_note.as_deref(),
+            Some(bank::FOREIGN_ACCOUNT_NOTE)
+        );
+
+        let job = queue
+            .enqueue(Invoice::test_fixture(), vec![], None)
+            .unwrap();
+        assert_eq!(
+            queue.get(&job.id).unwrap().unwrap().invoice().barcode_note,
+            None
+        );
+    }
+
+    #[test]
+    fn test_finished_and_waiting_jobs_are_not_due() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let queue = JobQueue::new(dir.path().to_path_buf());
+
+        let mut completed = queue
+            .enqueue(Invoice::test_fixture(), vec![], None)
+            .unwrap();
+        completed.status = JobStatus::Completed;
+        queue.save(&completed).unwrap();
+
+        let mut retrying = queue
+            .enqueue(Invoice::test_fixture(), vec![], None)
+            .unwrap();
+        retrying.status = JobStatus::Retrying;
+        retrying.next_attempt_at = now() + 60;
+        queue.save(&retrying).unwrap();
+
+        assert!(queue.due().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_finished_job_is_removed_from_index() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let queue = JobQueue::new(dir.path().to_path_buf());
+
+        let mut job = queue
+            .enqueue(Invoice::test_fixture(), vec![], None)
+            .unwrap();
+        assert!(queue.pending_dir().join(&job.id).exists());
+
+        job.status = JobStatus::Completed;
+        queue.save(&job).unwrap();
+        assert!(!queue.pending_dir().join(&job.id).exists());
+        assert!(queue.due().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_index_is_rebuilt() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let queue = JobQueue::new(dir.path().to_path_buf());
+
+        let waiting = queue
+            .enqueue(Invoice::test_fixture(), vec![], None)
+            .unwrap();
+        let mut completed = queue
+            .enqueue(Invoice::test_fixture(), vec![], None)
+            .unwrap();
+        completed.status = JobStatus::Completed;
+        queue.save(&completed).unwrap();
+
+        // E.g. the jobs of a version without the index
+        fs::remove_dir_all(queue.pending_dir()).unwrap();
+        let queue = JobQueue::new(dir.path().to_path_buf());
+        assert_eq!(queue.due().unwrap(), vec![waiting.id.clone()]);
+        assert!(queue.pending_dir().join(&waiting.id).exists());
+        assert!(!queue.pending_dir().join(&completed.id).exists());
+    }
+
+    #[test]
+    fn test_failed_job_can_be_resent() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let queue = JobQueue::new(dir.path().to_path_buf());
+
+        let mut job = queue
+            .enqueue(Invoice::test_fixture(), vec![], None)
+            .unwrap();
+        assert!(matches!(queue.resend(&job.id), Err(Error::JobNotFailed)));
+
+        job.status = JobStatus::Failed;
+        job.attempts = 8;
+        job.last_error = Some("Mailgun rejected the email".to_string());
+        queue.save(&job).unwrap();
+        assert_eq!(queue.outbox().unwrap().len(), 1);
+        assert!(queue.due().unwrap().is_empty());
+
+        let resent = queue.resend(&job.id).unwrap();
+        assert_eq!(resent.status, JobStatus::Queued);
+        assert_eq!(resent.attempts, 0);
+        assert!(queue.outbox().unwrap().is_empty());
+        assert_eq!(queue.due().unwrap(), vec![job.id]);
+    }
+
+    #[test]
+    fn test_only_sent_invoice_can_be_approved() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let queue = JobQueue::new(dir.path().to_path_buf());
+
+        let mut job = queue
+            .enqueue(Invoice::test_fixture(), vec![], None)
+            .unwrap();
+        assert!(matches!(
+            queue.approve(&job.id, approval("2025-05-20"), None),
+            Err(Error::JobNotCompleted)
+        ));
+        assert!(queue.pending().unwrap().is_empty());
+
+        job.status = JobStatus::Completed;
+        queue.save(&job).unwrap();
+        assert_eq!(queue.pending().unwrap()[0].id, job.id);
+
+        // An invoice approved without a voucher could never get one
+        assert!(matches!(
+            queue.set_state(
+                &job.id,
+                InvoiceState::Approved {
+                    meeting: "Hallituksen kokous 5/2025".to_string(),
+                },
+            ),
+            Err(Error::InvalidState(_))
+        ));
+        assert_eq!(
+            queue.get(&job.id).unwrap().unwrap().state(),
+            &InvoiceState::Submitted
+        );
+
+        queue
+            .approve(&job.id, approval("2025-05-20"), None)
+            .unwrap();
+        assert_eq!(
+            queue.get(&job.id).unwrap().unwrap().state(),
+            &approval("2025-05-20").state()
+        );
+        assert!(queue.pending().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_unpaid_invoice_is_overdue() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let queue = JobQueue::new(dir.path().to_path_buf());
+        let today = chrono::NaiveDate::from_ymd_opt(2025, 6, 1).unwrap();
+
+        let sent = |due_date: &str| {
+            let mut invoice = Invoice::test_fixture();
+            invoice.due_date = Some(due_date.to_string());
+            let mut job = queue.enqueue(invoice, vec![], None).unwrap();
+            job.status = JobStatus::Completed;
+            queue.save(&job).unwrap();
+            job
+        };
+
+        let late = sent("2025-05-20");
+        let later = sent("2025-05-01");
+        sent("2025-06-01");
+        let paid = sent("2025-05-10");
+        queue
+            .approve(&paid.id, approval("2025-05-20"), None)
+            .unwrap();
+        queue
+            .set_state(
+                &paid.id,
+                InvoiceState::Paid {
+                    date: "2025-05-30".to_string(),
+                },
+            )
+            .unwrap();
+
+        let overdue: Vec<String> = queue
+            .overdue(today)
+            .unwrap()
+            .into_iter()
+            .map(|job| job.id)
+            .collect();
+        assert_eq!(overdue, vec![later.id, late.id]);
+    }
+
+    fn approval(date: &str) -> Approval {
+        Approval {
+            date: date.to_string(),
+            meeting: 5,
+            account: "4000".to_string(),
+            voucher: String::new(),
+        }
+    }
+
+    fn completed(queue: &JobQueue) -> Job {
+        let mut job = queue
+            .enqueue(Invoice::test_fixture(), vec![], None)
+            .unwrap();
+        job.status = JobStatus::Completed;
+        queue.save(&job).unwrap();
+        job
+    }
+
+    #[test]
+    fn test_approved_invoice_keeps_voucher() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let queue = JobQueue::new(dir.path().to_path_buf());
+
+        let job = completed(&queue);
+        assert!(matches!(
+            queue.voucher(&job.id),
+            Err(Error::VoucherNotFound)
+        ));
+
+        let (approved, voucher) = queue
+            .approve(&job.id, approval("2025-05-20"), None)
+            .unwrap();
+        assert_eq!(approved.approval().unwrap().voucher, "1");
+        assert_eq!(queue.voucher(&job.id).unwrap(), voucher);
+        assert!(matches!(
+            queue.approve(&job.id, approval("2025-05-20"), None),
+            Err(Error::InvalidTransition { .. })
+        ));
+
+        // The failed approval didn't take a number
+        let job = completed(&queue);
+        let (approved, _) = queue
+            .approve(&job.id, approval("2025-05-20"), None)
+            .unwrap();
+        assert_eq!(approved.approval().unwrap().voucher, "2");
+    }
+
+    #[test]
+    fn test_files_are_kept_until_handled() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let queue = JobQueue::new(dir.path().to_path_buf());
+        let files_exist = |job: &Job| {
+            [queue.attachment_path(&job.id, 0), queue.pdf_path(&job.id)].map(|path| path.exists())
+        };
+
+        let sent: Vec<Job> = (0..3)
+            .map(|_| {
+                let mut job = queue
+                    .enqueue(
+                        Invoice::test_fixture(),
+                        vec![attachment("receipt.png", b"png")],
+                        None,
+                    )
+                    .unwrap();
+                queue.save_pdf(&job.id, b"%PDF-1.7").unwrap();
+                job.status = JobStatus::Completed;
+                queue.save(&job).unwrap();
+                job
+            })
+            .collect();
+
+        queue
+            .approve(&sent[0].id, approval("2025-05-20"), None)
+            .unwrap();
+        queue
+            .set_state(
+                &sent[1].id,
+                InvoiceState::Rejected {
+                    reason: "No receipt".to_string(),
+                },
+            )
+            .unwrap();
+
+        // Only the status and the voucher of a handled invoice are kept, while the sent invoice
+        // waiting for the meeting keeps its files to be rendered again when approved
+        assert_eq!(files_exist(&sent[0]), [false, false]);
+        assert!(queue.voucher(&sent[0].id).is_ok());
+        assert_eq!(files_exist(&sent[1]), [false, false]);
+        assert_eq!(files_exist(&sent[2]), [true, true]);
+    }
+
+    #[test]
+    fn test_parallel_approvals_of_job_take_one_number() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let queue = JobQueue::new(dir.path().to_path_buf());
+        let job = completed(&queue);
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let queue = queue.clone();
+                let id = job.id.clone();
+                std::thread::spawn(move || queue.approve(&id, approval("2025-05-20"), None))
+            })
+            .collect();
+
+        let approved: Vec<Job> = handles
+            .into_iter()
+            .filter_map(|h| h.join().unwrap().ok())
+            .map(|(job, _)| job)
+            .collect();
+        assert_eq!(approved.len(), 1);
+        assert_eq!(approved[0].approval().unwrap().voucher, "1");
+
+        let (next, _) = queue
+            .approve(&completed(&queue).id, approval("2025-05-20"), None)
+            .unwrap();
+        assert_eq!(next.approval().unwrap().voucher, "2");
+    }
+
+    #[test]
+    fn test_vouchers_are_numbered_per_fiscal_year() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let queue = JobQueue::new(dir.path().to_path_buf()).numbering(VoucherNumbering {
+            prefix: "{year}/".to_string(),
+            start: 10,
+            yearly_reset: true,
+        });
+
+        let voucher = |date: &str| {
+            let job = completed(&queue);
+            let (job, _) = queue.approve(&job.id, approval(date), None).unwrap();
+            job.approval().unwrap().voucher.clone()
+        };
+
+        assert_eq!(voucher("2025-12-31"), "2025/10");
+        assert_eq!(voucher("2025-12-31"), "2025/11");
+        assert_eq!(voucher("2026-01-01"), "2026/10");
+        assert_eq!(voucher("2025-12-31"), "2025/12");
+    }
+
+    #[test]
+    fn test_parallel_approvals_get_distinct_numbers() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let queue = JobQueue::new(dir.path().to_path_buf());
+        let jobs: Vec<Job> = (0..20).map(|_| completed(&queue)).collect();
+
+        let handles: Vec<_> = jobs
+            .into_iter()
+            .enumerate()
+            .map(|(i, job)| {
+                // Half of the approvals go through another queue on the same directory
+                let queue = if i % 2 == 0 {
+                    queue.clone()
+                } else {
+                    JobQueue::new(dir.path().to_path_buf())
+                };
+                std::thread::spawn(move || {
+                    let (job, _) = queue
+                        .approve(&job.id, approval("2025-05-20"), None)
+                        .unwrap();
+                    job.approval().unwrap().voucher.parse::<u32>().unwrap()
+                })
+            })
+            .collect();
+
+        let mut numbers: Vec<u32> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        numbers.sort();
+        assert_eq!(numbers, (1..=20).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_invalid_job_id_is_not_found() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let queue = JobQueue::new(dir.path().to_path_buf());
+
+        assert!(queue.get("../../etc").unwrap().is_none());
+        assert!(queue
+            .get(&uuid::Uuid::new_v4().to_string())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_job_is_claimed_once() {
+        let id = uuid::Uuid::new_v4().to_string();
+
+        let claim = JobQueue::claim(&id);
+        assert!(claim.is_some());
+        assert!(JobQueue::claim(&id).is_none());
+
+        drop(claim);
+        assert!(JobQueue::claim(&id).is_some());
+    }
+
+    #[test]
+    fn test_retry_delay_doubles() {
+        let base = Duration::from_secs(30);
+
+        assert_eq!(retry_delay(base, 1), Duration::from_secs(30));
+        assert_eq!(retry_delay(base, 2), Duration::from_secs(60));
+        assert_eq!(retry_delay(base, 4), Duration::from_secs(240));
+        assert_eq!(retry_delay(base, 100), MAX_RETRY_DELAY);
+    }
+}
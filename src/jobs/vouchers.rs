@@ -0,0 +1,128 @@
+use crate::error::Error;
+use crate::CONFIG;
+
+use serde_derive::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, io::ErrorKind, path::Path};
+
+/// How the vouchers of approved invoices are numbered
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VoucherNumbering {
+    /// Prepended to the number, with `{year}` replaced by the fiscal year, e.g. `{year}-` gives
+    /// `2025-12`
+    pub prefix: String,
+    /// The first number of each fiscal year, or of all vouchers if the numbering isn't reset
+    pub start: u32,
+    /// Whether the numbering starts over each fiscal year
+    pub yearly_reset: bool,
+}
+
+impl Default for VoucherNumbering {
+    fn default() -> Self {
+        Self {
+            prefix: String::new(),
+            start: 1,
+            yearly_reset: true,
+        }
+    }
+}
+
+impl VoucherNumbering {
+    pub fn from_config() -> Self {
+        Self {
+            prefix: CONFIG.voucher_prefix.clone(),
+            start: CONFIG.voucher_start,
+            yearly_reset: !CONFIG.voucher_continuous,
+        }
+    }
+
+    /// The sequence the vouchers of the fiscal year are numbered in
+    fn sequence(&self, year: i32) -> String {
+        if self.yearly_reset {
+            year.to_string()
+        } else {
+            "continuous".to_string()
+        }
+    }
+
+    pub fn format(&self, year: i32, number: u32) -> String {
+        format!(
+            "{}{number}",
+            self.prefix.replace("{year}", &year.to_string())
+        )
+    }
+}
+
+/// The last number handed out in each sequence, stored as `vouchers.json` in the job directory
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct VoucherCounter(BTreeMap<String, u32>);
+
+impl VoucherCounter {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        match fs::read(path) {
+            Ok(contents) => Ok(serde_json::from_slice(&contents)?),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Hands out the next number of the fiscal year. The number is only taken once the counter
+    /// has been saved.
+    ///
+    /// The counter has 0 for the sequences without numbers, so the numbers start from 1 at the
+    /// earliest, as `VOUCHER_START` requires.
+    pub fn next(&mut self, numbering: &VoucherNumbering, year: i32) -> u32 {
+        let start = numbering.start.max(1);
+        let last = self.0.entry(numbering.sequence(year)).or_default();
+        *last = match *last {
+            0 => start,
+            last => (last + 1).max(start),
+        };
+        *last
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numbering_resets_yearly() {
+        let numbering = VoucherNumbering {
+            prefix: "{year}-".to_string(),
+            start: 100,
+            yearly_reset: true,
+        };
+        let mut counter = VoucherCounter::default();
+
+        assert_eq!(counter.next(&numbering, 2025), 100);
+        assert_eq!(counter.next(&numbering, 2025), 101);
+        assert_eq!(counter.next(&numbering, 2026), 100);
+        assert_eq!(numbering.format(2025, 101), "2025-101");
+    }
+
+    #[test]
+    fn test_continuous_numbering() {
+        let numbering = VoucherNumbering {
+            prefix: "T".to_string(),
+            start: 1,
+            yearly_reset: false,
+        };
+        let mut counter = VoucherCounter::default();
+
+        assert_eq!(counter.next(&numbering, 2025), 1);
+        assert_eq!(counter.next(&numbering, 2026), 2);
+        assert_eq!(numbering.format(2026, 2), "T2");
+    }
+
+    #[test]
+    fn test_numbering_starts_from_one() {
+        let numbering = VoucherNumbering {
+            start: 0,
+            ..Default::default()
+        };
+        let mut counter = VoucherCounter::default();
+
+        assert_eq!(counter.next(&numbering, 2025), 1);
+        assert_eq!(counter.next(&numbering, 2025), 2);
+    }
+}
@@ -0,0 +1,398 @@
+use super::{now, retry_delay, Job, JobQueue, JobStatus, Mail, MailJob};
+use crate::audit::{AuditEvent, AuditRecord, AUDIT_LOG};
+use crate::error::Error;
+use crate::mailgun::MailgunClient;
+use crate::notifications;
+use crate::outbound::OutboundInvoices;
+use crate::pdfgen::DocumentBuilder;
+use crate::signing::Signer;
+use crate::CONFIG;
+
+use std::{sync::Arc, time::Duration};
+use tokio::sync::Semaphore;
+
+/// How often the queue is checked for jobs that are due for a retry
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The maximum number of jobs processed at once
+const MAX_CONCURRENT_JOBS: usize = 4;
+
+/// Renders and sends the invoices in the queue
+#[derive(Clone)]
+pub struct Worker {
+    queue: JobQueue,
+    mailgun_client: Option<MailgunClient>,
+    signer: Option<Signer>,
+    outbound: Option<OutboundInvoices>,
+}
+
+impl Worker {
+    pub fn new(
+        queue: JobQueue,
+        mailgun_client: Option<MailgunClient>,
+        signer: Option<Signer>,
+    ) -> Self {
+        Self {
+            queue,
+            mailgun_client,
+            signer,
+            outbound: None,
+        }
+    }
+
+    /// Sets where the outbound invoices queued for sending are read from
+    pub fn outbound(mut self, outbound: Option<OutboundInvoices>) -> Self {
+        self.outbound = outbound;
+        self
+    }
+
+    /// Runs a queue operation on the blocking thread pool, as the queue is stored in files
+    async fn blocking<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&JobQueue) -> Result<T, Error> + Send + 'static,
+    ) -> Result<T, Error> {
+        let queue = self.queue.clone();
+        tokio::task::spawn_blocking(move || f(&queue)).await?
+    }
+
+    /// Processes the jobs in the queue until the runtime shuts down
+    pub fn spawn(self) {
+        tokio::spawn(async move {
+            let permits = Arc::new(Semaphore::new(MAX_CONCURRENT_JOBS));
+
+            loop {
+                let due = self.blocking(JobQueue::due).await.unwrap_or_else(|e| {
+                    error!("Failed to read the job queue: {e}");
+                    vec![]
+                });
+                let due_mail = self.blocking(JobQueue::due_mail).await.unwrap_or_else(|e| {
+                    error!("Failed to read the email queue: {e}");
+                    vec![]
+                });
+
+                let due = due.into_iter().map(|id| (id, false));
+                for (id, mail) in due.chain(due_mail.into_iter().map(|id| (id, true))) {
+                    let Some(claim) = JobQueue::claim(&id) else {
+                        continue;
+                    };
+                    let Ok(permit) = permits.clone().acquire_owned().await else {
+                        return;
+                    };
+
+                    let worker = self.clone();
+                    tokio::spawn(async move {
+                        if mail {
+                            worker.process_mail(&id).await;
+                        } else {
+                            worker.process(&id).await;
+                        }
+                        drop((claim, permit));
+                    });
+                }
+
+                tokio::select! {
+                    _ = self.queue.notify.notified() => {}
+                    _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                }
+            }
+        });
+    }
+
+    async fn save(&self, job: &Job) -> Result<(), Error> {
+        let job = job.clone();
+        self.blocking(move |queue| queue.save(&job)).await
+    }
+
+    /// Makes an attempt at the job and schedules a retry if it fails
+    async fn process(&self, id: &str) {
+        let loading = id.to_string();
+        let mut job = match self.blocking(move |queue| queue.get(&loading)).await {
+            // The index of the waiting jobs may be behind if the service stopped while saving
+            Ok(Some(job)) if !job.status.is_waiting() => {
+                if let Err(e) = self.blocking(move |queue| queue.index(&job)).await {
+                    error!("Failed to update the index of job {id}: {e}");
+                }
+                return;
+            }
+            Ok(Some(job)) => job,
+            Ok(None) => return,
+            Err(e) => {
+                error!("Failed to load job {id}: {e}");
+                return;
+            }
+        };
+
+        job.status = JobStatus::Processing;
+        job.attempts += 1;
+        if let Err(e) = self.save(&job).await {
+            error!("Failed to update job {id}: {e}");
+            return;
+        }
+
+        match self.run(&mut job).await {
+            Ok(()) => {
+                // The files are kept until the invoice has been handled, see `remove_files`
+                info!("Sent invoice {id}");
+                job.status = JobStatus::Completed;
+                job.last_error = None;
+            }
+            Err(e) => match retry_delay_after(&e, job.attempts) {
+                None => {
+                    error!(
+                        "Moving job {id} to the outbox after {} attempts: {e}",
+                        job.attempts
+                    );
+                    job.status = JobStatus::Failed;
+                    job.last_error = Some(e.to_string());
+                }
+                Some(delay) => {
+                    warn!(
+                        "Attempt {} of job {id} failed, retrying in {delay:?}: {e}",
+                        job.attempts
+                    );
+                    job.status = JobStatus::Retrying;
+                    job.last_error = Some(e.to_string());
+                    job.next_attempt_at = now() + delay.as_secs() as i64;
+                }
+            },
+        }
+
+        if let Err(e) = self.save(&job).await {
+            error!("Failed to update job {id}: {e}");
+        }
+    }
+
+    /// Makes an attempt at sending the email and schedules a retry if it fails
+    async fn process_mail(&self, id: &str) {
+        let loading = id.to_string();
+        let mut job = match self.blocking(move |queue| queue.get_mail(&loading)).await {
+            Ok(Some(job)) if job.status.is_waiting() => job,
+            Ok(_) => return,
+            Err(e) => {
+                error!("Failed to load email {id}: {e}");
+                return;
+            }
+        };
+
+        job.status = JobStatus::Processing;
+        job.attempts += 1;
+        if let Err(e) = self.save_mail(&job).await {
+            error!("Failed to update email {id}: {e}");
+            return;
+        }
+
+        match self.send(&job).await {
+            Ok(()) => {
+                info!("Sent email {id}");
+                job.status = JobStatus::Completed;
+                job.last_error = None;
+            }
+            Err(e) => match retry_delay_after(&e, job.attempts) {
+                None => {
+                    error!(
+                        "Moving email {id} to the outbox after {} attempts: {e}",
+                        job.attempts
+                    );
+                    job.status = JobStatus::Failed;
+                    job.last_error = Some(e.to_string());
+                }
+                Some(delay) => {
+                    warn!(
+                        "Attempt {} of email {id} failed, retrying in {delay:?}: {e}",
+                        job.attempts
+                    );
+                    job.status = JobStatus::Retrying;
+                    job.last_error = Some(e.to_string());
+                    job.next_attempt_at = now() + delay.as_secs() as i64;
+                }
+            },
+        }
+
+        if let Err(e) = self.save_mail(&job).await {
+            error!("Failed to update email {id}: {e}");
+        }
+    }
+
+    async fn save_mail(&self, job: &MailJob) -> Result<(), Error> {
+        let job = job.clone();
+        self.blocking(move |queue| queue.save_mail(&job)).await
+    }
+
+    /// Loads the submitted invoice an email is about
+    async fn invoice(&self, id: &str) -> Result<Job, Error> {
+        let loading = id.to_string();
+        self.blocking(move |queue| queue.get(&loading))
+            .await?
+            .ok_or(Error::JobNotFound)
+    }
+
+    /// Sends the email, recording the attempt in the audit log
+    async fn send(&self, job: &MailJob) -> Result<(), Error> {
+        let client = self.mailgun_client.as_ref().ok_or(Error::MailDisabled)?;
+
+        let (sent, actor, details) = match &job.mail {
+            Mail::Confirmation { invoice_id } => {
+                let invoice = self.invoice(invoice_id).await?;
+                let invoice = invoice.invoice();
+                (
+                    client.send_confirmation(invoice).await,
+                    format!("{} <{}>", invoice.recipient_name, invoice.recipient_email),
+                    "Confirmation to the submitter".to_string(),
+                )
+            }
+            Mail::Notification { invoice_id, state } => {
+                let invoice = self.invoice(invoice_id).await?;
+
+                // Nothing is sent to the submitters who have unsubscribed
+                match notifications::notify(client, invoice.invoice(), state).await {
+                    Ok(false) => return Ok(()),
+                    sent => (
+                        sent.map(|_| ()),
+                        "admin".to_string(),
+                        format!("Notification: {}", state.name()),
+                    ),
+                }
+            }
+            Mail::Outbound { number } => {
+                let outbound = self.outbound.clone().ok_or(Error::OutboundDisabled)?;
+                let reading = outbound.clone();
+                let loading = number.clone();
+                let (invoice, pdf) =
+                    tokio::task::spawn_blocking(move || reading.with_pdf(&loading)).await??;
+
+                let sent = client.send_outbound(&invoice, pdf).await;
+                if sent.is_ok() {
+                    // The invoice was delivered, so it isn't sent again even if this fails
+                    let marking = number.clone();
+                    match tokio::task::spawn_blocking(move || outbound.mark_sent(&marking)).await {
+                        Ok(Ok(_)) => {}
+                        Ok(Err(e)) => error!("Failed to mark outbound invoice {number} sent: {e}"),
+                        Err(e) => error!("Failed to mark outbound invoice {number} sent: {e}"),
+                    }
+                }
+                (
+                    sent,
+                    "admin".to_string(),
+                    format!("Outbound invoice {number} to {}", invoice.customer.email),
+                )
+            }
+        };
+
+        let record = match &sent {
+            Ok(()) => AuditRecord::new(AuditEvent::EmailSent).details(details),
+            Err(e) => AuditRecord::new(AuditEvent::EmailFailed).details(format!("{details}: {e}")),
+        };
+        let record = record.actor(actor).ip(job.ip);
+        AUDIT_LOG
+            .record(match job.mail.invoice_id() {
+                Some(id) => record.invoice_id(id),
+                None => record,
+            })
+            .await;
+
+        sent
+    }
+
+    /// Renders the invoice, unless an earlier attempt already did, and sends it
+    async fn run(&self, job: &mut Job) -> Result<(), Error> {
+        let submitter = format!(
+            "{} <{}>",
+            job.invoice.recipient_name, job.invoice.recipient_email
+        );
+
+        let id = job.id.clone();
+        let pdf = match self.blocking(move |queue| queue.pdf(&id)).await? {
+            Some(pdf) => pdf,
+            None => {
+                let rendered = self.render(job).await;
+
+                let record = match &rendered {
+                    Ok(pdf) => AuditRecord::new(AuditEvent::Rendered).pdf(pdf),
+                    Err(e) => AuditRecord::new(AuditEvent::RenderFailed).details(e.to_string()),
+                };
+                AUDIT_LOG
+                    .record(record.actor(&submitter).ip(job.ip).invoice_id(&job.id))
+                    .await;
+
+                rendered?
+            }
+        };
+
+        if let Some(client) = &self.mailgun_client {
+            if !job.treasurer_sent {
+                let sent = client.send_mail(&job.invoice, pdf).await;
+
+                let record = match &sent {
+                    Ok(()) => AuditRecord::new(AuditEvent::EmailSent),
+                    Err(e) => AuditRecord::new(AuditEvent::EmailFailed).details(e.to_string()),
+                };
+                AUDIT_LOG
+                    .record(record.actor(&submitter).ip(job.ip).invoice_id(&job.id))
+                    .await;
+
+                sent?;
+                // Don't send the invoice again if queueing the confirmation fails
+                job.treasurer_sent = true;
+                self.save(job).await?;
+            }
+
+            // The confirmation is queued separately, so that the invoice counts as delivered even
+            // if the confirmation can't be sent
+            let mail = Mail::Confirmation {
+                invoice_id: job.id.clone(),
+            };
+            let ip = job.ip;
+            self.blocking(move |queue| queue.enqueue_mail(mail, ip))
+                .await?;
+        } else {
+            use tempfile::NamedTempFile;
+            use tokio::fs::File;
+            use tokio::io::AsyncWriteExt;
+
+            let tmp = NamedTempFile::with_suffix(".pdf")?;
+            let (file, path) = tmp.keep().map_err(|e| e.error)?;
+            let mut file = File::from_std(file);
+            file.write_all(&pdf).await?;
+
+            info!("Wrote invoice to {:?}", path);
+        }
+
+        Ok(())
+    }
+
+    /// Renders the PDF and stores it with the job, so that a retry only has to send the email
+    async fn render(&self, job: &Job) -> Result<Vec<u8>, Error> {
+        let queue = self.queue.clone();
+        let signer = self.signer.clone();
+        let stored = job.clone();
+
+        // PDF compilation is heavily blocking
+        tokio::task::spawn_blocking(move || {
+            let attachments = queue.attachments(&stored)?;
+            let pdf = DocumentBuilder::new(stored.invoice, attachments)
+                .pdf_a(CONFIG.pdf_a)
+                .signer(signer)
+                .build_pdf()?;
+            queue.save_pdf(&stored.id, &pdf)?;
+            Ok::<_, Error>(pdf)
+        })
+        .await?
+    }
+}
+
+/// The delay before the next attempt after a failed one, or `None` if the job should be moved to
+/// the outbox because the error isn't transient or the attempts ran out
+fn retry_delay_after(e: &Error, attempts: u32) -> Option<Duration> {
+    if !e.is_transient() || attempts >= CONFIG.job_max_attempts {
+        return None;
+    }
+
+    let delay = retry_delay(Duration::from_secs(CONFIG.job_retry_base_secs), attempts);
+    match e {
+        Error::MailUnavailable {
+            retry_after: Some(retry_after),
+            ..
+        } => Some(delay.max(*retry_after)),
+        _ => Some(delay),
+    }
+}
@@ -1,12 +1,23 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use std::sync::LazyLock;
 
 pub mod api;
+pub mod audit;
+pub mod bank;
+pub mod duplicates;
 pub mod error;
+pub mod idempotency;
+pub mod jobs;
+pub mod local_time;
 pub mod mailgun;
 pub mod merge;
+pub mod notifications;
+pub mod outbound;
 pub mod pdfgen;
+pub mod signing;
 pub mod state;
+pub mod util;
+pub mod webhooks;
 
 #[macro_use]
 extern crate tracing;
@@ -49,13 +60,58 @@ pub struct MailgunConfig {
         required_if_eq("disable", "false")
     )]
     pub from: Option<String>,
+    #[clap(
+        long = "mailgun-timeout-secs",
+        env = "MAILGUN_TIMEOUT_SECS",
+        default_value = "30"
+    )]
+    pub timeout_secs: u64,
+}
+
+#[derive(Parser, Clone, Debug)]
+pub struct SigningConfig {
+    #[clap(
+        long = "signing-certificate",
+        env = "SIGNING_CERTIFICATE",
+        requires = "key"
+    )]
+    pub certificate: Option<std::path::PathBuf>,
+    #[clap(long = "signing-key", env = "SIGNING_KEY", requires = "certificate")]
+    pub key: Option<std::path::PathBuf>,
+}
+
+#[derive(Subcommand, Clone, Debug)]
+pub enum Command {
+    /// Verify the hash chain of the audit log and exit
+    VerifyAuditLog,
+    /// List the invoices that couldn't be sent and exit
+    ListOutbox,
+    /// Queue an invoice in the outbox to be sent again by the service and exit
+    ResendOutbox {
+        /// The ID of the job
+        id: String,
+    },
+    /// Render the invoices waiting for approval as an appendix to the minutes of a board meeting
+    /// and exit
+    Agenda {
+        /// The meeting shown in the heading, e.g. "Hallituksen kokous 5/2025"
+        #[clap(long)]
+        meeting: Option<String>,
+        /// Where to write the PDF
+        #[clap(long, short, default_value = "laskut.pdf")]
+        output: std::path::PathBuf,
+    },
 }
 
 #[derive(Parser, Clone, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, subcommand_negates_reqs = true)]
 pub struct LaskugenConfig {
+    #[command(subcommand)]
+    pub command: Option<Command>,
     #[clap(flatten)]
     pub mailgun: MailgunConfig,
+    #[clap(flatten)]
+    pub signing: SigningConfig,
     #[clap(long, env, required = false, default_value = "3000")]
     pub port: u16,
     #[clap(long, env, required = false, default_value = "127.0.0.1")]
@@ -68,6 +124,79 @@ pub struct LaskugenConfig {
     pub rate_limit_period_secs: u64,
     #[clap(long, env, default_value = "5")]
     pub rate_limit_burst_size: u32,
+    #[clap(long, env, default_value = "false")]
+    pub pdf_a: bool,
+    #[clap(long, env, default_value = "audit.jsonl")]
+    pub audit_log: std::path::PathBuf,
+    #[clap(long, env)]
+    pub admin_token: Option<String>,
+    #[clap(long, env, default_value = "86400")]
+    pub idempotency_window_secs: u64,
+    #[clap(long, env, default_value = "receipts.jsonl")]
+    pub receipt_index: std::path::PathBuf,
+    #[clap(long, env)]
+    pub public_url: Option<String>,
+    #[clap(long, env, default_value = "14")]
+    pub processing_time_days: u32,
+    #[clap(long, env, default_value = "14")]
+    pub payment_terms_days: u32,
+    #[clap(long, env)]
+    pub notification_secret: Option<String>,
+    #[clap(long, env, default_value = "unsubscribed.txt")]
+    pub unsubscribe_list: std::path::PathBuf,
+    #[clap(long, env, default_value = "jobs")]
+    pub job_dir: std::path::PathBuf,
+    #[clap(long, env, default_value = "8")]
+    pub job_max_attempts: u32,
+    #[clap(long, env, default_value = "30")]
+    pub job_retry_base_secs: u64,
+    #[clap(long, env, default_value = "")]
+    pub voucher_prefix: String,
+    // The voucher counter takes 0 to mean that no number has been handed out yet
+    #[clap(
+        long,
+        env,
+        default_value = "1",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pub voucher_start: u32,
+    #[clap(long, env, default_value = "false")]
+    pub voucher_continuous: bool,
+    #[clap(long, env, default_value = "Tietokilta ry")]
+    pub issuer_name: String,
+    #[clap(long, env)]
+    pub issuer_business_id: Option<String>,
+    #[clap(long, env)]
+    pub issuer_address: Option<String>,
+    #[clap(long, env)]
+    pub issuer_iban: Option<String>,
+    #[clap(long, env)]
+    pub issuer_email: Option<String>,
+    #[clap(long, env, default_value = "outbound")]
+    pub outbound_dir: std::path::PathBuf,
+    // The reference numbers are derived from the invoice numbers and need at least three digits
+    #[clap(
+        long,
+        env,
+        default_value = "1000",
+        value_parser = clap::value_parser!(u32).range(100..)
+    )]
+    pub outbound_number_start: u32,
+    #[clap(
+        long = "webhook-url",
+        env = "WEBHOOK_URLS",
+        value_delimiter = ',',
+        requires = "webhook_secret"
+    )]
+    pub webhook_urls: Vec<String>,
+    #[clap(long, env)]
+    pub webhook_secret: Option<String>,
+    #[clap(long, env, default_value = "webhooks.jsonl")]
+    pub webhook_log: std::path::PathBuf,
+    #[clap(long, env, default_value = "5")]
+    pub webhook_max_attempts: u32,
+    #[clap(long, env, default_value = "10")]
+    pub webhook_retry_base_secs: u64,
 }
 
 pub static CONFIG: LazyLock<LaskugenConfig> = LazyLock::new(LaskugenConfig::parse);
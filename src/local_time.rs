@@ -0,0 +1,74 @@
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
+use chrono_tz::Europe::Helsinki;
+
+/// The time in Helsinki, with the offset in effect at that moment
+pub fn at(time: DateTime<Utc>) -> DateTime<FixedOffset> {
+    time.with_timezone(&Helsinki).fixed_offset()
+}
+
+/// The current time in Helsinki
+pub fn now() -> DateTime<FixedOffset> {
+    at(Utc::now())
+}
+
+/// The current date in Helsinki
+pub fn today() -> NaiveDate {
+    now().date_naive()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Finnish standard time, UTC+2
+    const STANDARD_OFFSET_SECS: i32 = 2 * 60 * 60;
+    /// Finnish summer time, UTC+3
+    const SUMMER_OFFSET_SECS: i32 = 3 * 60 * 60;
+
+    fn utc(time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(time).unwrap().to_utc()
+    }
+
+    #[test]
+    fn test_winter_and_summer_offsets() {
+        assert_eq!(
+            at(utc("2025-01-15T12:00:00Z")).to_rfc3339(),
+            "2025-01-15T14:00:00+02:00"
+        );
+        assert_eq!(
+            at(utc("2025-07-15T12:00:00Z")).to_rfc3339(),
+            "2025-07-15T15:00:00+03:00"
+        );
+    }
+
+    #[test]
+    fn test_summer_time_boundaries() {
+        // The summer time of 2025 started on 30 March and ended on 26 October at 01:00 UTC
+        assert_eq!(
+            at(utc("2025-03-30T00:59:59Z")).offset().local_minus_utc(),
+            STANDARD_OFFSET_SECS
+        );
+        assert_eq!(
+            at(utc("2025-03-30T01:00:00Z")).offset().local_minus_utc(),
+            SUMMER_OFFSET_SECS
+        );
+        assert_eq!(
+            at(utc("2025-10-26T00:59:59Z")).offset().local_minus_utc(),
+            SUMMER_OFFSET_SECS
+        );
+        assert_eq!(
+            at(utc("2025-10-26T01:00:00Z")).offset().local_minus_utc(),
+            STANDARD_OFFSET_SECS
+        );
+    }
+
+    #[test]
+    fn test_date_after_midnight() {
+        // Just after midnight in Helsinki, but still the previous day in UTC
+        let time = at(utc("2025-05-19T21:30:00Z"));
+        assert_eq!(
+            time.date_naive(),
+            NaiveDate::from_ymd_opt(2025, 5, 20).unwrap()
+        );
+    }
+}
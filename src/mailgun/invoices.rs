@@ -1,43 +1,280 @@
+use super::templates::{self, Body};
 use super::MailgunClient;
 use crate::api::invoices::Invoice;
 use crate::error::Error;
-use chrono::{self, Local};
+use crate::jobs::InvoiceState;
+use crate::local_time;
+use crate::outbound::OutboundInvoice;
+use reqwest::{
+    header::{HeaderMap, RETRY_AFTER},
+    StatusCode,
+};
+use std::time::Duration;
+
+/// An email sent with Mailgun
+pub struct Message {
+    pub to: String,
+    pub subject: String,
+    pub body: Body,
+    pub attachment: Option<(String, Vec<u8>)>,
+    /// Additional headers of the email, e.g. `List-Unsubscribe`
+    pub headers: Vec<(String, String)>,
+}
 
 impl MailgunClient {
-    pub async fn send_mail(self, invoice: &Invoice, pdf: Vec<u8>) -> Result<(), Error> {
-        let invoice_recipient = format!("{} <{}>", invoice.recipient_name, invoice.recipient_email);
-        let form = reqwest::multipart::Form::new()
-            .text("from", self.from)
-            .text("to", self.default_to)
-            .text("cc", invoice_recipient)
-            .text(
-                "subject",
-                format!("Uusi lasku, lähettäjä {}", invoice.recipient_name),
-            )
-            .text(
-                "html",
-                format!("Uusi lasku, lähettäjä {}", invoice.recipient_name),
-            )
-            .part(
+    /// Sends the message, telling apart the errors worth retrying from the ones that aren't
+    pub async fn send(&self, message: Message) -> Result<(), Error> {
+        let mut form = reqwest::multipart::Form::new()
+            .text("from", self.from.clone())
+            .text("to", message.to)
+            .text("subject", message.subject)
+            .text("html", message.body.html)
+            .text("text", message.body.text);
+
+        for (name, value) in message.headers {
+            form = form.text(format!("h:{name}"), value);
+        }
+
+        if let Some((filename, bytes)) = message.attachment {
+            form = form.part(
                 "attachment",
-                reqwest::multipart::Part::bytes(pdf).file_name(format!(
-                    "{creator} - {date}.pdf",
-                    creator = invoice.recipient_name,
-                    date = Local::now().date_naive().format("%Y-%m-%d")
-                )),
+                reqwest::multipart::Part::bytes(bytes).file_name(filename),
             );
+        }
 
         let response = self
             .client
-            .post(self.url)
-            .basic_auth(self.api_user, Some(self.api_key))
+            .post(&self.url)
+            .basic_auth(&self.api_user, Some(&self.api_key))
             .multipart(form)
             .send()
-            .await?;
+            .await
+            .map_err(|e| Error::MailUnavailable {
+                message: e.to_string(),
+                retry_after: None,
+            })?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+
+        let retry_after = retry_after(response.headers());
+        let body = response.text().await.unwrap_or_default();
+        let message = format!("{status} {}", body.trim());
+
+        if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+            Err(Error::MailUnavailable {
+                message,
+                retry_after,
+            })
+        } else {
+            Err(Error::MailRejected(message))
+        }
+    }
+
+    /// Sends the invoice to the treasurer
+    pub async fn send_mail(&self, invoice: &Invoice, pdf: Vec<u8>) -> Result<(), Error> {
+        self.send(Message {
+            to: self.default_to.clone(),
+            subject: format!("Uusi lasku, lähettäjä {}", invoice.recipient_name),
+            body: templates::treasurer(invoice)?,
+            attachment: Some((
+                format!(
+                    "{creator} - {date}.pdf",
+                    creator = invoice.recipient_name,
+                    date = invoice
+                        .issued()
+                        .unwrap_or_else(local_time::now)
+                        .format("%Y-%m-%d")
+                ),
+                pdf,
+            )),
+            headers: vec![],
+        })
+        .await
+    }
+
+    /// Confirms to the submitter that the invoice was received
+    pub async fn send_confirmation(&self, invoice: &Invoice) -> Result<(), Error> {
+        self.send(Message {
+            to: format!("{} <{}>", invoice.recipient_name, invoice.recipient_email),
+            subject: format!("Lasku vastaanotettu: {}", invoice.subject),
+            body: templates::confirmation(invoice)?,
+            attachment: None,
+            headers: vec![],
+        })
+        .await
+    }
+
+    /// Tells the submitter that the invoice moved to a new state
+    pub async fn send_notification(
+        &self,
+        invoice: &Invoice,
+        state: &InvoiceState,
+        unsubscribe_url: &str,
+    ) -> Result<(), Error> {
+        let subject = match state {
+            InvoiceState::Submitted => "Lasku vastaanotettu",
+            InvoiceState::Approved { .. } => "Lasku hyväksytty",
+            InvoiceState::Paid { .. } => "Lasku maksettu",
+            InvoiceState::Rejected { .. } => "Lasku hylätty",
+        };
+
+        self.send(Message {
+            to: format!("{} <{}>", invoice.recipient_name, invoice.recipient_email),
+            subject: format!("{subject}: {}", invoice.subject),
+            body: templates::notification(invoice, state, unsubscribe_url)?,
+            attachment: None,
+            // Lets email clients unsubscribe with a POST to the link (RFC 8058)
+            headers: vec![
+                (
+                    "List-Unsubscribe".to_string(),
+                    format!("<{unsubscribe_url}>"),
+                ),
+                (
+                    "List-Unsubscribe-Post".to_string(),
+                    "List-Unsubscribe=One-Click".to_string(),
+                ),
+            ],
+        })
+        .await
+    }
+
+    /// Sends the outbound invoice to the customer, with the replies going to the issuer
+    pub async fn send_outbound(
+        &self,
+        invoice: &OutboundInvoice,
+        pdf: Vec<u8>,
+    ) -> Result<(), Error> {
+        let headers = match &invoice.issuer.email {
+            Some(email) => vec![("Reply-To".to_string(), email.clone())],
+            None => vec![],
+        };
 
-        match response.error_for_status() {
-            Ok(_) => Ok(()),
-            Err(e) => Err(Error::ReqwestError(e)),
+        self.send(Message {
+            to: format!("{} <{}>", invoice.customer.name, invoice.customer.email),
+            subject: format!("Lasku {}: {}", invoice.number, invoice.subject),
+            body: templates::outbound(invoice)?,
+            attachment: Some((format!("lasku-{}.pdf", invoice.number), pdf)),
+            headers,
+        })
+        .await
+    }
+}
+
+/// The delay requested by the `Retry-After` header, given either in seconds or as an HTTP date
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    // HTTP dates, e.g. `Wed, 21 Oct 2015 07:28:00 GMT`, are a subset of the RFC 2822 format. A
+    // date in the past asks for no delay.
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (date.to_utc() - chrono::Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::Path, routing::post, Router};
+
+    /// Starts a stand-in for Mailgun that responds with the status in the path, e.g. `/429`
+    async fn mock_mailgun() -> String {
+        let app = Router::new().route(
+            "/{status}",
+            post(|Path(status): Path<u16>| async move {
+                (
+                    StatusCode::from_u16(status).unwrap(),
+                    [(RETRY_AFTER, "120")],
+                    "Mailgun says no",
+                )
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        format!("http://{addr}")
+    }
+
+    fn client(url: String) -> MailgunClient {
+        MailgunClient {
+            client: reqwest::Client::new(),
+            url,
+            api_user: "api".to_string(),
+            api_key: "key".to_string(),
+            default_to: "treasurer@example.com".to_string(),
+            from: "laskut@example.com".to_string(),
+        }
+    }
+
+    fn message() -> Message {
+        Message {
+            to: "Test User <test@example.com>".to_string(),
+            subject: "Test".to_string(),
+            body: Body {
+                html: "<p>Test</p>".to_string(),
+                text: "Test".to_string(),
+            },
+            attachment: Some(("test.pdf".to_string(), b"%PDF-1.7".to_vec())),
+            headers: vec![("Reply-To".to_string(), "treasurer@example.com".to_string())],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_errors_are_classified() {
+        let url = mock_mailgun().await;
+        assert!(client(format!("{url}/200")).send(message()).await.is_ok());
+
+        for status in [429, 500, 503] {
+            match client(format!("{url}/{status}")).send(message()).await {
+                Err(Error::MailUnavailable { retry_after, .. }) => {
+                    assert_eq!(retry_after, Some(Duration::from_secs(120)))
+                }
+                other => panic!("{status} should be retried, got {other:?}"),
+            }
         }
+
+        for status in [400, 401, 404] {
+            match client(format!("{url}/{status}")).send(message()).await {
+                Err(Error::MailRejected(message)) => assert!(message.contains("Mailgun says no")),
+                other => panic!("{status} shouldn't be retried, got {other:?}"),
+            }
+        }
+
+        // Nothing listens on port 1
+        assert!(matches!(
+            client("http://127.0.0.1:1".to_string())
+                .send(message())
+                .await,
+            Err(Error::MailUnavailable {
+                retry_after: None,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let headers = |value: &str| HeaderMap::from_iter([(RETRY_AFTER, value.parse().unwrap())]);
+
+        assert_eq!(retry_after(&HeaderMap::new()), None);
+        assert_eq!(retry_after(&headers("120")), Some(Duration::from_secs(120)));
+        assert!(
+            retry_after(&headers("Fri, 01 Jan 2100 00:00:00 GMT")).unwrap()
+                > Duration::from_secs(365 * 24 * 60 * 60)
+        );
+        assert_eq!(
+            retry_after(&headers("Wed, 21 Oct 2015 07:28:00 GMT")),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(retry_after(&headers("soon")), None);
     }
 }
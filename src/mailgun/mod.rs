@@ -3,8 +3,12 @@ use axum::{
     extract::{FromRef, OptionalFromRequestParts},
     http::request::Parts,
 };
+use std::time::Duration;
 
 mod invoices;
+mod templates;
+
+pub use invoices::Message;
 
 #[derive(Clone, Debug)]
 pub struct MailgunClient {
@@ -31,7 +35,10 @@ impl TryFrom<crate::MailgunConfig> for MailgunClient {
                 from: config
                     .from
                     .ok_or("mailgun 'from' address is not configured")?,
-                client: reqwest::Client::new(),
+                client: reqwest::Client::builder()
+                    .timeout(Duration::from_secs(config.timeout_secs))
+                    .build()
+                    .map_err(|e| format!("failed to build the HTTP client: {e}"))?,
             })
         } else {
             Err("mailgun is disabled".to_string())
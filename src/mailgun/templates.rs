@@ -0,0 +1,309 @@
+use crate::api::invoices::Invoice;
+use crate::error::Error;
+use crate::jobs::InvoiceState;
+use crate::outbound::OutboundInvoice;
+use crate::CONFIG;
+
+use minijinja::Environment;
+use serde_derive::Serialize;
+use std::sync::LazyLock;
+
+/// The email templates. Templates ending in `.html` escape the values inserted into them.
+static TEMPLATES: LazyLock<Environment<'static>> = LazyLock::new(|| {
+    let mut env = Environment::new();
+    env.add_filter("euros", euros);
+    env.add_filter("date", finnish_date);
+
+    let templates = [
+        (
+            "treasurer.html",
+            include_str!("../../templates/email/treasurer.html"),
+        ),
+        (
+            "treasurer.txt",
+            include_str!("../../templates/email/treasurer.txt"),
+        ),
+        (
+            "confirmation.html",
+            include_str!("../../templates/email/confirmation.html"),
+        ),
+        (
+            "confirmation.txt",
+            include_str!("../../templates/email/confirmation.txt"),
+        ),
+        (
+            "notification.html",
+            include_str!("../../templates/email/notification.html"),
+        ),
+        (
+            "notification.txt",
+            include_str!("../../templates/email/notification.txt"),
+        ),
+        (
+            "outbound.html",
+            include_str!("../../templates/email/outbound.html"),
+        ),
+        (
+            "outbound.txt",
+            include_str!("../../templates/email/outbound.txt"),
+        ),
+        // Included by both the HTML and the plain text email, escaped only in the former
+        (
+            "duplicate.html",
+            include_str!("../../templates/email/duplicate.jinja"),
+        ),
+        (
+            "duplicate.txt",
+            include_str!("../../templates/email/duplicate.jinja"),
+        ),
+    ];
+    for (name, source) in templates {
+        env.add_template(name, source)
+            .unwrap_or_else(|e| panic!("invalid email template {name}: {e}"));
+    }
+
+    env
+});
+
+/// Formats an amount of cents as euros, e.g. `1234` as `12,34 €`
+fn euros(cents: i64) -> String {
+    let sign = if cents < 0 { "-" } else { "" };
+    let cents = cents.unsigned_abs();
+    format!("{sign}{},{:02} €", cents / 100, cents % 100)
+}
+
+/// Formats a `YYYY-MM-DD` date the Finnish way, e.g. `2025-05-20` as `20.5.2025`
+fn finnish_date(date: String) -> String {
+    chrono::NaiveDate::parse_from_str(&date, "%Y-%m-%d")
+        .map(|date| date.format("%-d.%-m.%Y").to_string())
+        .unwrap_or(date)
+}
+
+/// The values available to the email templates
+#[derive(Serialize)]
+struct Context<'a> {
+    invoice: &'a Invoice,
+    /// The sum of the rows in cents
+    total: i64,
+    processing_days: u32,
+    /// The URL of the job status, if the public URL of the service is configured
+    status_url: Option<String>,
+    /// The new state of the invoice in notifications
+    state: Option<&'a InvoiceState>,
+    unsubscribe_url: Option<&'a str>,
+}
+
+impl<'a> Context<'a> {
+    fn new(invoice: &'a Invoice) -> Self {
+        Self {
+            invoice,
+            total: invoice
+                .rows
+                .iter()
+                .map(|row| i64::from(row.unit_price))
+                .sum(),
+            processing_days: CONFIG.processing_time_days,
+            status_url: CONFIG
+                .public_url
+                .as_ref()
+                .map(|url| format!("{}/jobs/{}", url.trim_end_matches('/'), invoice.id)),
+            state: None,
+            unsubscribe_url: None,
+        }
+    }
+}
+
+/// The HTML and plain text bodies of an email
+pub struct Body {
+    pub html: String,
+    pub text: String,
+}
+
+/// The values available to the email sent with an outbound invoice
+#[derive(Serialize)]
+struct OutboundContext<'a> {
+    invoice: &'a OutboundInvoice,
+    /// The sum of the rows in cents
+    total: i64,
+}
+
+fn render(name: &str, context: &impl serde::Serialize) -> Result<Body, Error> {
+    let render = |extension: &str| {
+        TEMPLATES
+            .get_template(&format!("{name}.{extension}"))
+            .and_then(|template| template.render(context))
+            .map_err(|e| Error::EmailTemplate(format!("{name}.{extension}: {e}")))
+    };
+
+    Ok(Body {
+        html: render("html")?,
+        text: render("txt")?,
+    })
+}
+
+/// The email to the treasurer with the details of the invoice and the warnings about it
+pub fn treasurer(invoice: &Invoice) -> Result<Body, Error> {
+    render("treasurer", &Context::new(invoice))
+}
+
+/// The email confirming to the submitter that the invoice was received
+pub fn confirmation(invoice: &Invoice) -> Result<Body, Error> {
+    render("confirmation", &Context::new(invoice))
+}
+
+/// The email telling the submitter that the invoice moved to a new state
+pub fn notification(
+    invoice: &Invoice,
+    state: &InvoiceState,
+    unsubscribe_url: &str,
+) -> Result<Body, Error> {
+    let context = Context {
+        state: Some(state),
+        unsubscribe_url: Some(unsubscribe_url),
+        ..Context::new(invoice)
+    };
+    render("notification", &context)
+}
+
+/// The email sending an outbound invoice to the customer
+pub fn outbound(invoice: &OutboundInvoice) -> Result<Body, Error> {
+    let context = OutboundContext {
+        invoice,
+        total: invoice.total(),
+    };
+    render("outbound", &context)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::invoices::{InvoiceAttachment, InvoiceRow};
+    use crate::duplicates::{DuplicateReason, PossibleDuplicate};
+
+    fn test_invoice() -> Invoice {
+        let row = |product: &str, unit_price| InvoiceRow {
+            product: product.to_string(),
+            unit_price,
+            category: None,
+        };
+
+        Invoice {
+            id: "test-id".to_string(),
+            recipient_name: "Test <User>".to_string(),
+            subject: "Sitsit & snacks".to_string(),
+            attachment_descriptions: vec!["Receipt".to_string()],
+            rows: vec![row("Snacks", 1250), row("Drinks", 705)],
+            attachments: vec![InvoiceAttachment {
+                filename: "<kuitti>.jpg".to_string(),
+                bytes: vec![],
+            }],
+            ..Invoice::test_fixture()
+        }
+    }
+
+    #[test]
+    fn test_euros() {
+        assert_eq!(euros(1955), "19,55 €");
+        assert_eq!(euros(5), "0,05 €");
+        assert_eq!(euros(-100), "-1,00 €");
+    }
+
+    #[test]
+    fn test_confirmation_has_rows_and_total() {
+        let body = confirmation(&test_invoice()).unwrap();
+
+        for text in [&body.html, &body.text] {
+            assert!(text.contains("12,50 €"));
+            assert!(text.contains("7,05 €"));
+            assert!(text.contains("Yhteensä: 19,55 €") || text.contains("<th>19,55 €</th>"));
+            assert!(text.contains("test-id"));
+        }
+    }
+
+    #[test]
+    fn test_notification_describes_state() {
+        let state = InvoiceState::Paid {
+            date: "2025-05-20".to_string(),
+        };
+        let body =
+            notification(&test_invoice(), &state, "https://example.com/unsubscribe").unwrap();
+
+        for text in [&body.html, &body.text] {
+            assert!(text.contains("on maksettu"));
+            assert!(text.contains("20.5.2025"));
+            assert!(text.contains("19,55 €"));
+        }
+        assert!(body.text.contains("https://example.com/unsubscribe"));
+    }
+
+    #[test]
+    fn test_treasurer_shows_business_id() {
+        let mut invoice = test_invoice();
+        invoice.business_id = Some("0112038-9".to_string());
+        let body = treasurer(&invoice).unwrap();
+
+        for text in [&body.html, &body.text] {
+            assert!(text.contains("0112038-9"));
+            assert!(!text.contains("ALV-tunniste"));
+        }
+    }
+
+    #[test]
+    fn test_outbound_has_payment_details() {
+        let invoice: OutboundInvoice = serde_json::from_value(serde_json::json!({
+            "number": "1000",
+            "reference": "10003",
+            "issued_at": "2025-05-20T12:00:00+03:00",
+            "due_date": "2025-06-03",
+            "issuer": {
+                "name": "Tietokilta ry",
+                "business_id": null,
+                "address": null,
+                "bank_account_number": "FI21 1234 5600 0007 85",
+                "email": "rahastonhoitaja@tietokilta.fi"
+            },
+            "customer": {
+                "name": "Yritys Oy",
+                "address": { "street": "Testikatu 1", "city": "Helsinki", "zip": "00100" },
+                "email": "laskut@example.com"
+            },
+            "subject": "Sponsorointi",
+            "description": "",
+            "rows": [{ "product": "Pääyhteistyökumppanuus", "unit_price": 150000 }],
+            "sent_at": null
+        }))
+        .expect("Invalid test invoice");
+        let body = outbound(&invoice).unwrap();
+
+        for text in [&body.html, &body.text] {
+            assert!(text.contains("1500,00 €"));
+            assert!(text.contains("3.6.2025"));
+            assert!(text.contains("10003"));
+            assert!(text.contains("FI21 1234 5600 0007 85"));
+        }
+    }
+
+    #[test]
+    fn test_html_is_escaped_only_in_html_body() {
+        let mut invoice = test_invoice();
+        invoice.possible_duplicates = vec![PossibleDuplicate {
+            reason: DuplicateReason::IdenticalAttachment,
+            filename: Some("<kuitti>.jpg".to_string()),
+            previous_invoice_id: "previous-id".to_string(),
+            previous_filename: Some("receipt.jpg".to_string()),
+            previous_submitted_at: "2025-01-31T12:00:00Z".to_string(),
+        }];
+
+        let body = treasurer(&invoice).unwrap();
+        assert!(body.html.contains("Sitsit &amp; snacks"));
+        assert!(body
+            .html
+            .contains("Liite &lt;kuitti&gt;.jpg on sama kuin laskun previous-id"));
+        assert!(!body.html.contains("<kuitti>"));
+
+        assert!(body.text.contains("Sitsit & snacks"));
+        assert!(body
+            .text
+            .contains("- Liite <kuitti>.jpg on sama kuin laskun previous-id (2025-01-31)"));
+    }
+}
@@ -1,4 +1,10 @@
-use laskugeneraattori::{api, state, CONFIG};
+use laskugeneraattori::{
+    api, audit,
+    error::Error,
+    jobs::{JobQueue, JobView, MailView},
+    pdfgen::agenda::AgendaBuilder,
+    state, Command, CONFIG,
+};
 use std::net::SocketAddr;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
@@ -8,6 +14,90 @@ mod tests;
 #[tokio::main]
 async fn main() {
     dotenv::dotenv().ok();
+
+    match &CONFIG.command {
+        Some(Command::VerifyAuditLog) => {
+            match audit::verify(&CONFIG.audit_log) {
+                Ok(count) => println!("The audit log is intact ({count} entries)"),
+                Err(e) => {
+                    eprintln!("Failed to verify the audit log: {e}");
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Some(Command::ListOutbox) => {
+            let queue = JobQueue::new(CONFIG.job_dir.clone());
+            match queue
+                .outbox()
+                .and_then(|jobs| Ok((jobs, queue.mail_outbox()?)))
+            {
+                Ok((jobs, mails)) => {
+                    for job in jobs.into_iter().map(JobView::from) {
+                        println!(
+                            "{}\t{}\t{}\t{}\t{}",
+                            job.id,
+                            job.created_at,
+                            job.invoice.recipient_name,
+                            job.invoice.subject,
+                            job.last_error.unwrap_or_default()
+                        );
+                    }
+                    for mail in mails.into_iter().map(MailView::from) {
+                        println!(
+                            "{}\t{}\temail\t{}\t{}",
+                            mail.id,
+                            mail.created_at,
+                            serde_json::to_string(&mail.mail).unwrap_or_default(),
+                            mail.last_error.unwrap_or_default()
+                        );
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Failed to read the outbox: {e}");
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Some(Command::ResendOutbox { id }) => {
+            let queue = JobQueue::new(CONFIG.job_dir.clone());
+            let resent = match queue.resend(id) {
+                Err(Error::JobNotFound) => queue.resend_mail(id).map(|_| ()),
+                resent => resent.map(|_| ()),
+            };
+            match resent {
+                Ok(()) => println!("Queued {id} to be sent again"),
+                Err(e) => {
+                    eprintln!("Failed to resend {id}: {e}");
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Some(Command::Agenda { meeting, output }) => {
+            let written = JobQueue::new(CONFIG.job_dir.clone())
+                .pending()
+                .and_then(|pending| {
+                    AgendaBuilder::new(pending.iter().map(|job| job.invoice()))
+                        .meeting(meeting.clone())
+                        .pdf_a(CONFIG.pdf_a)
+                        .build_pdf()
+                })
+                .and_then(|pdf| std::fs::write(output, pdf).map_err(Error::from));
+
+            match written {
+                Ok(()) => println!("Wrote the agenda to {}", output.display()),
+                Err(e) => {
+                    eprintln!("Failed to render the agenda: {e}");
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        None => {}
+    }
+
     tracing_subscriber::registry()
         .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| {
             "laskugeneraattori=debug,tower_http=debug,axum::rejection=trace".into()
@@ -1,214 +0,0 @@
-use crate::error::Error;
-use std::collections::BTreeMap;
-
-use lopdf::{Document, Object, ObjectId};
-
-// Mostly copied from https://github.com/J-F-Liu/lopdf/blob/master/README.md merge example
-pub fn merge_pdf(documents: Vec<Vec<u8>>) -> Result<Vec<u8>, Error> {
-    let documents = documents
-        .iter()
-        .flat_map(|f| Document::load_mem(f))
-        .collect::<Vec<Document>>();
-    let mut max_id = 1;
-    // Collect all Documents Objects grouped by a map
-    let mut documents_pages = BTreeMap::new();
-    let mut documents_objects = BTreeMap::new();
-    let mut document = Document::with_version("1.5");
-
-    for mut doc in documents {
-        doc.renumber_objects_with(max_id);
-
-        max_id = doc.max_id + 1;
-
-        documents_pages.extend(
-            doc.get_pages()
-                .into_values()
-                .map(|object_id| (object_id, doc.get_object(object_id).unwrap().to_owned())),
-        );
-        documents_objects.extend(doc.objects);
-    }
-
-    // "Catalog" and "Pages" are mandatory.
-    let mut catalog_object: Option<(ObjectId, Object)> = None;
-    let mut pages_object: Option<(ObjectId, Object)> = None;
-
-    // Process all objects except "Page" type
-    for (object_id, object) in documents_objects.iter() {
-        // We have to ignore "Page" (as are processed later), "Outlines" and "Outline" objects.
-        // All other objects should be collected and inserted into the main Document.
-        match object.type_name().unwrap_or(b"") {
-            b"Catalog" => {
-                // Collect a first "Catalog" object and use it for the future "Pages".
-                catalog_object = Some((
-                    if let Some((id, _)) = catalog_object {
-                        id
-                    } else {
-                        *object_id
-                    },
-                    object.clone(),
-                ));
-            }
-            b"Pages" => {
-                // Collect and update a first "Pages" object and use it for the future "Catalog"
-                // We have also to merge all dictionaries of the old and the new "Pages" object
-                if let Ok(dictionary) = object.as_dict() {
-                    let mut dictionary = dictionary.clone();
-                    if let Some((_, ref object)) = pages_object {
-                        if let Ok(old_dictionary) = object.as_dict() {
-                            dictionary.extend(old_dictionary);
-                        }
-                    }
-
-                    pages_object = Some((
-                        if let Some((id, _)) = pages_object {
-                            id
-                        } else {
-                            *object_id
-                        },
-                        Object::Dictionary(dictionary),
-                    ));
-                }
-            }
-            b"Page" => {}     // Ignored, processed later and separately
-            b"Outlines" => {} // Ignored, not supported yet
-            b"Outline" => {}  // Ignored, not supported yet
-            _ => {
-                document.objects.insert(*object_id, object.clone());
-            }
-        }
-    }
-
-    let catalog_object = catalog_object.expect("Catalog not found when merging PDF");
-    let pages_object = pages_object.expect("Pages not found when merging PDF");
-
-    // Iterate over all "Page" objects and collect into the parent "Pages" created before
-    for (object_id, object) in documents_pages.iter() {
-        if let Ok(dictionary) = object.as_dict() {
-            let mut dictionary = dictionary.clone();
-            dictionary.set("Parent", pages_object.0);
-
-            document
-                .objects
-                .insert(*object_id, Object::Dictionary(dictionary));
-        }
-    }
-
-    // Build a new "Pages" with updated fields
-    if let Ok(dictionary) = pages_object.1.as_dict() {
-        let mut dictionary = dictionary.clone();
-
-        // Set new pages count
-        dictionary.set("Count", documents_pages.len() as u32);
-
-        // Set new "Kids" list (collected from documents pages) for "Pages"
-        dictionary.set(
-            "Kids",
-            documents_pages
-                .into_keys()
-                .map(Object::Reference)
-                .collect::<Vec<_>>(),
-        );
-
-        document
-            .objects
-            .insert(pages_object.0, Object::Dictionary(dictionary));
-    }
-
-    // Build a new "Catalog" with updated fields
-    if let Ok(dictionary) = catalog_object.1.as_dict() {
-        let mut dictionary = dictionary.clone();
-        dictionary.set("Pages", pages_object.0);
-        dictionary.remove(b"Outlines"); // Outlines not supported in merged PDFs
-
-        document
-            .objects
-            .insert(catalog_object.0, Object::Dictionary(dictionary));
-    }
-
-    document.trailer.set("Root", catalog_object.0);
-
-    // Update the max internal ID as wasn't updated before due to direct objects insertion
-    document.max_id = document.objects.len() as u32;
-
-    // Reorder all new Document objects
-    document.renumber_objects();
-
-    // Set any Bookmarks to the First child if they are not set to a page
-    document.adjust_zero_pages();
-
-    document.compress();
-
-    let mut buffer = Vec::new();
-    document
-        .save_to(&mut buffer)
-        .expect("Error saving merged PDF");
-    Ok(buffer)
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::fs;
-
-    // Helper function for testing PDFs
-    // Expects the merged PDF (with one page from sample pdf) to have `expected_pages` pages and contain `expected_text`
-    fn test_pdf(filename: &str, expected_pages: usize, expected_text: &str) {
-        // Load test PDFs
-        let test_pdf = fs::read("testdata/test.pdf").expect("Failed to read test.pdf");
-        let http_header_pdf = fs::read(filename).expect("Failed to read PDF file");
-
-        // Merge the PDFs
-        let merged_pdf = merge_pdf(vec![test_pdf, http_header_pdf]).expect("Failed to merge PDFs");
-
-        // Load the merged PDF to verify
-        let document = Document::load_mem(&merged_pdf).expect("Failed to load merged PDF");
-
-        // Check that the merged PDF has correct number of pages
-        let pages = document.get_pages();
-        assert_eq!(
-            pages.len(),
-            expected_pages,
-            "Merged PDF should have {} pages",
-            expected_pages
-        );
-
-        // Extract text from the PDF to verify content
-        let mut found_text = false;
-        for (page_num, _) in pages.iter() {
-            if let Ok(text) = document.extract_text(&[*page_num]) {
-                if text.contains(expected_text) {
-                    found_text = true;
-                    break;
-                }
-            }
-        }
-
-        assert!(found_text, "Merged PDF should contain '{}'", expected_text);
-    }
-
-    // Sample test with a normal PDF
-    #[test]
-    fn test_merge_simple_pdf() {
-        test_pdf("testdata/regression/normal.pdf", 2, "Sample pdf content");
-    }
-
-    // Regression test for PDFs that have HTTP headers before the PDF content
-    #[test]
-    fn test_merge_pdf_with_http_header() {
-        test_pdf(
-            "testdata/regression/http-header.pdf",
-            2,
-            "Sample pdf content",
-        );
-    }
-
-    // Regression test for PDFs that have extra whitespace after "stream" keyword
-    #[test]
-    fn test_merge_pdf_with_whitespace_stream() {
-        test_pdf(
-            "testdata/regression/whitespace-stream.pdf",
-            2,
-            "Sample pdf content",
-        );
-    }
-}
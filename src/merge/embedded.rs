@@ -0,0 +1,167 @@
+use crate::api::invoices::InvoiceAttachment;
+
+use lopdf::{dictionary, text_string, Dictionary, Document, Object, Stream};
+
+/// How an embedded file relates to the content of the merged document
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Relationship {
+    /// The original file the content was rendered from, e.g. an uploaded attachment
+    Source,
+    /// Machine-readable data of the content, e.g. the invoice as JSON
+    Data,
+}
+
+impl Relationship {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Source => "Source",
+            Self::Data => "Data",
+        }
+    }
+}
+
+/// A file carried inside the merged document as an embedded file attachment
+#[derive(Clone, Debug)]
+pub struct EmbeddedFile {
+    pub file: InvoiceAttachment,
+    pub description: String,
+    pub relationship: Relationship,
+}
+
+impl EmbeddedFile {
+    pub fn new(
+        file: InvoiceAttachment,
+        description: impl Into<String>,
+        relationship: Relationship,
+    ) -> Self {
+        Self {
+            file,
+            description: description.into(),
+            relationship,
+        }
+    }
+}
+
+fn mime_type(filename: &str) -> &'static str {
+    let extension = filename.rsplit_once('.').map(|(_, ext)| ext.to_lowercase());
+
+    match extension.as_deref() {
+        Some("pdf") => "application/pdf",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("png") => "image/png",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("json") => "application/json",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Adds the files to the document and lists them in the `EmbeddedFiles` name tree and the `AF`
+/// (associated files) array of the catalog, as required by PDF/A-3.
+pub(super) fn embed(document: &mut Document, catalog: &mut Dictionary, files: Vec<EmbeddedFile>) {
+    if files.is_empty() {
+        return;
+    }
+
+    let now = Object::from(time::OffsetDateTime::now_utc());
+    let mut names = Vec::new();
+    let mut specs: Vec<Object> = Vec::new();
+
+    for (i, embedded) in files.into_iter().enumerate() {
+        let InvoiceAttachment { filename, bytes } = embedded.file;
+
+        let stream_id = document.add_object(Stream::new(
+            dictionary! {
+                "Type" => "EmbeddedFile",
+                "Subtype" => mime_type(&filename),
+                "Params" => dictionary! {
+                    "Size" => bytes.len() as i64,
+                    "ModDate" => now.clone(),
+                },
+            },
+            bytes,
+        ));
+
+        // F is a byte string, which readers don't decode consistently, so only the text string
+        // UF carries the exact filename
+        let ascii_filename = filename
+            .chars()
+            .map(|c| if c.is_ascii() { c } else { '_' })
+            .collect::<String>();
+
+        let spec_id = document.add_object(dictionary! {
+            "Type" => "Filespec",
+            "F" => Object::string_literal(ascii_filename),
+            "UF" => text_string(&filename),
+            "Desc" => text_string(&embedded.description),
+            "AFRelationship" => embedded.relationship.name(),
+            "EF" => dictionary! {
+                "F" => stream_id,
+                "UF" => stream_id,
+            },
+        });
+
+        // The keys of a name tree must be unique and sorted, while filenames may be neither
+        names.push(Object::string_literal(format!("{i:04}")));
+        names.push(spec_id.into());
+        specs.push(spec_id.into());
+    }
+
+    let embedded_files = dictionary! { "Names" => names };
+
+    // Keep the other name trees of the catalog, e.g. named destinations
+    let tree_id = catalog.get(b"Names").and_then(Object::as_reference).ok();
+    match tree_id.and_then(|id| document.get_dictionary_mut(id).ok()) {
+        Some(tree) => tree.set("EmbeddedFiles", embedded_files),
+        None => {
+            let mut tree = catalog
+                .get(b"Names")
+                .and_then(Object::as_dict)
+                .cloned()
+                .unwrap_or_default();
+            tree.set("EmbeddedFiles", embedded_files);
+            catalog.set("Names", tree);
+        }
+    }
+
+    catalog.set("AF", specs);
+}
+
+/// Returns the filenames, file specifications and streams of the files embedded in the document
+/// in the order of the name tree, for checking the output in tests
+#[cfg(test)]
+pub(crate) fn embedded_files(document: &Document) -> Vec<(String, &Dictionary, &Stream)> {
+    let names = document
+        .catalog()
+        .and_then(|catalog| catalog.get(b"Names"))
+        .and_then(|names| document.dereference(names))
+        .and_then(|(_, names)| names.as_dict())
+        .and_then(|names| names.get(b"EmbeddedFiles"))
+        .and_then(Object::as_dict)
+        .and_then(|tree| tree.get(b"Names"))
+        .and_then(Object::as_array)
+        .expect("Missing embedded files");
+
+    names
+        .chunks(2)
+        .map(|entry| {
+            let spec = entry[1]
+                .as_reference()
+                .and_then(|id| document.get_dictionary(id))
+                .expect("Missing file specification");
+            let filename = spec
+                .get(b"UF")
+                .and_then(lopdf::decode_text_string)
+                .expect("Missing filename");
+            let stream = spec
+                .get(b"EF")
+                .and_then(Object::as_dict)
+                .and_then(|ef| ef.get(b"F"))
+                .and_then(Object::as_reference)
+                .and_then(|id| document.get_object(id))
+                .and_then(Object::as_stream)
+                .expect("Missing embedded file stream");
+            (filename, spec, stream)
+        })
+        .collect()
+}
@@ -0,0 +1,79 @@
+use lopdf::{dictionary, text_string, Document, Object, ObjectId};
+
+/// The application that produced the merged document
+pub(super) const PRODUCER: &str = concat!(
+    "Laskugeneraattori ",
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("COMMIT_HASH"),
+    ")"
+);
+
+/// Sets the producer of the document in both the document information dictionary and the XMP
+/// metadata of the catalog, which PDF/A requires to match.
+///
+/// The other metadata, e.g. the title and the author, is set by Typst when rendering the invoice.
+pub(super) fn set_producer(document: &mut Document, catalog_id: ObjectId) {
+    let info_id = document
+        .trailer
+        .get(b"Info")
+        .and_then(Object::as_reference)
+        .ok()
+        .filter(|id| document.get_dictionary(*id).is_ok());
+
+    match info_id.and_then(|id| document.get_dictionary_mut(id).ok()) {
+        Some(info) => info.set("Producer", text_string(PRODUCER)),
+        None => {
+            let info_id = document.add_object(dictionary! {
+                "Producer" => text_string(PRODUCER),
+            });
+            document.trailer.set("Info", info_id);
+        }
+    }
+
+    let metadata = document
+        .get_dictionary(catalog_id)
+        .and_then(|catalog| catalog.get(b"Metadata"))
+        .and_then(Object::as_reference)
+        .and_then(|id| document.get_object_mut(id))
+        .and_then(Object::as_stream_mut);
+
+    let Ok(metadata) = metadata else {
+        return;
+    };
+
+    let Ok(xmp) = std::str::from_utf8(&metadata.content) else {
+        warn!("Failed to set the producer: XMP metadata is not valid UTF-8");
+        return;
+    };
+
+    let producer = PRODUCER
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+
+    let mut content = xmp.to_string();
+    if let Some((start, end)) = element_content(xmp, "pdf:Producer") {
+        content.replace_range(start..end, &producer);
+    } else if let Some(end) = xmp.find("</rdf:RDF>") {
+        content.insert_str(
+            end,
+            &format!(
+                "<rdf:Description rdf:about=\"\" xmlns:pdf=\"http://ns.adobe.com/pdf/1.3/\">\
+                 <pdf:Producer>{producer}</pdf:Producer></rdf:Description>"
+            ),
+        );
+    } else {
+        warn!("Failed to set the producer: XMP metadata has no RDF element");
+        return;
+    }
+
+    metadata.set_content(content.into_bytes());
+}
+
+/// Returns the range of the text content of the first element with the given name
+fn element_content(xml: &str, name: &str) -> Option<(usize, usize)> {
+    let start = xml.find(&format!("<{name}>"))? + name.len() + 2;
+    let end = start + xml[start..].find(&format!("</{name}>"))?;
+    Some((start, end))
+}
@@ -0,0 +1,834 @@
+use crate::api::invoices::InvoiceAttachment;
+use crate::error::Error;
+use std::collections::BTreeMap;
+
+use lopdf::{Document, Object, ObjectId};
+
+mod embedded;
+mod metadata;
+mod outline;
+
+#[cfg(test)]
+pub(crate) use embedded::embedded_files;
+pub use embedded::{EmbeddedFile, Relationship};
+pub use outline::Bookmark;
+
+/// A PDF file to be merged along with the bookmarks pointing to its pages
+pub struct MergeSource {
+    pub file: InvoiceAttachment,
+    pub bookmarks: Vec<Bookmark>,
+}
+
+impl From<InvoiceAttachment> for MergeSource {
+    fn from(file: InvoiceAttachment) -> Self {
+        Self {
+            file,
+            bookmarks: vec![],
+        }
+    }
+}
+
+/// Parses a PDF file, failing if it can't be read or contains no pages that could be merged.
+///
+/// Encrypted files are accepted as long as they can be opened without a password, e.g. when
+/// only an owner password restricting editing or printing has been set.
+pub fn load_pdf(file: &InvoiceAttachment) -> Result<Document, Error> {
+    let mut document = Document::load_mem(&file.bytes).map_err(|e| {
+        warn!("Failed to parse PDF {}: {e}", file.filename);
+        Error::InvalidPdf(file.filename.clone())
+    })?;
+
+    if document.is_encrypted() {
+        // lopdf decrypts the objects while loading if the user password is empty
+        if document.authenticate_password("").is_err() {
+            return Err(Error::EncryptedPdf(file.filename.clone()));
+        }
+
+        // Drop the encryption dictionary, as the merged document is written unencrypted
+        if let Some(Object::Reference(id)) = document.trailer.remove(b"Encrypt") {
+            document.objects.remove(&id);
+        }
+    }
+
+    if document.get_pages().is_empty() {
+        return Err(Error::InvalidPdf(file.filename.clone()));
+    }
+
+    Ok(document)
+}
+
+/// Logs the fonts of a document that aren't embedded in it, as the merged document can't then
+/// conform to PDF/A
+fn warn_unembedded_fonts(document: &Document) {
+    let font_name = |dict: &lopdf::Dictionary, key: &[u8]| {
+        dict.get(key)
+            .and_then(Object::as_name)
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .unwrap_or_default()
+    };
+
+    for dict in document.objects.values().filter_map(|o| o.as_dict().ok()) {
+        let unembedded = if dict.has_type(b"FontDescriptor") {
+            !["FontFile", "FontFile2", "FontFile3"]
+                .into_iter()
+                .any(|key| dict.has(key.as_bytes()))
+        } else {
+            // Simple fonts without a descriptor are one of the standard 14 fonts, which are
+            // never embedded. Composite and Type 3 fonts are checked through their descendants.
+            dict.has_type(b"Font")
+                && !dict.has(b"FontDescriptor")
+                && !matches!(
+                    dict.get(b"Subtype").and_then(Object::as_name),
+                    Ok(b"Type0" | b"Type3")
+                )
+        };
+
+        if unembedded {
+            let name = match dict.has_type(b"Font") {
+                true => font_name(dict, b"BaseFont"),
+                false => font_name(dict, b"FontName"),
+            };
+            warn!("Font {name} is not embedded in an attached PDF");
+        }
+    }
+}
+
+pub fn merge_pdf(documents: Vec<MergeSource>) -> Result<Vec<u8>, Error> {
+    merge_pdf_with_files(documents, vec![])
+}
+
+/// Merges the documents and embeds the files as attachments of the merged document
+// Mostly copied from https://github.com/J-F-Liu/lopdf/blob/master/README.md merge example
+pub fn merge_pdf_with_files(
+    documents: Vec<MergeSource>,
+    files: Vec<EmbeddedFile>,
+) -> Result<Vec<u8>, Error> {
+    let documents = documents
+        .into_iter()
+        .map(|source| Ok((load_pdf(&source.file)?, source.bookmarks)))
+        .collect::<Result<Vec<_>, Error>>()?;
+    let mut max_id = 1;
+    // Collect all Documents Objects grouped by a map
+    let mut documents_pages = BTreeMap::new();
+    let mut documents_objects = BTreeMap::new();
+    let mut documents_outlines = Vec::new();
+
+    // Don't downgrade the version, as e.g. PDF/A-3 is based on PDF 1.7
+    let version = documents
+        .iter()
+        .map(|(doc, _)| doc.version.as_str())
+        .max()
+        .unwrap_or("1.7");
+    let mut document = Document::with_version(version);
+
+    for (i, (mut doc, bookmarks)) in documents.into_iter().enumerate() {
+        doc.renumber_objects_with(max_id);
+
+        max_id = doc.max_id + 1;
+
+        // The first document is the invoice itself, keep its file identifier and document
+        // information, which are required for e.g. PDF/A
+        if i == 0 {
+            for key in [&b"ID"[..], b"Info"] {
+                if let Ok(value) = doc.trailer.get(key) {
+                    document.trailer.set(key, value.clone());
+                }
+            }
+        } else {
+            warn_unembedded_fonts(&doc);
+        }
+
+        documents_outlines.push(outline::DocumentOutline::new(&doc, bookmarks));
+
+        for object_id in doc.get_pages().into_values() {
+            let page = doc
+                .get_object(object_id)
+                .map_err(|e| Error::PdfMerge(format!("failed to read page: {e}")))?;
+            documents_pages.insert(object_id, page.to_owned());
+        }
+        documents_objects.extend(doc.objects);
+    }
+
+    // "Catalog" and "Pages" are mandatory.
+    let mut catalog_object: Option<(ObjectId, Object)> = None;
+    let mut pages_object: Option<(ObjectId, Object)> = None;
+
+    // Process all objects except "Page" type
+    for (object_id, object) in documents_objects.iter() {
+        // We have to ignore "Page" (as are processed later) and "Outlines" objects, as a new
+        // outline is built for the merged document. All other objects should be collected and
+        // inserted into the main Document.
+        match object.type_name().unwrap_or(b"") {
+            b"Catalog" => {
+                // Keep the first "Catalog" object along with e.g. its metadata and output
+                // intents, and use it for the future "Pages".
+                if catalog_object.is_none() {
+                    catalog_object = Some((*object_id, object.clone()));
+                }
+            }
+            b"Pages" => {
+                // Collect and update a first "Pages" object and use it for the future "Catalog"
+                // We have also to merge all dictionaries of the old and the new "Pages" object
+                if let Ok(dictionary) = object.as_dict() {
+                    let mut dictionary = dictionary.clone();
+                    if let Some((_, ref object)) = pages_object {
+                        if let Ok(old_dictionary) = object.as_dict() {
+                            dictionary.extend(old_dictionary);
+                        }
+                    }
+
+                    pages_object = Some((
+                        if let Some((id, _)) = pages_object {
+                            id
+                        } else {
+                            *object_id
+                        },
+                        Object::Dictionary(dictionary),
+                    ));
+                }
+            }
+            b"Page" => {}     // Ignored, processed later and separately
+            b"Outlines" => {} // Ignored, replaced by the outline of the merged document
+            _ => {
+                document.objects.insert(*object_id, object.clone());
+            }
+        }
+    }
+
+    let catalog_object =
+        catalog_object.ok_or_else(|| Error::PdfMerge("Catalog not found".to_string()))?;
+    let pages_object =
+        pages_object.ok_or_else(|| Error::PdfMerge("Pages not found".to_string()))?;
+
+    // Iterate over all "Page" objects and collect into the parent "Pages" created before
+    for (object_id, object) in documents_pages.iter() {
+        if let Ok(dictionary) = object.as_dict() {
+            let mut dictionary = dictionary.clone();
+            dictionary.set("Parent", pages_object.0);
+
+            document
+                .objects
+                .insert(*object_id, Object::Dictionary(dictionary));
+        }
+    }
+
+    // Build a new "Pages" with updated fields
+    if let Ok(dictionary) = pages_object.1.as_dict() {
+        let mut dictionary = dictionary.clone();
+
+        // Set new pages count
+        dictionary.set("Count", documents_pages.len() as u32);
+
+        // Set new "Kids" list (collected from documents pages) for "Pages"
+        dictionary.set(
+            "Kids",
+            documents_pages
+                .into_keys()
+                .map(Object::Reference)
+                .collect::<Vec<_>>(),
+        );
+
+        document
+            .objects
+            .insert(pages_object.0, Object::Dictionary(dictionary));
+    }
+
+    // Build the outline with the bookmarks of all documents
+    document.max_id = max_id;
+    let outline = outline::build(&mut document, documents_outlines);
+
+    // Build a new "Catalog" with updated fields
+    if let Ok(dictionary) = catalog_object.1.as_dict() {
+        let mut dictionary = dictionary.clone();
+        dictionary.set("Pages", pages_object.0);
+        match outline {
+            Some(outline) => {
+                dictionary.set("Outlines", outline);
+                dictionary.set("PageMode", "UseOutlines");
+            }
+            None => {
+                dictionary.remove(b"Outlines");
+            }
+        }
+        embedded::embed(&mut document, &mut dictionary, files);
+
+        document
+            .objects
+            .insert(catalog_object.0, Object::Dictionary(dictionary));
+    }
+
+    document.trailer.set("Root", catalog_object.0);
+    metadata::set_producer(&mut document, catalog_object.0);
+
+    // PDF/A requires the XMP metadata to be readable without decompressing it
+    if let Ok(Object::Stream(metadata)) = document
+        .get_dictionary(catalog_object.0)
+        .and_then(|catalog| catalog.get(b"Metadata"))
+        .and_then(Object::as_reference)
+        .and_then(|id| document.get_object_mut(id))
+    {
+        metadata.allows_compression = false;
+    }
+
+    // Update the max internal ID as wasn't updated before due to direct objects insertion
+    document.max_id = document.objects.len() as u32;
+
+    // Reorder all new Document objects
+    document.renumber_objects();
+
+    // Set any Bookmarks to the First child if they are not set to a page
+    document.adjust_zero_pages();
+
+    document.compress();
+
+    let mut buffer = Vec::new();
+    document.save_to(&mut buffer)?;
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn read_attachment(filename: &str) -> InvoiceAttachment {
+        InvoiceAttachment {
+            filename: filename.to_string(),
+            bytes: fs::read(filename).expect("Failed to read PDF file"),
+        }
+    }
+
+    // A small xorshift generator, so that the fuzz tests are reproducible
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn bytes(&mut self, len: usize) -> Vec<u8> {
+            (0..len).map(|_| self.next() as u8).collect()
+        }
+    }
+
+    // Merges the given bytes as an attachment, expecting an error or a valid PDF but no panics
+    fn assert_merge_does_not_panic(bytes: Vec<u8>) {
+        let test_pdf = read_attachment("testdata/test.pdf");
+        let attachment = InvoiceAttachment {
+            filename: "fuzz.pdf".to_string(),
+            bytes,
+        };
+
+        match merge_pdf(vec![test_pdf.into(), attachment.into()]) {
+            Ok(merged_pdf) => {
+                Document::load_mem(&merged_pdf).expect("Merged PDF should be valid");
+            }
+            Err(Error::InvalidPdf(_) | Error::EncryptedPdf(_) | Error::PdfMerge(_)) => {}
+            Err(e) => panic!("Unexpected error: {e}"),
+        }
+    }
+
+    // Returns the titles of the outline items of a PDF along with their nesting depth and the
+    // page numbers they point to
+    fn outline_items(document: &Document) -> Vec<(usize, String, u32)> {
+        fn walk(
+            document: &Document,
+            first: Option<ObjectId>,
+            depth: usize,
+            items: &mut Vec<(usize, String, u32)>,
+        ) {
+            let pages = document.get_pages();
+            let mut next = first;
+            while let Some(id) = next {
+                let item = document.get_dictionary(id).unwrap();
+                let title = lopdf::decode_text_string(item.get(b"Title").unwrap()).unwrap();
+                let page_id = item.get(b"Dest").unwrap().as_array().unwrap()[0]
+                    .as_reference()
+                    .unwrap();
+                let page = pages.iter().find(|(_, id)| **id == page_id).unwrap().0;
+                items.push((depth, title, *page));
+
+                let child = item.get(b"First").and_then(Object::as_reference).ok();
+                walk(document, child, depth + 1, items);
+                next = item.get(b"Next").and_then(Object::as_reference).ok();
+            }
+        }
+
+        let outlines = document
+            .catalog()
+            .and_then(|catalog| catalog.get(b"Outlines"))
+            .and_then(Object::as_reference)
+            .and_then(|id| document.get_dictionary(id))
+            .expect("Merged PDF should have an outline");
+
+        let mut items = Vec::new();
+        let first = outlines.get(b"First").and_then(Object::as_reference).ok();
+        walk(document, first, 0, &mut items);
+        items
+    }
+
+    // Helper function for testing PDFs
+    // Expects the merged PDF (with one page from sample pdf) to have `expected_pages` pages and contain `expected_text`
+    fn test_pdf(filename: &str, expected_pages: usize, expected_text: &str) {
+        // Load test PDFs
+        let test_pdf = read_attachment("testdata/test.pdf");
+        let http_header_pdf = read_attachment(filename);
+
+        // Merge the PDFs
+        let merged_pdf =
+            merge_pdf(vec![test_pdf.into(), http_header_pdf.into()]).expect("Failed to merge PDFs");
+
+        // Load the merged PDF to verify
+        let document = Document::load_mem(&merged_pdf).expect("Failed to load merged PDF");
+
+        // Check that the merged PDF has correct number of pages
+        let pages = document.get_pages();
+        assert_eq!(
+            pages.len(),
+            expected_pages,
+            "Merged PDF should have {} pages",
+            expected_pages
+        );
+
+        // Extract text from the PDF to verify content
+        let mut found_text = false;
+        for (page_num, _) in pages.iter() {
+            if let Ok(text) = document.extract_text(&[*page_num]) {
+                if text.contains(expected_text) {
+                    found_text = true;
+                    break;
+                }
+            }
+        }
+
+        assert!(found_text, "Merged PDF should contain '{}'", expected_text);
+    }
+
+    // Sample test with a normal PDF
+    #[test]
+    fn test_merge_simple_pdf() {
+        test_pdf("testdata/regression/normal.pdf", 2, "Sample pdf content");
+    }
+
+    // Regression test for PDFs that have HTTP headers before the PDF content
+    #[test]
+    fn test_merge_pdf_with_http_header() {
+        test_pdf(
+            "testdata/regression/http-header.pdf",
+            2,
+            "Sample pdf content",
+        );
+    }
+
+    // Regression test for PDFs that have extra whitespace after "stream" keyword
+    #[test]
+    fn test_merge_pdf_with_whitespace_stream() {
+        test_pdf(
+            "testdata/regression/whitespace-stream.pdf",
+            2,
+            "Sample pdf content",
+        );
+    }
+
+    // Regression test for corrupt PDFs being silently dropped from the merged document
+    #[test]
+    fn test_merge_corrupt_pdf_fails() {
+        let test_pdf = read_attachment("testdata/test.pdf");
+        let corrupt_pdf = read_attachment("testdata/regression/corrupt.pdf");
+
+        match merge_pdf(vec![test_pdf.into(), corrupt_pdf.into()]) {
+            Err(Error::InvalidPdf(filename)) => {
+                assert_eq!(filename, "testdata/regression/corrupt.pdf")
+            }
+            other => panic!("Expected InvalidPdf error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_merge_creates_bookmarks() {
+        let merged_pdf = merge_pdf(vec![
+            MergeSource {
+                file: read_attachment("testdata/test.pdf"),
+                bookmarks: vec![Bookmark::new("Lasku", 0)],
+            },
+            MergeSource {
+                file: read_attachment("testdata/regression/normal.pdf"),
+                bookmarks: vec![Bookmark::new("Kuitti äöå", 0)],
+            },
+        ])
+        .expect("Failed to merge PDFs");
+
+        let document = Document::load_mem(&merged_pdf).expect("Failed to load merged PDF");
+        assert_eq!(
+            outline_items(&document),
+            vec![
+                (0, "Lasku".to_string(), 1),
+                (0, "Kuitti äöå".to_string(), 2),
+                // The bookmark of normal.pdf itself
+                (1, "Sample pdf content".to_string(), 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge_nests_existing_bookmarks() {
+        let attachment = merge_pdf(vec![
+            MergeSource {
+                file: read_attachment("testdata/test.pdf"),
+                bookmarks: vec![Bookmark::new("First page", 0)],
+            },
+            MergeSource {
+                file: read_attachment("testdata/regression/normal.pdf"),
+                bookmarks: vec![Bookmark::new("Second page", 0)],
+            },
+        ])
+        .expect("Failed to merge PDFs");
+
+        let merged_pdf = merge_pdf(vec![
+            MergeSource {
+                file: read_attachment("testdata/test.pdf"),
+                bookmarks: vec![Bookmark::new("Lasku", 0)],
+            },
+            MergeSource {
+                file: InvoiceAttachment {
+                    filename: "attachment.pdf".to_string(),
+                    bytes: attachment,
+                },
+                bookmarks: vec![Bookmark::new("Liite", 0)],
+            },
+        ])
+        .expect("Failed to merge PDFs");
+
+        let document = Document::load_mem(&merged_pdf).expect("Failed to load merged PDF");
+        assert_eq!(
+            outline_items(&document),
+            vec![
+                (0, "Lasku".to_string(), 1),
+                (0, "Liite".to_string(), 2),
+                (1, "First page".to_string(), 2),
+                (1, "Second page".to_string(), 3),
+                (2, "Sample pdf content".to_string(), 3),
+            ]
+        );
+    }
+
+    // Regression test for PDFs that are encrypted with only an owner password
+    #[test]
+    fn test_merge_pdf_with_owner_password() {
+        test_pdf(
+            "testdata/regression/encrypted-owner-password.pdf",
+            2,
+            "Sample pdf content",
+        );
+    }
+
+    #[test]
+    fn test_merged_pdf_is_not_encrypted() {
+        let test_pdf = read_attachment("testdata/test.pdf");
+        let encrypted_pdf = read_attachment("testdata/regression/encrypted-owner-password.pdf");
+
+        let merged_pdf =
+            merge_pdf(vec![test_pdf.into(), encrypted_pdf.into()]).expect("Failed to merge PDFs");
+
+        let document = Document::load_mem(&merged_pdf).expect("Failed to load merged PDF");
+        assert!(!document.is_encrypted());
+    }
+
+    #[test]
+    fn test_merge_pdf_with_user_password_fails() {
+        let test_pdf = read_attachment("testdata/test.pdf");
+        let encrypted_pdf = read_attachment("testdata/regression/encrypted-user-password.pdf");
+
+        match merge_pdf(vec![test_pdf.into(), encrypted_pdf.into()]) {
+            Err(Error::EncryptedPdf(filename)) => {
+                assert_eq!(filename, "testdata/regression/encrypted-user-password.pdf")
+            }
+            other => panic!("Expected EncryptedPdf error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    // A single page PDF with the catalog entries and file identifier of a PDF/A document
+    fn archival_cover() -> InvoiceAttachment {
+        use lopdf::{dictionary, Stream};
+
+        let mut document = Document::with_version("1.7");
+        let pages_id = document.new_object_id();
+        let page_id = document.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+        });
+        document.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => vec![page_id.into()],
+                "Count" => 1,
+            }),
+        );
+
+        let metadata_id = document.add_object(Stream::new(
+            dictionary! { "Type" => "Metadata", "Subtype" => "XML" },
+            ARCHIVAL_XMP.to_vec(),
+        ));
+        let profile_id = document.add_object(Stream::new(dictionary! { "N" => 3 }, vec![0; 128]));
+        let names_id = document.add_object(dictionary! {
+            "Dests" => dictionary! {
+                "Names" => vec![Object::string_literal("invoice"), vec![page_id.into(), "Fit".into()].into()],
+            },
+        });
+        let catalog_id = document.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+            "Metadata" => metadata_id,
+            "Lang" => Object::string_literal("fi"),
+            "Names" => names_id,
+            "OutputIntents" => vec![dictionary! {
+                "Type" => "OutputIntent",
+                "S" => "GTS_PDFA1",
+                "OutputConditionIdentifier" => Object::string_literal("sRGB"),
+                "DestOutputProfile" => profile_id,
+            }.into()],
+        });
+        let info_id = document.add_object(dictionary! {
+            "Title" => lopdf::text_string("Lasku"),
+            "Subject" => lopdf::text_string("Kahvit"),
+        });
+        document.trailer.set("Root", catalog_id);
+        document.trailer.set("Info", info_id);
+        document.trailer.set(
+            "ID",
+            vec![
+                Object::string_literal(ARCHIVAL_ID),
+                Object::string_literal(ARCHIVAL_ID),
+            ],
+        );
+
+        let mut bytes = Vec::new();
+        document.save_to(&mut bytes).unwrap();
+        InvoiceAttachment {
+            filename: "invoice.pdf".to_string(),
+            bytes,
+        }
+    }
+
+    const ARCHIVAL_ID: &[u8] = b"0123456789abcdef";
+    const ARCHIVAL_XMP: &[u8] = b"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"><rdf:RDF>\
+        <rdf:Description><pdfaid:part>3</pdfaid:part><pdfaid:conformance>B</pdfaid:conformance>\
+        </rdf:Description></rdf:RDF></x:xmpmeta>";
+
+    // The merged document must keep what makes the invoice itself a PDF/A document
+    #[test]
+    fn test_merge_keeps_archival_metadata() {
+        let merged_pdf = merge_pdf(vec![
+            archival_cover().into(),
+            read_attachment("testdata/test.pdf").into(),
+            read_attachment("testdata/regression/normal.pdf").into(),
+        ])
+        .expect("Failed to merge PDFs");
+
+        assert!(merged_pdf.starts_with(b"%PDF-1.7"));
+
+        let document = Document::load_mem(&merged_pdf).expect("Failed to load merged PDF");
+        assert_eq!(document.get_pages().len(), 3);
+
+        let id = document
+            .trailer
+            .get(b"ID")
+            .and_then(Object::as_array)
+            .unwrap();
+        assert_eq!(id[0].as_str().unwrap(), ARCHIVAL_ID);
+
+        let catalog = document.catalog().unwrap();
+        assert_eq!(
+            catalog.get(b"Lang").and_then(Object::as_str).unwrap(),
+            b"fi"
+        );
+
+        let intents = catalog
+            .get(b"OutputIntents")
+            .and_then(Object::as_array)
+            .unwrap();
+        let intent = intents[0].as_dict().unwrap();
+        assert_eq!(
+            intent.get(b"S").and_then(Object::as_name).unwrap(),
+            b"GTS_PDFA1"
+        );
+        let profile = intent.get(b"DestOutputProfile").unwrap().as_reference();
+        assert!(document.get_object(profile.unwrap()).is_ok());
+
+        let metadata = catalog
+            .get(b"Metadata")
+            .and_then(Object::as_reference)
+            .and_then(|id| document.get_object(id))
+            .and_then(Object::as_stream)
+            .unwrap();
+        assert!(
+            !metadata.dict.has(b"Filter"),
+            "XMP metadata must not be compressed"
+        );
+        let xmp = std::str::from_utf8(&metadata.content).unwrap();
+        assert!(xmp.contains("<pdfaid:part>3</pdfaid:part>"));
+    }
+
+    #[test]
+    fn test_merge_embeds_files() {
+        let receipt = read_attachment("testdata/regression/normal.pdf");
+        let photo = read_attachment("testdata/test.jpg");
+        let json = InvoiceAttachment {
+            filename: "invoice.json".to_string(),
+            bytes: br#"{"subject":"Test Invoice"}"#.to_vec(),
+        };
+
+        let merged_pdf = merge_pdf_with_files(
+            vec![
+                read_attachment("testdata/test.pdf").into(),
+                receipt.clone().into(),
+            ],
+            vec![
+                EmbeddedFile::new(receipt.clone(), "Receipt", Relationship::Source),
+                EmbeddedFile::new(photo.clone(), "Photo", Relationship::Source),
+                // Filenames are not unique
+                EmbeddedFile::new(photo.clone(), "Another photo", Relationship::Source),
+                EmbeddedFile::new(json.clone(), "Invoice data", Relationship::Data),
+            ],
+        )
+        .expect("Failed to merge PDFs");
+
+        let document = Document::load_mem(&merged_pdf).expect("Failed to load merged PDF");
+        assert_eq!(document.get_pages().len(), 2);
+
+        let files = embedded_files(&document)
+            .into_iter()
+            .map(|(filename, spec, stream)| {
+                let relationship = spec.get(b"AFRelationship").and_then(Object::as_name);
+                let contents = stream
+                    .decompressed_content()
+                    .unwrap_or_else(|_| stream.content.clone());
+                (filename, relationship.unwrap().to_vec(), contents)
+            })
+            .collect::<Vec<_>>();
+        let associated = document
+            .catalog()
+            .and_then(|catalog| catalog.get(b"AF"))
+            .and_then(Object::as_array)
+            .unwrap();
+        assert_eq!(associated.len(), files.len());
+        assert_eq!(
+            files,
+            vec![
+                (receipt.filename, b"Source".to_vec(), receipt.bytes),
+                (
+                    photo.filename.clone(),
+                    b"Source".to_vec(),
+                    photo.bytes.clone()
+                ),
+                (photo.filename, b"Source".to_vec(), photo.bytes),
+                (json.filename, b"Data".to_vec(), json.bytes),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge_embeds_files_with_existing_names() {
+        let merged_pdf = merge_pdf_with_files(
+            vec![archival_cover().into()],
+            vec![EmbeddedFile::new(
+                read_attachment("testdata/test.jpg"),
+                "Kuva äöå",
+                Relationship::Source,
+            )],
+        )
+        .expect("Failed to merge PDFs");
+
+        let document = Document::load_mem(&merged_pdf).expect("Failed to load merged PDF");
+        let catalog = document.catalog().unwrap();
+        let names = catalog
+            .get(b"Names")
+            .and_then(|names| document.dereference(names))
+            .and_then(|(_, names)| names.as_dict())
+            .unwrap();
+        assert!(names.has(b"Dests"), "Named destinations should be kept");
+        assert_eq!(embedded_files(&document).len(), 1);
+    }
+
+    #[test]
+    fn test_merge_sets_producer() {
+        let merged_pdf = merge_pdf(vec![
+            archival_cover().into(),
+            read_attachment("testdata/test.pdf").into(),
+        ])
+        .expect("Failed to merge PDFs");
+
+        let document = Document::load_mem(&merged_pdf).expect("Failed to load merged PDF");
+        let info = document
+            .trailer
+            .get(b"Info")
+            .and_then(Object::as_reference)
+            .and_then(|id| document.get_dictionary(id))
+            .expect("Merged PDF should have a document information dictionary");
+        assert_eq!(
+            lopdf::decode_text_string(info.get(b"Producer").unwrap()).unwrap(),
+            metadata::PRODUCER
+        );
+        // The rest of the information of the invoice is kept
+        assert_eq!(
+            lopdf::decode_text_string(info.get(b"Title").unwrap()).unwrap(),
+            "Lasku"
+        );
+        assert_eq!(
+            lopdf::decode_text_string(info.get(b"Subject").unwrap()).unwrap(),
+            "Kahvit"
+        );
+
+        let metadata = document
+            .catalog()
+            .and_then(|catalog| catalog.get(b"Metadata"))
+            .and_then(Object::as_reference)
+            .and_then(|id| document.get_object(id))
+            .and_then(Object::as_stream)
+            .unwrap();
+        let xmp = std::str::from_utf8(&metadata.content).unwrap();
+        assert!(xmp.contains(&format!(
+            "<pdf:Producer>{}</pdf:Producer>",
+            metadata::PRODUCER
+        )));
+        assert!(xmp.contains("<pdfaid:part>3</pdfaid:part>"));
+    }
+
+    #[test]
+    fn test_merge_random_bytes() {
+        let mut rng = XorShift(0x5eed);
+
+        for i in 0..256 {
+            let len = (rng.next() % 4096) as usize;
+            let mut bytes = rng.bytes(len);
+            if i % 2 == 0 {
+                // Get past the header check for half of the inputs
+                bytes.splice(0..0, b"%PDF-1.7\n".iter().copied());
+            }
+            assert_merge_does_not_panic(bytes);
+        }
+    }
+
+    #[test]
+    fn test_merge_mutated_pdf() {
+        let original = fs::read("testdata/regression/normal.pdf").expect("Failed to read PDF");
+        let mut rng = XorShift(0xf00d);
+
+        for i in 0..256 {
+            let mut bytes = original.clone();
+            for _ in 0..1 + rng.next() % 16 {
+                let index = rng.next() as usize % bytes.len();
+                bytes[index] = rng.next() as u8;
+            }
+            if i % 4 == 0 {
+                bytes.truncate(rng.next() as usize % bytes.len());
+            }
+            assert_merge_does_not_panic(bytes);
+        }
+    }
+}
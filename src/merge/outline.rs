@@ -0,0 +1,142 @@
+use std::collections::HashSet;
+
+use lopdf::{dictionary, text_string, Dictionary, Document, Object, ObjectId};
+
+/// A bookmark pointing to a page of one of the merged documents
+#[derive(Clone, Debug)]
+pub struct Bookmark {
+    pub title: String,
+    /// The zero-based index of the page within the document the bookmark belongs to
+    pub page: usize,
+}
+
+impl Bookmark {
+    pub fn new(title: impl Into<String>, page: usize) -> Self {
+        Self {
+            title: title.into(),
+            page,
+        }
+    }
+}
+
+/// The bookmarks of a single document, collected before its objects are merged
+pub(super) struct DocumentOutline {
+    /// The titles of the bookmarks along with the pages they point to
+    bookmarks: Vec<(String, ObjectId)>,
+    /// The first top-level item of the document's own outline, if it has one
+    existing: Option<ObjectId>,
+}
+
+impl DocumentOutline {
+    pub fn new(document: &Document, bookmarks: Vec<Bookmark>) -> Self {
+        let pages = document.get_pages();
+
+        let bookmarks = bookmarks
+            .into_iter()
+            .filter_map(|bookmark| match pages.get(&(bookmark.page as u32 + 1)) {
+                Some(page_id) => Some((bookmark.title, *page_id)),
+                None => {
+                    warn!(
+                        "Bookmark {} points to a nonexistent page {}",
+                        bookmark.title, bookmark.page
+                    );
+                    None
+                }
+            })
+            .collect();
+
+        let existing = document
+            .catalog()
+            .and_then(|catalog| catalog.get(b"Outlines"))
+            .and_then(Object::as_reference)
+            .and_then(|id| document.get_dictionary(id))
+            .and_then(|outlines| outlines.get(b"First"))
+            .and_then(Object::as_reference)
+            .ok();
+
+        Self {
+            bookmarks,
+            existing,
+        }
+    }
+}
+
+/// Builds the outline of the merged document and returns the id of its root.
+///
+/// Each document's own outline is nested (collapsed) under the first bookmark of the document.
+/// The items of the nested outlines must already be in `document`.
+pub(super) fn build(document: &mut Document, outlines: Vec<DocumentOutline>) -> Option<ObjectId> {
+    let root_id = document.new_object_id();
+    let mut items: Vec<(ObjectId, Dictionary)> = Vec::new();
+
+    for outline in outlines {
+        for (i, (title, page_id)) in outline.bookmarks.into_iter().enumerate() {
+            let id = document.new_object_id();
+            let mut item = dictionary! {
+                "Title" => text_string(&title),
+                "Parent" => root_id,
+                "Dest" => vec![page_id.into(), "Fit".into()],
+            };
+
+            if let (0, Some(first)) = (i, outline.existing) {
+                let (last, count) = adopt_children(document, id, first);
+                if count > 0 {
+                    item.set("First", first);
+                    item.set("Last", last);
+                    // A negative count means the item is closed by default
+                    item.set("Count", -count);
+                }
+            }
+
+            items.push((id, item));
+        }
+    }
+
+    let ids = items.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+    let (first, last) = (*ids.first()?, *ids.last()?);
+
+    for (i, (id, mut item)) in items.into_iter().enumerate() {
+        if i > 0 {
+            item.set("Prev", ids[i - 1]);
+        }
+        if let Some(next) = ids.get(i + 1) {
+            item.set("Next", *next);
+        }
+        document.objects.insert(id, Object::Dictionary(item));
+    }
+
+    document.objects.insert(
+        root_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Outlines",
+            "First" => first,
+            "Last" => last,
+            "Count" => ids.len() as i64,
+        }),
+    );
+
+    Some(root_id)
+}
+
+/// Moves the sibling chain starting from `first` under `parent`.
+/// Returns the last sibling and the number of siblings.
+fn adopt_children(document: &mut Document, parent: ObjectId, first: ObjectId) -> (ObjectId, i64) {
+    let mut visited = HashSet::new();
+    let mut last = first;
+    let mut count = 0;
+    let mut next = Some(first);
+
+    // The chain is read from an untrusted document, so guard against cycles
+    while let Some(id) = next.filter(|id| visited.insert(*id)) {
+        let Ok(child) = document.get_dictionary_mut(id) else {
+            break;
+        };
+
+        child.set("Parent", parent);
+        last = id;
+        count += 1;
+        next = child.get(b"Next").and_then(Object::as_reference).ok();
+    }
+
+    (last, count)
+}
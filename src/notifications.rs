@@ -0,0 +1,174 @@
+use crate::api::invoices::Invoice;
+use crate::error::Error;
+use crate::jobs::InvoiceState;
+use crate::mailgun::MailgunClient;
+use crate::util::{hmac_sha256_hex, read_lines};
+use crate::CONFIG;
+
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashSet,
+    fs::OpenOptions,
+    io::Write,
+    path::PathBuf,
+    sync::{LazyLock, Mutex, PoisonError},
+};
+
+/// The submitters who don't want notifications, stored in the file given by `UNSUBSCRIBE_LIST`
+pub static UNSUBSCRIBED: LazyLock<UnsubscribeList> =
+    LazyLock::new(|| UnsubscribeList::new(CONFIG.unsubscribe_list.clone()));
+
+fn normalize(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+/// The token of the unsubscribe link of the email address, or `None` if no secret is
+/// configured
+pub fn unsubscribe_token(email: &str) -> Option<String> {
+    let secret = CONFIG.notification_secret.as_ref()?;
+    Some(hmac_sha256_hex(
+        secret.as_bytes(),
+        &[b"unsubscribe:".as_slice(), normalize(email).as_bytes()],
+    ))
+}
+
+/// Checks the token of an unsubscribe link
+pub fn verify_unsubscribe_token(email: &str, token: &str) -> bool {
+    // Compare the digests so that the time taken doesn't reveal the token
+    unsubscribe_token(email).is_some_and(|expected| {
+        Sha256::digest(expected.as_bytes()) == Sha256::digest(token.as_bytes())
+    })
+}
+
+/// The link the submitter can use to stop the notifications, if the public URL of the service
+/// and the notification secret are configured
+pub fn unsubscribe_url(email: &str) -> Option<String> {
+    let url = CONFIG.public_url.as_ref()?;
+    let token = unsubscribe_token(email)?;
+
+    Some(unsubscribe_link(url, email, &token))
+}
+
+fn unsubscribe_link(public_url: &str, email: &str, token: &str) -> String {
+    let query = form_urlencoded::Serializer::new(String::new())
+        .append_pair("email", &normalize(email))
+        .append_pair("token", token)
+        .finish();
+
+    format!("{}/unsubscribe?{query}", public_url.trim_end_matches('/'))
+}
+
+/// The email addresses that have unsubscribed from the notifications, one per line
+pub struct UnsubscribeList {
+    path: PathBuf,
+    /// Read from the file on first use
+    emails: Mutex<Option<HashSet<String>>>,
+}
+
+impl UnsubscribeList {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            emails: Mutex::new(None),
+        }
+    }
+
+    fn read_emails(&self) -> Result<HashSet<String>, Error> {
+        Ok(read_lines(&self.path)?
+            .iter()
+            .map(|line| normalize(line))
+            .collect())
+    }
+
+    fn with_emails<T>(
+        &self,
+        f: impl FnOnce(&mut HashSet<String>) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let mut emails = self.emails.lock().unwrap_or_else(PoisonError::into_inner);
+        if emails.is_none() {
+            *emails = Some(self.read_emails()?);
+        }
+
+        f(emails.get_or_insert_with(HashSet::new))
+    }
+
+    pub fn contains(&self, email: &str) -> Result<bool, Error> {
+        self.with_emails(|emails| Ok(emails.contains(&normalize(email))))
+    }
+
+    /// Adds the email address to the list. Adding an address twice does nothing.
+    pub fn add(&self, email: &str) -> Result<(), Error> {
+        self.with_emails(|emails| {
+            let email = normalize(email);
+            if emails.contains(&email) {
+                return Ok(());
+            }
+
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?
+                .write_all(format!("{email}\n").as_bytes())?;
+
+            emails.insert(email);
+            Ok(())
+        })
+    }
+}
+
+/// Tells the submitter that the invoice moved to a new state. Returns whether the email was
+/// sent: nothing is sent to the submitters who have unsubscribed, or if the unsubscribe link
+/// can't be made because the public URL or the notification secret isn't configured.
+pub async fn notify(
+    client: &MailgunClient,
+    invoice: &Invoice,
+    state: &InvoiceState,
+) -> Result<bool, Error> {
+    let Some(unsubscribe_url) = unsubscribe_url(&invoice.recipient_email) else {
+        debug!("Not sending notifications as PUBLIC_URL or NOTIFICATION_SECRET isn't set");
+        return Ok(false);
+    };
+
+    let email = invoice.recipient_email.clone();
+    let unsubscribed = tokio::task::spawn_blocking(move || UNSUBSCRIBED.contains(&email)).await??;
+    if unsubscribed || *state == InvoiceState::Submitted {
+        return Ok(false);
+    }
+
+    client
+        .send_notification(invoice, state, &unsubscribe_url)
+        .await?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unsubscribe_list_is_persisted() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("unsubscribed.txt");
+
+        let list = UnsubscribeList::new(path.clone());
+        assert!(!list.contains("test@example.com").unwrap());
+        list.add("Test@Example.com ").unwrap();
+        list.add("test@example.com").unwrap();
+
+        let list = UnsubscribeList::new(path.clone());
+        assert!(list.contains("TEST@example.com").unwrap());
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "test@example.com\n");
+    }
+
+    #[test]
+    fn test_unsubscribe_link_is_encoded() {
+        assert_eq!(
+            unsubscribe_link(
+                "https://laskut.example.com/",
+                "First.Last+invoices@example.com",
+                "abc"
+            ),
+            "https://laskut.example.com/unsubscribe?email=first.last%2Binvoices%40example.com&token=abc"
+        );
+    }
+}
@@ -0,0 +1,419 @@
+use crate::api::invoices::{is_valid_business_id, is_valid_due_date, Address, InvoiceRow};
+use crate::bank;
+use crate::error::Error;
+use crate::jobs::{
+    vouchers::{VoucherCounter, VoucherNumbering},
+    JobQueue,
+};
+use crate::local_time;
+use crate::pdfgen::outbound::OutboundBuilder;
+use crate::state::State;
+use crate::CONFIG;
+
+use axum::{
+    extract::{FromRef, OptionalFromRequestParts},
+    http::request::Parts,
+};
+use chrono::Datelike;
+use garde::Validate;
+use iban::Iban;
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::ErrorKind,
+    path::PathBuf,
+    sync::{Arc, LazyLock, Mutex, PoisonError},
+};
+use utoipa::ToSchema;
+
+/// Held while issuing an invoice, so that no two invoices get the same number
+static ISSUING: LazyLock<Mutex<()>> = LazyLock::new(|| Mutex::new(()));
+
+/// The guild issuing the outbound invoices, given by the `ISSUER_*` options
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct Issuer {
+    pub name: String,
+    /// The business ID ("Y-tunnus")
+    pub business_id: Option<String>,
+    /// The postal address on a single line
+    pub address: Option<String>,
+    /// The IBAN the invoices are paid to
+    pub bank_account_number: String,
+    /// The BIC of the bank, derived for Finnish accounts
+    #[serde(default)]
+    pub bic: Option<String>,
+    /// Where the replies of the customers are sent
+    pub email: Option<String>,
+}
+
+/// The organisation an outbound invoice is issued to
+#[derive(Clone, Debug, Serialize, Deserialize, Validate, ToSchema)]
+pub struct Customer {
+    /// The name of the customer, at least 1 character and at most 128 characters long
+    #[garde(length(chars, min = 1, max = 128))]
+    pub name: String,
+    /// The business ID ("Y-tunnus") of the customer, e.g. `1234567-1`
+    #[garde(custom(is_valid_business_id))]
+    #[serde(default)]
+    pub business_id: Option<String>,
+    /// The contact person at the customer, maximum length of 128 characters
+    #[garde(length(chars, max = 128))]
+    #[serde(default)]
+    pub contact: Option<String>,
+    /// The billing address of the customer
+    #[garde(dive)]
+    pub address: Address,
+    /// The email address the invoice is sent to, maximum length of 128 characters
+    #[garde(email, length(chars, max = 128))]
+    pub email: String,
+}
+
+/// Body for the request for issuing an outbound invoice
+#[derive(Clone, Debug, Deserialize, Validate, ToSchema)]
+pub struct OutboundInvoiceRequest {
+    #[garde(dive)]
+    pub customer: Customer,
+    /// The subject of the invoice, at least 1 character and at most 128 characters long
+    #[garde(length(chars, min = 1, max = 128))]
+    pub subject: String,
+    /// The description of the invoice, maximum length of 4096 characters
+    #[garde(length(chars, max = 4096))]
+    #[serde(default)]
+    pub description: String,
+    /// The rows of the invoice
+    #[garde(length(min = 1), dive)]
+    pub rows: Vec<InvoiceRow>,
+    /// The due date in `YYYY-MM-DD` format, which can't be in the past. By default the invoice
+    /// is due after the payment terms given by `PAYMENT_TERMS_DAYS`.
+    #[garde(custom(is_valid_due_date))]
+    #[serde(default)]
+    pub due_date: Option<String>,
+}
+
+/// An invoice issued by the guild to a customer
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct OutboundInvoice {
+    /// The invoice number, assigned in order without gaps
+    pub number: String,
+    /// The Finnish reference number ("viitenumero") of the payment, derived from the number
+    pub reference: String,
+    /// The time the invoice was issued in RFC 3339 format in Finnish time
+    pub issued_at: String,
+    /// The due date in `YYYY-MM-DD` format
+    pub due_date: String,
+    pub issuer: Issuer,
+    pub customer: Customer,
+    pub subject: String,
+    pub description: String,
+    pub rows: Vec<InvoiceRow>,
+    /// The time the invoice was last emailed to the customer in RFC 3339 format
+    pub sent_at: Option<String>,
+}
+
+impl OutboundInvoice {
+    /// The sum of the rows in cents
+    pub fn total(&self) -> i64 {
+        self.rows.iter().map(|row| i64::from(row.unit_price)).sum()
+    }
+
+    pub fn issued(&self) -> Option<chrono::DateTime<chrono::FixedOffset>> {
+        chrono::DateTime::parse_from_rfc3339(&self.issued_at).ok()
+    }
+
+    pub fn due(&self) -> Option<chrono::NaiveDate> {
+        chrono::NaiveDate::parse_from_str(&self.due_date, "%Y-%m-%d").ok()
+    }
+}
+
+/// The Finnish reference number of the base number: the digits followed by a check digit
+/// weighted 7, 3, 1 from the right, grouped in fives from the right. A reference number has at
+/// least four digits and no leading zeros, so the base has to be at least 100, which
+/// `OUTBOUND_NUMBER_START` ensures.
+pub fn reference_number(base: u32) -> String {
+    let digits = base.to_string();
+    let sum: u32 = digits
+        .chars()
+        .rev()
+        .filter_map(|digit| digit.to_digit(10))
+        .zip([7, 3, 1].into_iter().cycle())
+        .map(|(digit, weight)| digit * weight)
+        .sum();
+    let reference = format!("{digits}{}", (10 - sum % 10) % 10);
+
+    let chars: Vec<char> = reference.chars().collect();
+    let groups: Vec<String> = chars
+        .rchunks(5)
+        .rev()
+        .map(|group| group.iter().collect())
+        .collect();
+    groups.join(" ")
+}
+
+/// The outbound invoices stored in the directory given by `OUTBOUND_DIR`, each as
+/// `<number>.json` and `<number>.pdf`. Available only if the IBAN of the issuer is configured.
+#[derive(Clone)]
+pub struct OutboundInvoices {
+    dir: PathBuf,
+    issuer: Arc<Issuer>,
+    numbering: Arc<VoucherNumbering>,
+}
+
+impl OutboundInvoices {
+    pub fn new(dir: PathBuf, issuer: Issuer, start: u32) -> Self {
+        Self {
+            dir,
+            issuer: Arc::new(issuer),
+            numbering: Arc::new(VoucherNumbering {
+                prefix: String::new(),
+                start,
+                yearly_reset: false,
+            }),
+        }
+    }
+
+    pub fn from_config() -> Option<Self> {
+        let iban = CONFIG.issuer_iban.as_ref()?;
+        let bic = match iban.parse::<Iban>() {
+            Ok(parsed) => bank::finnish_bic(&parsed),
+            Err(e) => panic!("invalid ISSUER_IBAN: {e}"),
+        };
+        if let Err(e) = is_valid_business_id(&CONFIG.issuer_business_id, &()) {
+            panic!("invalid ISSUER_BUSINESS_ID: {e}");
+        }
+
+        let issuer = Issuer {
+            name: CONFIG.issuer_name.clone(),
+            business_id: CONFIG.issuer_business_id.clone(),
+            address: CONFIG.issuer_address.clone(),
+            bank_account_number: iban.clone(),
+            bic: bic.map(str::to_string),
+            email: CONFIG.issuer_email.clone(),
+        };
+        Some(Self::new(
+            CONFIG.outbound_dir.clone(),
+            issuer,
+            CONFIG.outbound_number_start,
+        ))
+    }
+
+    fn json_path(&self, number: &str) -> PathBuf {
+        self.dir.join(format!("{number}.json"))
+    }
+
+    fn pdf_path(&self, number: &str) -> PathBuf {
+        self.dir.join(format!("{number}.pdf"))
+    }
+
+    fn counter_path(&self) -> PathBuf {
+        self.dir.join("numbers.json")
+    }
+
+    fn save(&self, invoice: &OutboundInvoice) -> Result<(), Error> {
+        JobQueue::write_atomic(
+            &self.json_path(&invoice.number),
+            &serde_json::to_vec(invoice)?,
+        )
+    }
+
+    /// Issues the invoice with the next number and renders it, returning the invoice and the
+    /// PDF. As with vouchers, the number is taken only once the invoice has been stored. The
+    /// rendering is heavily blocking.
+    pub fn issue(
+        &self,
+        request: OutboundInvoiceRequest,
+    ) -> Result<(OutboundInvoice, Vec<u8>), Error> {
+        fs::create_dir_all(&self.dir)?;
+
+        let _issuing = ISSUING.lock().unwrap_or_else(PoisonError::into_inner);
+        let counter = VoucherCounter::load(&self.counter_path())?;
+        let mut taken = counter.clone();
+        let issued_at = local_time::now();
+        let number = taken.next(&self.numbering, issued_at.year());
+
+        let due_date = request.due_date.unwrap_or_else(|| {
+            (issued_at.date_naive() + chrono::Days::new(CONFIG.payment_terms_days.into()))
+                .format("%Y-%m-%d")
+                .to_string()
+        });
+        let invoice = OutboundInvoice {
+            number: self.numbering.format(issued_at.year(), number),
+            reference: reference_number(number),
+            issued_at: issued_at.to_rfc3339(),
+            due_date,
+            issuer: (*self.issuer).clone(),
+            customer: request.customer,
+            subject: request.subject,
+            description: request.description,
+            rows: request.rows,
+            sent_at: None,
+        };
+
+        let pdf = OutboundBuilder::new(&invoice)
+            .pdf_a(CONFIG.pdf_a)
+            .build_pdf()?;
+        JobQueue::write_atomic(&self.pdf_path(&invoice.number), &pdf)?;
+        JobQueue::write_atomic(&self.counter_path(), &serde_json::to_vec(&taken)?)?;
+
+        if let Err(e) = self.save(&invoice) {
+            if let Err(e) =
+                JobQueue::write_atomic(&self.counter_path(), &serde_json::to_vec(&counter)?)
+            {
+                error!("Failed to give back invoice number {}: {e}", invoice.number);
+            }
+            return Err(e);
+        }
+
+        Ok((invoice, pdf))
+    }
+
+    /// Returns the invoice with the number, or `None` if there is no such invoice
+    pub fn get(&self, number: &str) -> Result<Option<OutboundInvoice>, Error> {
+        // The number is used as a path, so only accept the numbers the service generates
+        if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+            return Ok(None);
+        }
+
+        match fs::read(self.json_path(number)) {
+            Ok(contents) => Ok(Some(serde_json::from_slice(&contents)?)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Returns the invoice with its PDF
+    pub fn with_pdf(&self, number: &str) -> Result<(OutboundInvoice, Vec<u8>), Error> {
+        let invoice = self.get(number)?.ok_or(Error::OutboundInvoiceNotFound)?;
+        let pdf = fs::read(self.pdf_path(number))?;
+        Ok((invoice, pdf))
+    }
+
+    /// Returns all issued invoices in the order of their numbers
+    pub fn all(&self) -> Result<Vec<OutboundInvoice>, Error> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut invoices = vec![];
+        for entry in entries {
+            let filename = entry?.file_name().to_string_lossy().into_owned();
+            let Some(number) = filename.strip_suffix(".json") else {
+                continue;
+            };
+            match self.get(number) {
+                Ok(Some(invoice)) => invoices.push(invoice),
+                Ok(None) => {}
+                Err(e) => warn!("Skipping invalid outbound invoice {number}: {e}"),
+            }
+        }
+
+        invoices.sort_by_key(|invoice| invoice.number.parse::<u64>().unwrap_or_default());
+        Ok(invoices)
+    }
+
+    /// Records that the invoice was emailed to the customer
+    pub fn mark_sent(&self, number: &str) -> Result<OutboundInvoice, Error> {
+        let _issuing = ISSUING.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut invoice = self.get(number)?.ok_or(Error::OutboundInvoiceNotFound)?;
+        invoice.sent_at = Some(local_time::now().to_rfc3339());
+        self.save(&invoice)?;
+        Ok(invoice)
+    }
+}
+
+impl<S> OptionalFromRequestParts<S> for OutboundInvoices
+where
+    S: Send + Sync,
+    State: FromRef<S>,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(
+        _parts: &mut Parts,
+        state: &S,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        let state = State::from_ref(state);
+        Ok(state.outbound)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_request() -> OutboundInvoiceRequest {
+        serde_json::from_value(serde_json::json!({
+            "customer": {
+                "name": "Yritys Oy",
+                "address": { "street": "Testikatu 1", "city": "Helsinki", "zip": "00100" },
+                "email": "laskut@example.com"
+            },
+            "subject": "Sponsorointi",
+            "rows": [{ "product": "Pääyhteistyökumppanuus", "unit_price": 150000 }]
+        }))
+        .expect("Invalid test request")
+    }
+
+    fn test_issuer() -> Issuer {
+        Issuer {
+            name: "Tietokilta ry".to_string(),
+            business_id: None,
+            address: None,
+            bank_account_number: "FI21 1234 5600 0007 85".to_string(),
+            bic: None,
+            email: None,
+        }
+    }
+
+    #[test]
+    fn test_invoices_are_numbered_in_order() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let outbound = OutboundInvoices::new(dir.path().to_path_buf(), test_issuer(), 1000);
+
+        let (first, _) = outbound.issue(test_request()).unwrap();
+        let (second, pdf) = outbound.issue(test_request()).unwrap();
+        assert_eq!(
+            (first.number.as_str(), first.reference.as_str()),
+            ("1000", "10003")
+        );
+        assert_eq!(second.number, "1001");
+        assert_eq!(second.total(), 150000);
+        assert!(second.due().is_some());
+
+        let (stored, stored_pdf) = outbound.with_pdf("1001").unwrap();
+        assert_eq!(stored.reference, second.reference);
+        assert_eq!(stored_pdf, pdf);
+
+        let numbers: Vec<String> = outbound
+            .all()
+            .unwrap()
+            .into_iter()
+            .map(|i| i.number)
+            .collect();
+        assert_eq!(numbers, ["1000", "1001"]);
+    }
+
+    #[test]
+    fn test_only_invoice_numbers_are_looked_up() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let outbound = OutboundInvoices::new(dir.path().to_path_buf(), test_issuer(), 1000);
+        outbound.issue(test_request()).unwrap();
+
+        assert!(outbound.get("numbers").unwrap().is_none());
+        assert!(outbound.get("../1000").unwrap().is_none());
+        assert!(matches!(
+            outbound.mark_sent("1001"),
+            Err(Error::OutboundInvoiceNotFound)
+        ));
+        assert!(outbound.mark_sent("1000").unwrap().sent_at.is_some());
+    }
+
+    #[test]
+    fn test_reference_number() {
+        // The check digit of 123456 is 1, the usual example of a Finnish reference number
+        assert_eq!(reference_number(123456), "12 34561");
+        assert_eq!(reference_number(1000), "10003");
+        assert_eq!(reference_number(100), "1009");
+    }
+}
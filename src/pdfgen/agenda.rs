@@ -0,0 +1,146 @@
+use super::{categories, diagnostics_to_string, pdf_options, Sandbox, WORLD};
+use crate::api::invoices::Invoice;
+use crate::error::Error;
+use crate::local_time;
+
+use serde_derive::Serialize;
+use std::sync::LazyLock;
+use typst::{foundations::Value, layout::PagedDocument};
+
+static AGENDA_WORLD: LazyLock<Sandbox> =
+    LazyLock::new(|| WORLD.with_template(include_str!("../../templates/agenda.typ")));
+
+/// An invoice on the agenda
+#[derive(Serialize)]
+struct AgendaRow {
+    id: String,
+    submitter: String,
+    subject: String,
+    /// The sum of the rows in cents
+    total: i64,
+    categories: Vec<String>,
+}
+
+impl From<&Invoice> for AgendaRow {
+    fn from(invoice: &Invoice) -> Self {
+        Self {
+            id: invoice.id.clone(),
+            submitter: invoice.recipient_name.clone(),
+            subject: invoice.subject.clone(),
+            total: invoice
+                .rows
+                .iter()
+                .map(|row| i64::from(row.unit_price))
+                .sum(),
+            categories: categories(invoice),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct AgendaData {
+    meeting: Option<String>,
+    invoices: Vec<AgendaRow>,
+}
+
+/// Renders the invoices waiting for approval as an appendix to the minutes of a board meeting
+pub struct AgendaBuilder {
+    data: AgendaData,
+    pdf_a: bool,
+}
+
+impl AgendaBuilder {
+    pub fn new<'a>(invoices: impl IntoIterator<Item = &'a Invoice>) -> Self {
+        Self {
+            data: AgendaData {
+                meeting: None,
+                invoices: invoices.into_iter().map(AgendaRow::from).collect(),
+            },
+            pdf_a: false,
+        }
+    }
+
+    /// The meeting shown in the heading, e.g. "Hallituksen kokous 5/2025"
+    pub fn meeting(mut self, meeting: Option<String>) -> Self {
+        self.data.meeting = meeting.filter(|meeting| !meeting.trim().is_empty());
+        self
+    }
+
+    /// Whether to export the appendix as PDF/A-3b for archival with the minutes
+    pub fn pdf_a(mut self, pdf_a: bool) -> Self {
+        self.pdf_a = pdf_a;
+        self
+    }
+
+    pub fn build(self) -> Result<PagedDocument, Error> {
+        let data: Value = serde_json::to_value(&self.data)
+            .and_then(serde_json::from_value)
+            .map_err(|e| Error::TypstError(format!("failed to convert agenda data: {e}")))?;
+        let world = AGENDA_WORLD.with_data(data, local_time::now());
+
+        let typst::diag::Warned {
+            output,
+            warnings: _,
+        } = typst::compile(&world);
+
+        output.map_err(|err| Error::TypstError(diagnostics_to_string(err)))
+    }
+
+    pub fn build_pdf(self) -> Result<Vec<u8>, Error> {
+        let options = pdf_options(self.pdf_a)?;
+        let document = self.build()?;
+
+        typst_pdf::pdf(&document, &options)
+            .map_err(|err| Error::PdfExport(diagnostics_to_string(err)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::invoices::InvoiceRow;
+
+    fn test_invoice(subject: &str, category: Option<&str>) -> Invoice {
+        let row = |product: &str, unit_price| InvoiceRow {
+            product: product.to_string(),
+            unit_price,
+            category: category.map(str::to_string),
+        };
+
+        Invoice {
+            subject: subject.to_string(),
+            rows: vec![row("Snacks", 1250), row("Drinks", 705)],
+            ..Invoice::test_fixture()
+        }
+    }
+
+    #[test]
+    fn test_agenda_row() {
+        let row = AgendaRow::from(&test_invoice("Sitsit", Some(" Sitsit ")));
+        assert_eq!(row.total, 1955);
+        assert_eq!(row.categories, vec!["Sitsit".to_string()]);
+    }
+
+    #[test]
+    fn test_agenda_is_rendered() {
+        let invoices = vec![
+            test_invoice("Sitsit", Some("Sitsit")),
+            test_invoice("Fuksiaiset", None),
+        ];
+
+        let pdf = AgendaBuilder::new(&invoices)
+            .meeting(Some("Hallituksen kokous 5/2025".to_string()))
+            .pdf_a(true)
+            .build_pdf()
+            .expect("Failed to build the agenda");
+        assert!(pdf.starts_with(b"%PDF-"));
+    }
+
+    #[test]
+    fn test_empty_agenda_is_rendered() {
+        let document = AgendaBuilder::new(std::iter::empty())
+            .build()
+            .expect("Failed to build the agenda");
+        assert_eq!(document.pages.len(), 1);
+    }
+}
@@ -1,10 +1,17 @@
 use crate::api::invoices::InvoiceAttachment;
+use crate::bank;
+use crate::jobs::Approval;
+use crate::local_time;
+use crate::merge::{Bookmark, EmbeddedFile, MergeSource, Relationship};
+use crate::signing::Signer;
 use crate::{api::invoices::Invoice, error::Error};
-use bank_barcode::{Barcode, BarcodeBuilder};
+use bank_barcode::{Barcode, BarcodeBuilder, BuilderError};
+use chrono::Datelike;
+use iban::Iban;
 use std::sync::LazyLock;
 use std::{collections::HashMap, path::PathBuf, sync::OnceLock};
 use typst::{
-    diag::{FileError, FileResult},
+    diag::{FileError, FileResult, SourceDiagnostic},
     foundations::{Bytes, Datetime, IntoValue, Value},
     layout::PagedDocument,
     syntax::{FileId, Source, VirtualPath},
@@ -12,6 +19,11 @@ use typst::{
     utils::LazyHash,
     Library, World,
 };
+use typst_pdf::{PdfOptions, PdfStandard, PdfStandards, Timestamp};
+
+pub mod agenda;
+mod orientation;
+pub mod outbound;
 
 static WORLD: LazyLock<Sandbox> = LazyLock::new(Sandbox::new);
 
@@ -113,7 +125,8 @@ struct Sandbox {
     fonts: Vec<FontSlot>,
 
     files: HashMap<FileId, FileEntry>,
-    time: time::OffsetDateTime,
+    /// The time the document is dated by, in Finnish time
+    time: chrono::DateTime<chrono::FixedOffset>,
 }
 
 impl Sandbox {
@@ -125,7 +138,7 @@ impl Sandbox {
             book: LazyHash::new(book),
             fonts,
             source: Source::detached(include_str!("../../templates/invoice.typ")),
-            time: time::OffsetDateTime::now_utc(),
+            time: local_time::now(),
             files: HashMap::new(),
         };
 
@@ -133,10 +146,22 @@ impl Sandbox {
             FileId::new(None, VirtualPath::new("/tik.png")),
             FileEntry::new(include_bytes!("../../templates/tik.png").to_vec(), None),
         );
+        new.files.insert(
+            FileId::new(None, VirtualPath::new("/common.typ")),
+            FileEntry::new(include_bytes!("../../templates/common.typ").to_vec(), None),
+        );
 
         new
     }
 
+    /// The same world with another main template
+    fn with_template(&self, template: &str) -> Self {
+        Self {
+            source: Source::detached(template),
+            ..self.clone()
+        }
+    }
+
     fn sandbox_file(&self, id: FileId) -> FileResult<&FileEntry> {
         if let Some(entry) = self.files.get(&id) {
             Ok(entry)
@@ -147,14 +172,16 @@ impl Sandbox {
         }
     }
 
-    fn with_data(&self, data: impl IntoValue) -> Self {
+    /// The same world with the data in scope, dated at the given time so that `datetime.today()`
+    /// stays the same however late the document is rendered
+    fn with_data(&self, data: impl IntoValue, time: chrono::DateTime<chrono::FixedOffset>) -> Self {
         let mut new = self.clone();
         let scope = new.library.global.scope_mut();
         scope.define("data", data);
         scope.define("COMMIT_HASH", Value::Str(env!("COMMIT_HASH").into()));
         scope.define("VERSION", Value::Str(env!("CARGO_PKG_VERSION").into()));
 
-        new.time = time::OffsetDateTime::now_utc();
+        new.time = time;
         new
     }
 }
@@ -189,16 +216,20 @@ impl World for Sandbox {
     }
 
     fn today(&self, offset: Option<i64>) -> Option<Datetime> {
-        let offset = offset.unwrap_or(0);
-        let offset = time::UtcOffset::from_hms(offset.try_into().ok()?, 0, 0).ok()?;
-        let time = self.time.checked_to_offset(offset)?;
-        Some(Datetime::Date(time.date()))
-    }
-}
-
-impl IntoValue for Invoice {
-    fn into_value(self) -> typst::foundations::Value {
-        serde_json::from_str(&serde_json::to_string(&self).unwrap()).unwrap()
+        // The date in Finland unless another offset is asked for
+        let date = match offset {
+            None => self.time.date_naive(),
+            Some(hours) => {
+                let offset =
+                    chrono::FixedOffset::east_opt(i32::try_from(hours).ok()?.checked_mul(3600)?)?;
+                self.time.with_timezone(&offset).date_naive()
+            }
+        };
+        Datetime::from_ymd(
+            date.year(),
+            date.month().try_into().ok()?,
+            date.day().try_into().ok()?,
+        )
     }
 }
 
@@ -206,14 +237,18 @@ impl TryInto<PagedDocument> for Invoice {
     type Error = Error;
 
     fn try_into(self) -> Result<PagedDocument, Error> {
-        let mut w = WORLD.clone().with_data(self.clone());
+        let data: Value = serde_json::to_value(&self)
+            .and_then(serde_json::from_value)
+            .map_err(|e| Error::TypstError(format!("failed to convert invoice data: {e}")))?;
+        let issued = self.issued().unwrap_or_else(local_time::now);
+        let mut w = WORLD.clone().with_data(data, issued);
         self.attachments.into_iter().for_each(|a| {
             w.files.insert(
                 FileId::new(
                     None,
                     VirtualPath::new("/attachments/".to_owned() + &a.filename),
                 ),
-                FileEntry::new(a.bytes, None),
+                FileEntry::new(orientation::upright(a.bytes), None),
             );
         });
 
@@ -224,30 +259,112 @@ impl TryInto<PagedDocument> for Invoice {
 
         match output {
             Ok(template) => Ok(template),
-            Err(err) => Err(Error::TypstError(
-                err.into_iter()
-                    .map(|e| e.message.to_string())
-                    .collect::<Vec<_>>()
-                    .join("\n"),
-            )),
+            Err(err) => Err(Error::TypstError(diagnostics_to_string(err))),
         }
     }
 }
 
+fn diagnostics_to_string(diagnostics: impl IntoIterator<Item = SourceDiagnostic>) -> String {
+    diagnostics
+        .into_iter()
+        .map(|e| e.message.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 impl TryFrom<Invoice> for Barcode {
     type Error = bank_barcode::BuilderError;
 
     fn try_from(invoice: Invoice) -> Result<Self, Self::Error> {
-        BarcodeBuilder::v4()
+        let builder = BarcodeBuilder::v4()
             .account_number(&invoice.bank_account_number)
-            .sum(invoice.rows.iter().map(|row| row.unit_price as u32).sum())
-            .build()
+            .sum(invoice.rows.iter().map(|row| row.unit_price as u32).sum());
+
+        match invoice.due().and_then(barcode_date) {
+            Some(due_date) => builder.due_date(due_date).build(),
+            None => builder.build(),
+        }
+    }
+}
+
+/// The bank barcode of a payment to the account. Only Finnish accounts have a barcode, so failing
+/// to create one for them is logged, while foreign accounts get a note in its place.
+fn barcode(barcode: Result<Barcode, BuilderError>, account: &str) -> Option<String> {
+    match barcode {
+        Ok(barcode) => Some(barcode.to_string()),
+        Err(e) => {
+            if !is_foreign_account(account) {
+                warn!("Failed to create the bank barcode for {account}: {e:?}");
+            }
+            None
+        }
     }
 }
 
+fn is_foreign_account(account: &str) -> bool {
+    account
+        .parse::<Iban>()
+        .is_ok_and(|iban| !bank::is_finnish(&iban))
+}
+
+/// The date as the barcode builder takes it
+fn barcode_date(date: chrono::NaiveDate) -> Option<time::Date> {
+    let month = time::Month::try_from(u8::try_from(date.month()).ok()?).ok()?;
+    time::Date::from_calendar_date(date.year(), month, u8::try_from(date.day()).ok()?).ok()
+}
+
+/// The options of the PDF export, with the creation date that PDF/A requires in the metadata
+fn pdf_options(pdf_a: bool) -> Result<PdfOptions<'static>, Error> {
+    let standards = if pdf_a {
+        PdfStandards::new(&[PdfStandard::A_3b]).map_err(|e| Error::PdfExport(e.to_string()))?
+    } else {
+        PdfStandards::default()
+    };
+
+    let now = time::OffsetDateTime::now_utc();
+    let timestamp = Datetime::from_ymd_hms(
+        now.year(),
+        now.month().into(),
+        now.day(),
+        now.hour(),
+        now.minute(),
+        now.second(),
+    )
+    .map(Timestamp::new_utc);
+
+    Ok(PdfOptions {
+        timestamp,
+        standards,
+        ..Default::default()
+    })
+}
+
+/// The distinct categories of the rows of the invoice
+fn categories(invoice: &Invoice) -> Vec<String> {
+    let mut categories: Vec<String> = Vec::new();
+    let row_categories = invoice
+        .rows
+        .iter()
+        .filter_map(|row| row.category.as_deref());
+
+    for category in row_categories.map(str::trim).filter(|c| !c.is_empty()) {
+        if !categories.iter().any(|c| c == category) {
+            categories.push(category.to_string());
+        }
+    }
+    categories
+}
+
+pub(crate) fn is_pdf(attachment: &InvoiceAttachment) -> bool {
+    attachment.filename.to_lowercase().ends_with(".pdf")
+}
+
 pub struct DocumentBuilder {
     invoice: Invoice,
     attachments: Vec<InvoiceAttachment>,
+    pdf_a: bool,
+    signer: Option<Signer>,
+    approval: Option<Approval>,
 }
 
 impl DocumentBuilder {
@@ -255,27 +372,161 @@ impl DocumentBuilder {
         Self {
             invoice,
             attachments,
+            pdf_a: false,
+            signer: None,
+            approval: None,
         }
     }
 
+    /// Whether to export the invoice as PDF/A-3b for long-term archival. PDF/A-3 is required for
+    /// embedding the original attachments, which aren't necessarily PDF/A files themselves.
+    ///
+    /// The merged document conforms to PDF/A only if the attached PDFs do as well, e.g. embed
+    /// all of their fonts.
+    pub fn pdf_a(mut self, pdf_a: bool) -> Self {
+        self.pdf_a = pdf_a;
+        self
+    }
+
+    /// Signs the final PDF, including the merged attachments, with the given signer
+    pub fn signer(mut self, signer: Option<Signer>) -> Self {
+        self.signer = signer;
+        self
+    }
+
+    /// Fills in the treasurer's markings on the cover page, making the invoice a voucher
+    pub fn approval(mut self, approval: Option<Approval>) -> Self {
+        self.approval = approval;
+        self
+    }
+
+    /// The markings with the date split into the blanks of the template
+    fn stamp(&self) -> serde_json::Value {
+        let Some(approval) = &self.approval else {
+            return serde_json::Value::Null;
+        };
+        let date = approval.date();
+
+        serde_json::json!({
+            "day": date.map(|date| date.day()),
+            "month": date.map(|date| date.month()),
+            "year": date.map(|date| date.year()),
+            "meeting": approval.meeting,
+            "account": approval.account,
+            "voucher": approval.voucher,
+        })
+    }
+
     // FIXME: this is very ugly
-    fn data(&self) -> Value {
-        let mut value: serde_json::Value = serde_json::from_str(
-            serde_json::to_string(&self.invoice)
-                .expect("BUG: serializing invoice failed")
-                .as_str(),
-        )
-        .expect("BUG: deserializing invoice failed");
+    fn data(&self) -> Result<Value, Error> {
+        let mut value = serde_json::to_value(&self.invoice)
+            .map_err(|e| Error::TypstError(format!("failed to serialize invoice: {e}")))?;
 
-        let barcode = Barcode::try_from(self.invoice.clone());
+        let account = &self.invoice.bank_account_number;
+        value["barcode"] = barcode(Barcode::try_from(self.invoice.clone()), account).into();
+        value["foreign_account"] = is_foreign_account(account).into();
 
-        value["barcode"] = barcode
-            .map(|barcode| barcode.to_string())
-            .unwrap_or_default()
-            .into();
+        // The keywords in the PDF metadata
+        value["keywords"] = categories(&self.invoice).into();
+        value["approval"] = self.stamp();
 
-        serde_json::from_str(&value.to_string())
-            .expect("BUG: failed to deserialize into typst::Value")
+        serde_json::from_value(value)
+            .map_err(|e| Error::TypstError(format!("failed to convert invoice data: {e}")))
+    }
+
+    /// The title of each attachment in the bookmarks of the final PDF
+    fn attachment_titles(&self) -> Vec<String> {
+        self.attachments
+            .iter()
+            .enumerate()
+            .map(|(i, a)| {
+                self.invoice
+                    .attachment_descriptions
+                    .get(i)
+                    .filter(|d| !d.trim().is_empty())
+                    .unwrap_or(&a.filename)
+                    .clone()
+            })
+            .collect()
+    }
+
+    /// Renders the invoice and merges the PDF attachments after it, with a bookmark for the
+    /// invoice and each attachment, and signs the result if a signer is set
+    pub fn build_pdf(self) -> Result<Vec<u8>, Error> {
+        let embedded_files = self.embedded_files()?;
+
+        let (pdf_titles, image_titles): (Vec<_>, Vec<_>) = self
+            .attachments
+            .iter()
+            .map(is_pdf)
+            .zip(self.attachment_titles())
+            .partition(|(pdf, _)| *pdf);
+
+        let options = pdf_options(self.pdf_a)?;
+        let signer = self.signer.clone();
+        let (document, attached_pdfs) = self.build_with_pdfs()?;
+
+        // Each image is rendered on its own page at the end of the document
+        let first_image_page = document.pages.len().saturating_sub(image_titles.len());
+
+        let mut bookmarks = vec![Bookmark::new("Lasku", 0)];
+        bookmarks.extend(
+            image_titles
+                .into_iter()
+                .enumerate()
+                .map(|(i, (_, title))| Bookmark::new(title, first_image_page + i)),
+        );
+
+        let pdf = typst_pdf::pdf(&document, &options)
+            .map_err(|err| Error::PdfExport(diagnostics_to_string(err)))?;
+
+        let mut pdfs = vec![MergeSource {
+            file: InvoiceAttachment {
+                filename: "invoice.pdf".to_string(),
+                bytes: pdf,
+            },
+            bookmarks,
+        }];
+        pdfs.extend(
+            attached_pdfs
+                .into_iter()
+                .zip(pdf_titles)
+                .map(|(file, (_, title))| MergeSource {
+                    file,
+                    bookmarks: vec![Bookmark::new(title, 0)],
+                }),
+        );
+
+        let pdf = crate::merge::merge_pdf_with_files(pdfs, embedded_files)?;
+
+        match signer {
+            Some(signer) => signer.sign_pdf(pdf),
+            None => Ok(pdf),
+        }
+    }
+
+    /// The original attachments and the invoice as JSON, to be embedded in the final PDF as is
+    fn embedded_files(&self) -> Result<Vec<EmbeddedFile>, Error> {
+        let json = serde_json::to_vec_pretty(&self.invoice)
+            .map_err(|e| Error::PdfExport(format!("failed to serialize invoice: {e}")))?;
+
+        let mut files = self
+            .attachments
+            .iter()
+            .zip(self.attachment_titles())
+            .map(|(file, title)| EmbeddedFile::new(file.clone(), title, Relationship::Source))
+            .collect::<Vec<_>>();
+
+        files.push(EmbeddedFile::new(
+            InvoiceAttachment {
+                filename: "invoice.json".to_string(),
+                bytes: json,
+            },
+            "Laskun tiedot",
+            Relationship::Data,
+        ));
+
+        Ok(files)
     }
 
     #[allow(dead_code)]
@@ -284,13 +535,14 @@ impl DocumentBuilder {
     }
 
     pub fn build_with_pdfs(self) -> Result<(PagedDocument, Vec<InvoiceAttachment>), Error> {
-        let mut w = WORLD.clone().with_data(self.data());
+        let issued = self.invoice.issued().unwrap_or_else(local_time::now);
+        let mut w = WORLD.clone().with_data(self.data()?, issued);
 
         let pdfs = self
             .attachments
             .into_iter()
             .filter_map(|a| {
-                if a.filename.to_lowercase().ends_with(".pdf") {
+                if is_pdf(&a) {
                     Some(a)
                 } else {
                     w.files.insert(
@@ -298,7 +550,7 @@ impl DocumentBuilder {
                             None,
                             VirtualPath::new(format!("/attachments/{}", a.filename)),
                         ),
-                        FileEntry::new(a.bytes, None),
+                        FileEntry::new(orientation::upright(a.bytes), None),
                     );
                     None
                 }
@@ -312,12 +564,442 @@ impl DocumentBuilder {
 
         match output {
             Ok(template) => Ok((template, pdfs)),
-            Err(err) => Err(Error::TypstError(
-                err.into_iter()
-                    .map(|e| e.message.to_string())
-                    .collect::<Vec<_>>()
-                    .join("\n"),
-            )),
+            Err(err) => Err(Error::TypstError(diagnostics_to_string(err))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::invoices::InvoiceRow;
+    use crate::merge::embedded_files;
+    use lopdf::{Dictionary, Document, Object};
+    use std::fs;
+    use typst::layout::{Frame, FrameItem};
+
+    fn test_invoice() -> Invoice {
+        let row = |product: &str, unit_price, category: Option<&str>| InvoiceRow {
+            product: product.to_string(),
+            unit_price,
+            category: category.map(str::to_string),
+        };
+
+        Invoice {
+            description: "Test description for invoice".to_string(),
+            attachment_descriptions: vec!["Receipt".to_string(), "Photo".to_string()],
+            rows: vec![
+                row("Test Product", 1000, Some("Fuksit")),
+                row("Other Product", 500, Some(" Fuksit ")),
+                row("Third Product", 500, Some("Sitsit")),
+                row("Uncategorized Product", 500, None),
+            ],
+            ..Invoice::test_fixture()
         }
     }
+
+    fn read_attachment(filename: &str) -> InvoiceAttachment {
+        InvoiceAttachment {
+            filename: filename.to_string(),
+            bytes: fs::read(format!("testdata/{filename}")).expect("Failed to read attachment"),
+        }
+    }
+
+    fn xmp_property<'a>(xmp: &'a str, name: &str) -> Option<&'a str> {
+        // The property may be written either as an element or as an attribute
+        let element = format!("<{name}>");
+        let attribute = format!("{name}=\"");
+        let (start, end) = if let Some(i) = xmp.find(&element) {
+            (i + element.len(), '<')
+        } else {
+            (xmp.find(&attribute)? + attribute.len(), '"')
+        };
+        let value = &xmp[start..];
+        Some(&value[..value.find(end)?])
+    }
+
+    fn is_font_embedded(document: &Document, font: &Dictionary) -> bool {
+        let descriptor = match font.get(b"Subtype").and_then(Object::as_name) {
+            // Type 3 glyphs are content streams within the font itself
+            Ok(b"Type3") => return true,
+            Ok(b"Type0") => font
+                .get(b"DescendantFonts")
+                .and_then(Object::as_array)
+                .ok()
+                .and_then(|fonts| fonts.first())
+                .and_then(|font| document.dereference(font).ok())
+                .and_then(|(_, font)| font.as_dict().ok())
+                .and_then(|font| font.get(b"FontDescriptor").ok()),
+            _ => font.get(b"FontDescriptor").ok(),
+        };
+
+        descriptor
+            .and_then(|descriptor| document.dereference(descriptor).ok())
+            .and_then(|(_, descriptor)| descriptor.as_dict().ok())
+            .is_some_and(|descriptor| {
+                [&b"FontFile"[..], b"FontFile2", b"FontFile3"]
+                    .into_iter()
+                    .any(|key| descriptor.has(key))
+            })
+    }
+
+    // Checks the structural requirements of ISO 19005-3 level B that the merge could break. This
+    // is not a PDF/A validator: e.g. the glyphs of the fonts, colour spaces and the XMP schemas
+    // aren't checked, for which the output has to be run through a validator such as veraPDF.
+    fn assert_pdf_a_structure(bytes: &[u8]) {
+        // The header must be followed by a comment of at least four binary characters
+        assert!(bytes.starts_with(b"%PDF-1."), "Invalid PDF header");
+        let header_end = bytes.iter().position(|b| *b == b'\n').unwrap();
+        let comment = &bytes[header_end + 1..];
+        assert!(
+            comment[0] == b'%' && comment[1..5].iter().all(|b| *b >= 128),
+            "Missing binary comment after the header"
+        );
+
+        let document = Document::load_mem(bytes).expect("Failed to load PDF");
+
+        assert!(!document.is_encrypted(), "PDF/A must not be encrypted");
+        let id = document.trailer.get(b"ID").and_then(Object::as_array);
+        assert!(id.is_ok_and(|id| id.len() == 2), "Missing file identifier");
+
+        let catalog = document.catalog().expect("Missing catalog");
+
+        let metadata = catalog
+            .get(b"Metadata")
+            .and_then(Object::as_reference)
+            .and_then(|id| document.get_object(id))
+            .and_then(Object::as_stream)
+            .expect("Missing XMP metadata");
+        assert!(
+            !metadata.dict.has(b"Filter"),
+            "XMP metadata must not be filtered"
+        );
+        let xmp = std::str::from_utf8(&metadata.content).expect("XMP metadata must be UTF-8");
+        assert_eq!(xmp_property(xmp, "pdfaid:part"), Some("3"));
+        assert_eq!(xmp_property(xmp, "pdfaid:conformance"), Some("B"));
+
+        let intents = catalog
+            .get(b"OutputIntents")
+            .and_then(Object::as_array)
+            .expect("Missing output intents");
+        assert!(
+            intents.iter().any(|intent| {
+                let intent = document.dereference(intent).and_then(|(_, i)| i.as_dict());
+                intent.is_ok_and(|intent| {
+                    matches!(intent.get(b"S").and_then(Object::as_name), Ok(b"GTS_PDFA1"))
+                        && intent.has(b"DestOutputProfile")
+                })
+            }),
+            "Missing PDF/A output intent"
+        );
+
+        for object in document.objects.values() {
+            if let Ok(stream) = object.as_stream() {
+                let filter = stream.dict.get(b"Filter").and_then(Object::as_name);
+                assert!(
+                    !matches!(filter, Ok(b"LZWDecode")),
+                    "LZW compression is forbidden"
+                );
+            }
+
+            let Ok(dict) = object.as_dict() else {
+                continue;
+            };
+
+            // Descendant fonts are checked through their parent
+            let subtype = dict.get(b"Subtype").and_then(Object::as_name);
+            if dict.has_type(b"Font") && !matches!(subtype, Ok(b"CIDFontType0" | b"CIDFontType2")) {
+                assert!(is_font_embedded(&document, dict), "Font is not embedded");
+            }
+
+            for key in [&b"JS"[..], b"JavaScript"] {
+                assert!(!dict.has(key), "PDF/A must not contain JavaScript");
+            }
+
+            if dict.has_type(b"Filespec") {
+                for key in [&b"F"[..], b"UF", b"AFRelationship"] {
+                    assert!(dict.has(key), "Embedded file specification is incomplete");
+                }
+            }
+        }
+
+        let associated = catalog.get(b"AF").and_then(Object::as_array);
+        assert!(
+            associated.is_ok_and(|files| !files.is_empty()),
+            "Missing associated files"
+        );
+
+        for (_, _, file) in embedded_files(&document) {
+            let params = file.dict.get(b"Params").and_then(Object::as_dict);
+            assert!(
+                file.dict.has(b"Subtype"),
+                "Embedded file must have a MIME type"
+            );
+            assert!(
+                params.is_ok_and(|p| p.has(b"ModDate")),
+                "Embedded file must have a date"
+            );
+        }
+    }
+
+    #[test]
+    fn test_pdf_a_output_keeps_structure() {
+        let attachments = vec![read_attachment("test.pdf"), read_attachment("test.jpg")];
+
+        let pdf = DocumentBuilder::new(test_invoice(), attachments)
+            .pdf_a(true)
+            .build_pdf()
+            .expect("Failed to build PDF");
+
+        assert_pdf_a_structure(&pdf);
+    }
+
+    #[test]
+    fn test_pdf_a_output_with_attached_fonts_keeps_structure() {
+        let mut invoice = test_invoice();
+        invoice.attachment_descriptions = vec!["Receipt".to_string()];
+        let attachments = vec![InvoiceAttachment {
+            filename: "receipt.pdf".to_string(),
+            ..read_attachment("regression/normal.pdf")
+        }];
+
+        let pdf = DocumentBuilder::new(invoice, attachments)
+            .pdf_a(true)
+            .build_pdf()
+            .expect("Failed to build PDF");
+
+        assert_pdf_a_structure(&pdf);
+    }
+
+    #[test]
+    fn test_signed_pdf_a_output_keeps_structure() {
+        let key = rcgen::KeyPair::generate().expect("Failed to generate key");
+        let certificate = rcgen::CertificateParams::new(vec![])
+            .and_then(|params| params.self_signed(&key))
+            .expect("Failed to generate certificate");
+        let signer = Signer::from_pem(&certificate.pem(), &key.serialize_pem())
+            .expect("Failed to create signer");
+
+        let attachments = vec![read_attachment("test.pdf"), read_attachment("test.jpg")];
+        let pdf = DocumentBuilder::new(test_invoice(), attachments)
+            .pdf_a(true)
+            .signer(Some(signer.clone()))
+            .build_pdf()
+            .expect("Failed to build PDF");
+
+        assert_pdf_a_structure(&pdf);
+
+        let verification = signer
+            .verify_pdf(&InvoiceAttachment {
+                filename: "invoice.pdf".to_string(),
+                bytes: pdf,
+            })
+            .expect("Failed to verify PDF");
+        assert!(verification.valid, "{:?}", verification.reason);
+    }
+
+    #[test]
+    fn test_original_files_are_embedded() {
+        let attachments = vec![read_attachment("test.pdf"), read_attachment("test.jpg")];
+
+        let pdf = DocumentBuilder::new(test_invoice(), attachments.clone())
+            .build_pdf()
+            .expect("Failed to build PDF");
+
+        let document = Document::load_mem(&pdf).expect("Failed to load PDF");
+        let files = embedded_files(&document)
+            .into_iter()
+            .map(|(filename, _, stream)| {
+                let contents = stream
+                    .decompressed_content()
+                    .unwrap_or_else(|_| stream.content.clone());
+                (filename, contents)
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(files.len(), 3);
+        for (attachment, (filename, contents)) in attachments.iter().zip(&files) {
+            assert_eq!(&attachment.filename, filename);
+            assert_eq!(
+                &attachment.bytes, contents,
+                "Embedded file should be the original"
+            );
+        }
+
+        let (filename, json) = &files[2];
+        assert_eq!(filename, "invoice.json");
+        let invoice: Invoice = serde_json::from_slice(json).expect("Invalid invoice JSON");
+        assert_eq!(invoice.subject, "Test Invoice");
+    }
+
+    #[test]
+    fn test_document_metadata() {
+        let pdf = DocumentBuilder::new(test_invoice(), vec![])
+            .build_pdf()
+            .expect("Failed to build PDF");
+
+        let document = Document::load_mem(&pdf).expect("Failed to load PDF");
+        let info = document
+            .trailer
+            .get(b"Info")
+            .and_then(Object::as_reference)
+            .and_then(|id| document.get_dictionary(id))
+            .expect("Missing document information dictionary");
+        let entry = |key: &[u8]| {
+            info.get(key)
+                .ok()
+                .and_then(|value| lopdf::decode_text_string(value).ok())
+        };
+
+        assert_eq!(entry(b"Title").as_deref(), Some("Test Invoice"));
+        assert_eq!(entry(b"Author").as_deref(), Some("Test User"));
+        assert_eq!(
+            entry(b"Subject").as_deref(),
+            Some("Test description for invoice")
+        );
+        assert_eq!(entry(b"Keywords").as_deref(), Some("Fuksit, Sitsit"));
+        let producer = entry(b"Producer").expect("Missing producer");
+        assert!(producer.contains(env!("CARGO_PKG_VERSION")));
+        assert!(producer.contains(env!("COMMIT_HASH")));
+        assert!(info.has(b"CreationDate"), "Missing creation date");
+
+        // The XMP metadata must agree with the information dictionary
+        let metadata = document
+            .catalog()
+            .and_then(|catalog| catalog.get(b"Metadata"))
+            .and_then(Object::as_reference)
+            .and_then(|id| document.get_object(id))
+            .and_then(Object::as_stream)
+            .expect("Missing XMP metadata");
+        let xmp = std::str::from_utf8(&metadata.content).unwrap();
+        assert!(xmp.contains("<dc:description>"));
+        assert!(xmp.contains("Test description for invoice"));
+    }
+
+    #[test]
+    fn test_document_subject_without_description() {
+        let mut invoice = test_invoice();
+        invoice.description = String::new();
+        let pdf = DocumentBuilder::new(invoice, vec![])
+            .build_pdf()
+            .expect("Failed to build PDF");
+
+        let document = Document::load_mem(&pdf).expect("Failed to load PDF");
+        let info = document
+            .trailer
+            .get(b"Info")
+            .and_then(Object::as_reference)
+            .and_then(|id| document.get_dictionary(id))
+            .expect("Missing document information dictionary");
+        assert_eq!(
+            lopdf::decode_text_string(info.get(b"Subject").unwrap()).unwrap(),
+            "Test Invoice"
+        );
+    }
+
+    #[test]
+    fn test_document_is_dated_by_issue_time() {
+        // Just after midnight in Finland, but still the previous day in UTC
+        let issued = chrono::DateTime::parse_from_rfc3339("2025-05-20T00:15:00+03:00").unwrap();
+        let world = WORLD.with_data(Value::None, issued);
+
+        assert_eq!(world.today(None), Datetime::from_ymd(2025, 5, 20));
+        assert_eq!(world.today(Some(0)), Datetime::from_ymd(2025, 5, 19));
+    }
+
+    /// The text on the first page of the document, with a space between each run of text
+    fn first_page_text(document: &PagedDocument) -> String {
+        fn collect(frame: &Frame, text: &mut String) {
+            for (_, item) in frame.items() {
+                match item {
+                    FrameItem::Group(group) => collect(&group.frame, text),
+                    FrameItem::Text(item) => {
+                        text.push_str(&item.text);
+                        text.push(' ');
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let mut text = String::new();
+        collect(&document.pages[0].frame, &mut text);
+        text
+    }
+
+    #[test]
+    fn test_due_date_is_in_finnish_format() {
+        let mut invoice = test_invoice();
+        invoice.due_date = Some("2025-06-03".to_string());
+        let document = DocumentBuilder::new(invoice, vec![])
+            .build()
+            .expect("Failed to build the invoice");
+
+        let text = first_page_text(&document);
+        assert!(text.contains("3.6.2025"), "{text}");
+        assert!(!text.contains("2025-06-03"), "{text}");
+    }
+
+    #[test]
+    fn test_approval_is_stamped() {
+        let approval = Approval {
+            date: "2025-05-20".to_string(),
+            meeting: 5,
+            account: "4000 Sitsit".to_string(),
+            voucher: "12".to_string(),
+        };
+        let builder = DocumentBuilder::new(test_invoice(), vec![]).approval(Some(approval));
+
+        assert_eq!(
+            builder.stamp(),
+            serde_json::json!({
+                "day": 20,
+                "month": 5,
+                "year": 2025,
+                "meeting": 5,
+                "account": "4000 Sitsit",
+                "voucher": "12",
+            })
+        );
+        builder.build().expect("Failed to build the voucher");
+    }
+
+    #[test]
+    fn test_foreign_account_has_no_barcode() {
+        let mut invoice = test_invoice();
+        let account = invoice.bank_account_number.clone();
+        assert!(barcode(Barcode::try_from(invoice.clone()), &account).is_some());
+
+        invoice.bank_account_number = "DE89 3704 0044 0532 0130 00".to_string();
+        let account = invoice.bank_account_number.clone();
+        assert!(is_foreign_account(&account));
+        assert_eq!(barcode(Barcode::try_from(invoice.clone()), &account), None);
+        DocumentBuilder::new(invoice, vec![])
+            .build()
+            .expect("Failed to build the invoice");
+    }
+
+    #[test]
+    fn test_barcode_has_due_date() {
+        let mut invoice = test_invoice();
+        invoice.due_date = Some("2025-06-03".to_string());
+        let account = invoice.bank_account_number.clone();
+        let code = barcode(Barcode::try_from(invoice.clone()), &account).unwrap();
+
+        // Version 4 of the virtual barcode: the version, the account number without the country
+        // code, the euros and cents, a reserved field, the reference and the due date as YYMMDD
+        assert_eq!(code.len(), 54, "{code}");
+        assert!(code.chars().all(|c| c.is_ascii_digit()), "{code}");
+        assert_eq!(&code[..1], "4");
+        assert_eq!(&code[1..17], "2112345600000785");
+        assert_eq!(&code[17..23], "000025");
+        assert_eq!(&code[23..25], "00");
+        assert_eq!(&code[25..28], "000");
+        assert_eq!(&code[48..], "250603");
+
+        // Without a due date the field is left as zeros
+        invoice.due_date = None;
+        let code = barcode(Barcode::try_from(invoice), &account).unwrap();
+        assert_eq!(&code[48..], "000000");
+    }
 }
@@ -0,0 +1,123 @@
+use image::{
+    codecs::jpeg::JpegEncoder, metadata::Orientation, DynamicImage, ImageDecoder, ImageFormat,
+    ImageReader,
+};
+use std::io::Cursor;
+
+/// The quality used when re-encoding rotated JPEG images
+const JPEG_QUALITY: u8 = 90;
+
+/// Rotates the pixels of an image according to its EXIF orientation tag.
+///
+/// Phone cameras usually store portrait photos sideways and only set the orientation tag, which
+/// doesn't survive into the generated PDF. The returned image has the transformation applied and
+/// no orientation tag left. Images that don't need rotating are returned as is.
+pub fn upright(bytes: Vec<u8>) -> Vec<u8> {
+    match try_upright(&bytes) {
+        Ok(Some(rotated)) => rotated,
+        Ok(None) => bytes,
+        Err(e) => {
+            warn!("Failed to apply EXIF orientation: {e}");
+            bytes
+        }
+    }
+}
+
+fn try_upright(bytes: &[u8]) -> image::ImageResult<Option<Vec<u8>>> {
+    let reader = ImageReader::new(Cursor::new(bytes)).with_guessed_format()?;
+
+    // Only JPEG and PNG images carry EXIF metadata
+    let format = match reader.format() {
+        Some(format @ (ImageFormat::Jpeg | ImageFormat::Png)) => format,
+        _ => return Ok(None),
+    };
+
+    let mut decoder = reader.into_decoder()?;
+    let orientation = decoder.orientation()?;
+
+    if orientation == Orientation::NoTransforms {
+        return Ok(None);
+    }
+
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+
+    let mut buffer = Cursor::new(Vec::new());
+    match format {
+        ImageFormat::Jpeg => {
+            image.write_with_encoder(JpegEncoder::new_with_quality(&mut buffer, JPEG_QUALITY))?
+        }
+        _ => image.write_to(&mut buffer, format)?,
+    }
+
+    Ok(Some(buffer.into_inner()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{imageops, RgbImage};
+    use std::fs;
+
+    /// The largest mean difference re-encoding as JPEG may cause. The rotated images differ from
+    /// the original by about 2 and from the original turned upside down by about 24.
+    const MAX_JPEG_DIFFERENCE: f64 = 5.0;
+
+    /// The mean difference of the color channels of two images of the same size, from 0 to 255
+    fn difference(a: &RgbImage, b: &RgbImage) -> f64 {
+        let sum: u64 = a
+            .as_raw()
+            .iter()
+            .zip(b.as_raw())
+            .map(|(a, b)| u64::from(a.abs_diff(*b)))
+            .sum();
+        sum as f64 / a.as_raw().len() as f64
+    }
+
+    // The regression images contain testdata/test.png (447x223) stored sideways or upside down
+    // along with the EXIF orientation that should turn them back upright
+    fn test_orientation(filename: &str) {
+        let bytes = fs::read(filename).expect("Failed to read test image");
+        let rotated = upright(bytes);
+
+        let mut decoder = ImageReader::new(Cursor::new(&rotated))
+            .with_guessed_format()
+            .unwrap()
+            .into_decoder()
+            .expect("Failed to decode rotated image");
+        assert_eq!(decoder.orientation().unwrap(), Orientation::NoTransforms);
+
+        let image = DynamicImage::from_decoder(decoder).unwrap();
+        assert_eq!((image.width(), image.height()), (447, 223));
+
+        // The size alone can't tell an upright image from an upside down one, so the pixels are
+        // compared with the original. Turning it the wrong way would leave it upside down, as the
+        // images stored sideways are turned by a quarter.
+        let image = image.to_rgb8();
+        let original = image::open("testdata/test.png").unwrap().to_rgb8();
+        let upside_down = imageops::rotate180(&original);
+        assert!(difference(&image, &original) < MAX_JPEG_DIFFERENCE);
+        assert!(difference(&image, &upside_down) > MAX_JPEG_DIFFERENCE);
+    }
+
+    #[test]
+    fn test_rotate_180() {
+        test_orientation("testdata/regression/exif-orientation-3.jpg");
+    }
+
+    #[test]
+    fn test_rotate_90_clockwise() {
+        test_orientation("testdata/regression/exif-orientation-6.jpg");
+    }
+
+    #[test]
+    fn test_rotate_270_clockwise() {
+        test_orientation("testdata/regression/exif-orientation-8.jpg");
+    }
+
+    #[test]
+    fn test_image_without_orientation_is_untouched() {
+        let bytes = fs::read("testdata/test.jpg").expect("Failed to read test.jpg");
+        assert_eq!(upright(bytes.clone()), bytes);
+    }
+}
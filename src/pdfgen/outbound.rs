@@ -0,0 +1,139 @@
+use super::{
+    barcode, barcode_date, diagnostics_to_string, is_foreign_account, pdf_options, Sandbox, WORLD,
+};
+use crate::error::Error;
+use crate::local_time;
+use crate::outbound::OutboundInvoice;
+
+use bank_barcode::{Barcode, BarcodeBuilder};
+use serde_derive::Serialize;
+use std::sync::LazyLock;
+use typst::{foundations::Value, layout::PagedDocument};
+
+static OUTBOUND_WORLD: LazyLock<Sandbox> =
+    LazyLock::new(|| WORLD.with_template(include_str!("../../templates/outbound.typ")));
+
+impl TryFrom<&OutboundInvoice> for Barcode {
+    type Error = bank_barcode::BuilderError;
+
+    fn try_from(invoice: &OutboundInvoice) -> Result<Self, Self::Error> {
+        let builder = BarcodeBuilder::v4()
+            .account_number(&invoice.issuer.bank_account_number)
+            .sum(invoice.total() as u32)
+            .reference(&invoice.reference.replace(' ', ""));
+
+        match invoice.due().and_then(barcode_date) {
+            Some(due_date) => builder.due_date(due_date).build(),
+            None => builder.build(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct OutboundData<'a> {
+    #[serde(flatten)]
+    invoice: &'a OutboundInvoice,
+    /// The sum of the rows in cents
+    total: i64,
+    barcode: Option<String>,
+    foreign_account: bool,
+}
+
+/// Renders an invoice issued by the guild to a customer
+pub struct OutboundBuilder<'a> {
+    invoice: &'a OutboundInvoice,
+    pdf_a: bool,
+}
+
+impl<'a> OutboundBuilder<'a> {
+    pub fn new(invoice: &'a OutboundInvoice) -> Self {
+        Self {
+            invoice,
+            pdf_a: false,
+        }
+    }
+
+    /// Whether to export the invoice as PDF/A-3b for archival in the bookkeeping
+    pub fn pdf_a(mut self, pdf_a: bool) -> Self {
+        self.pdf_a = pdf_a;
+        self
+    }
+
+    pub fn build(self) -> Result<PagedDocument, Error> {
+        let account = &self.invoice.issuer.bank_account_number;
+        let data = OutboundData {
+            invoice: self.invoice,
+            total: self.invoice.total(),
+            barcode: barcode(Barcode::try_from(self.invoice), account),
+            foreign_account: is_foreign_account(account),
+        };
+        let data: Value = serde_json::to_value(&data)
+            .and_then(serde_json::from_value)
+            .map_err(|e| Error::TypstError(format!("failed to convert invoice data: {e}")))?;
+        let issued = self.invoice.issued().unwrap_or_else(local_time::now);
+        let world = OUTBOUND_WORLD.with_data(data, issued);
+
+        let typst::diag::Warned {
+            output,
+            warnings: _,
+        } = typst::compile(&world);
+
+        output.map_err(|err| Error::TypstError(diagnostics_to_string(err)))
+    }
+
+    pub fn build_pdf(self) -> Result<Vec<u8>, Error> {
+        let options = pdf_options(self.pdf_a)?;
+        let document = self.build()?;
+
+        typst_pdf::pdf(&document, &options)
+            .map_err(|err| Error::PdfExport(diagnostics_to_string(err)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_invoice() -> OutboundInvoice {
+        serde_json::from_value(serde_json::json!({
+            "number": "1000",
+            "reference": "10003",
+            "issued_at": "2025-05-20T00:15:00+03:00",
+            "due_date": "2025-06-03",
+            "issuer": {
+                "name": "Tietokilta ry",
+                "business_id": null,
+                "address": "Konemiehentie 2, 02150 Espoo",
+                "bank_account_number": "FI21 1234 5600 0007 85",
+                "email": "rahastonhoitaja@tietokilta.fi"
+            },
+            "customer": {
+                "name": "Yritys Oy",
+                "business_id": "1234567-1",
+                "contact": "Maija Meikäläinen",
+                "address": { "street": "Testikatu 1", "city": "Helsinki", "zip": "00100" },
+                "email": "laskut@example.com"
+            },
+            "subject": "Sponsorointi",
+            "description": "Vuosijuhlien yhteistyö",
+            "rows": [{ "product": "Pääyhteistyökumppanuus", "unit_price": 150000 }],
+            "sent_at": null
+        }))
+        .expect("Invalid test invoice")
+    }
+
+    #[test]
+    fn test_outbound_invoice_is_rendered() {
+        let invoice = test_invoice();
+        let document = OutboundBuilder::new(&invoice)
+            .build()
+            .expect("Failed to build the invoice");
+        assert_eq!(document.pages.len(), 1);
+
+        let pdf = OutboundBuilder::new(&invoice)
+            .pdf_a(true)
+            .build_pdf()
+            .expect("Failed to export the invoice");
+        assert!(pdf.starts_with(b"%PDF-"));
+    }
+}
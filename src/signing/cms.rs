@@ -0,0 +1,179 @@
+use super::SigningKey;
+
+use cms::{
+    builder::{SignedDataBuilder, SignerInfoBuilder},
+    cert::{CertificateChoices, IssuerAndSerialNumber},
+    content_info::ContentInfo,
+    signed_data::{EncapsulatedContentInfo, SignedData, SignerIdentifier},
+};
+use const_oid::db::{rfc5911, rfc5912};
+use der::{
+    asn1::{OctetString, OctetStringRef, SetOfVec},
+    referenced::OwnedToRef,
+    Any, Decode, Encode, Sequence, SliceReader,
+};
+use rsa::signature::Verifier;
+use sha2::{Digest, Sha256};
+use spki::AlgorithmIdentifierOwned;
+use x509_cert::{attr::Attribute, Certificate};
+
+/// `ESSCertIDv2` from RFC 5035, with the default SHA-256 hash algorithm and no issuer serial
+#[derive(Sequence)]
+struct EssCertIdV2 {
+    cert_hash: OctetString,
+}
+
+/// `SigningCertificateV2` from RFC 5035, which PAdES requires to bind the signer certificate
+/// to the signature
+#[derive(Sequence)]
+struct SigningCertificateV2 {
+    certs: Vec<EssCertIdV2>,
+}
+
+fn sha256() -> AlgorithmIdentifierOwned {
+    AlgorithmIdentifierOwned {
+        oid: rfc5912::ID_SHA_256,
+        parameters: None,
+    }
+}
+
+fn signing_certificate_attribute(certificate: &Certificate) -> der::Result<Attribute> {
+    let cert_hash = OctetString::new(Sha256::digest(certificate.to_der()?).to_vec())?;
+    let value = SigningCertificateV2 {
+        certs: vec![EssCertIdV2 { cert_hash }],
+    };
+
+    let mut values = SetOfVec::new();
+    values.insert(Any::from_der(&value.to_der()?)?)?;
+
+    Ok(Attribute {
+        oid: rfc5911::ID_AA_SIGNING_CERTIFICATE_V_2,
+        values,
+    })
+}
+
+/// Creates a detached CMS signature of content with the given SHA-256 digest
+pub(super) fn sign(
+    key: &SigningKey,
+    certificate: &Certificate,
+    digest: &[u8],
+) -> Result<Vec<u8>, cms::builder::Error> {
+    let content = EncapsulatedContentInfo {
+        econtent_type: rfc5911::ID_DATA,
+        econtent: None,
+    };
+    let sid = SignerIdentifier::IssuerAndSerialNumber(IssuerAndSerialNumber {
+        issuer: certificate.tbs_certificate.issuer.clone(),
+        serial_number: certificate.tbs_certificate.serial_number.clone(),
+    });
+
+    let mut builder = SignedDataBuilder::new(&content);
+    builder
+        .add_digest_algorithm(sha256())?
+        .add_certificate(CertificateChoices::Certificate(certificate.clone()))?;
+
+    let attribute = signing_certificate_attribute(certificate)?;
+
+    match key {
+        SigningKey::Ecdsa(key) => {
+            let mut signer = SignerInfoBuilder::new(key, sid, sha256(), &content, Some(digest))?;
+            signer.add_signed_attribute(attribute)?;
+            builder.add_signer_info::<_, p256::ecdsa::DerSignature>(signer)?;
+        }
+        SigningKey::Rsa(key) => {
+            let mut signer =
+                SignerInfoBuilder::new(key.as_ref(), sid, sha256(), &content, Some(digest))?;
+            signer.add_signed_attribute(attribute)?;
+            builder.add_signer_info::<_, rsa::pkcs1v15::Signature>(signer)?;
+        }
+    }
+
+    Ok(builder.build()?.to_der()?)
+}
+
+/// Verifies that a detached CMS signature of content with the given SHA-256 digest was made
+/// with the key of the certificate. Returns the reason if it wasn't.
+pub(super) fn verify(
+    signature: &[u8],
+    digest: &[u8],
+    certificate: &Certificate,
+) -> Result<(), String> {
+    let invalid = |e: der::Error| format!("The signature is malformed: {e}");
+
+    // The signature is padded with zeros to the size reserved for it in the PDF
+    let content_info =
+        ContentInfo::decode(&mut SliceReader::new(signature).map_err(invalid)?).map_err(invalid)?;
+    if content_info.content_type != rfc5911::ID_SIGNED_DATA {
+        return Err("The signature is not a CMS signature".to_string());
+    }
+    let signed_data = content_info
+        .content
+        .decode_as::<SignedData>()
+        .map_err(invalid)?;
+
+    let signer_info = signed_data
+        .signer_infos
+        .0
+        .iter()
+        .next()
+        .ok_or("The signature has no signers")?;
+
+    let issuer_and_serial = IssuerAndSerialNumber {
+        issuer: certificate.tbs_certificate.issuer.clone(),
+        serial_number: certificate.tbs_certificate.serial_number.clone(),
+    };
+    if signer_info.sid != SignerIdentifier::IssuerAndSerialNumber(issuer_and_serial) {
+        return Err("The PDF is signed with a different certificate".to_string());
+    }
+
+    if signer_info.digest_alg.oid != rfc5912::ID_SHA_256 {
+        return Err("The signature uses an unsupported digest algorithm".to_string());
+    }
+
+    let signed_attributes = signer_info
+        .signed_attrs
+        .as_ref()
+        .ok_or("The signature has no signed attributes")?;
+
+    let message_digest = signed_attributes
+        .iter()
+        .find(|attribute| attribute.oid == rfc5911::ID_MESSAGE_DIGEST)
+        .and_then(|attribute| attribute.values.iter().next())
+        .and_then(|value| value.decode_as::<OctetStringRef>().ok())
+        .ok_or("The signature has no message digest")?;
+
+    if message_digest.as_bytes() != digest {
+        return Err("The PDF has been modified after signing".to_string());
+    }
+
+    // The signature is calculated over the DER encoding of the signed attributes
+    let message = signed_attributes.to_der().map_err(invalid)?;
+    let signature = signer_info.signature.as_bytes();
+    let public_key = &certificate.tbs_certificate.subject_public_key_info;
+
+    let verified = match public_key.algorithm.oid {
+        rfc5912::ID_EC_PUBLIC_KEY => {
+            let key = p256::ecdsa::VerifyingKey::from_sec1_bytes(
+                public_key.subject_public_key.raw_bytes(),
+            )
+            .map_err(|e| format!("The certificate has an unsupported key: {e}"))?;
+            p256::ecdsa::DerSignature::from_bytes(signature)
+                .is_ok_and(|signature| key.verify(&message, &signature).is_ok())
+        }
+        rfc5912::RSA_ENCRYPTION => {
+            let key = rsa::RsaPublicKey::try_from(public_key.owned_to_ref())
+                .map_err(|e| format!("The certificate has an unsupported key: {e}"))?;
+            rsa::pkcs1v15::Signature::try_from(signature).is_ok_and(|signature| {
+                rsa::pkcs1v15::VerifyingKey::<Sha256>::new(key)
+                    .verify(&message, &signature)
+                    .is_ok()
+            })
+        }
+        _ => return Err("The certificate has an unsupported key".to_string()),
+    };
+
+    match verified {
+        true => Ok(()),
+        false => Err("The signature doesn't match the certificate".to_string()),
+    }
+}
@@ -0,0 +1,301 @@
+use crate::api::invoices::InvoiceAttachment;
+use crate::error::Error;
+use crate::state::State;
+
+use axum::{
+    extract::{FromRef, OptionalFromRequestParts},
+    http::request::Parts,
+};
+use der::{DecodePem, Encode};
+use p256::pkcs8::{DecodePrivateKey, EncodePublicKey};
+use serde_derive::Serialize;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use utoipa::ToSchema;
+use x509_cert::Certificate;
+
+mod cms;
+mod pdf;
+
+enum SigningKey {
+    Ecdsa(p256::ecdsa::SigningKey),
+    Rsa(Box<rsa::pkcs1v15::SigningKey<Sha256>>),
+}
+
+/// Signs the generated PDFs with the certificate of the service and verifies the signatures
+#[derive(Clone)]
+pub struct Signer {
+    key: Arc<SigningKey>,
+    certificate: Arc<Certificate>,
+}
+
+/// The result of verifying the signature of a PDF
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Verification {
+    /// Whether the PDF is signed with the certificate of the service and hasn't been modified
+    /// after signing
+    pub valid: bool,
+    /// The subject of the certificate the PDF was signed with
+    pub signer: Option<String>,
+    /// The time of signing in RFC 3339 format, as claimed by the signer
+    pub signed_at: Option<String>,
+    /// Why the signature is not valid
+    pub reason: Option<String>,
+}
+
+impl Verification {
+    fn invalid(reason: impl Into<String>) -> Self {
+        Self {
+            valid: false,
+            signer: None,
+            signed_at: None,
+            reason: Some(reason.into()),
+        }
+    }
+}
+
+impl TryFrom<crate::SigningConfig> for Signer {
+    type Error = String;
+
+    fn try_from(config: crate::SigningConfig) -> Result<Self, Self::Error> {
+        let (Some(certificate), Some(key)) = (config.certificate, config.key) else {
+            return Err("signing is disabled".to_string());
+        };
+
+        let certificate = std::fs::read_to_string(&certificate)
+            .map_err(|e| format!("failed to read {}: {e}", certificate.display()))?;
+        let key = std::fs::read_to_string(&key)
+            .map_err(|e| format!("failed to read {}: {e}", key.display()))?;
+
+        Self::from_pem(&certificate, &key)
+    }
+}
+
+impl Signer {
+    /// Creates a signer from a PEM encoded X.509 certificate and a PEM encoded PKCS #8 private
+    /// key, which must be either an ECDSA P-256 or an RSA key
+    pub fn from_pem(certificate: &str, key: &str) -> Result<Self, String> {
+        let certificate = Certificate::from_pem(certificate)
+            .map_err(|e| format!("failed to parse certificate: {e}"))?;
+
+        let (key, public_key) = if let Ok(key) = p256::ecdsa::SigningKey::from_pkcs8_pem(key) {
+            let public_key = key.verifying_key().to_public_key_der();
+            (SigningKey::Ecdsa(key), public_key)
+        } else {
+            let key = rsa::RsaPrivateKey::from_pkcs8_pem(key).map_err(|e| {
+                format!("failed to parse private key, expected a P-256 or RSA key: {e}")
+            })?;
+            let public_key = key.to_public_key().to_public_key_der();
+            (
+                SigningKey::Rsa(Box::new(rsa::pkcs1v15::SigningKey::new(key))),
+                public_key,
+            )
+        };
+
+        let public_key = public_key.map_err(|e| format!("failed to encode public key: {e}"))?;
+        let certificate_key = certificate
+            .tbs_certificate
+            .subject_public_key_info
+            .to_der()
+            .map_err(|e| format!("failed to encode certificate public key: {e}"))?;
+
+        if public_key.as_bytes() != certificate_key.as_slice() {
+            return Err("the private key doesn't match the certificate".to_string());
+        }
+
+        Ok(Self {
+            key: Arc::new(key),
+            certificate: Arc::new(certificate),
+        })
+    }
+
+    /// Signs the whole PDF with a detached CAdES signature (PAdES baseline B-B)
+    pub fn sign_pdf(&self, pdf: Vec<u8>) -> Result<Vec<u8>, Error> {
+        let mut prepared = pdf::prepare(&pdf).map_err(Error::Signing)?;
+
+        let mut hasher = Sha256::new();
+        for range in prepared.signed_ranges() {
+            hasher.update(range);
+        }
+
+        let signature = cms::sign(&self.key, &self.certificate, &hasher.finalize())
+            .map_err(|e| Error::Signing(format!("failed to create signature: {e}")))?;
+
+        prepared
+            .insert_signature(&signature)
+            .map_err(Error::Signing)?;
+        Ok(prepared.into_bytes())
+    }
+
+    /// Verifies that the PDF is signed with the certificate of the service and that the
+    /// signature covers the whole file.
+    pub fn verify_pdf(&self, file: &InvoiceAttachment) -> Result<Verification, Error> {
+        let signatures = pdf::signatures(&crate::merge::load_pdf(file)?);
+
+        let Some(signature) = signatures.last() else {
+            return Ok(Verification::invalid("The PDF is not signed"));
+        };
+
+        if let Err(reason) = signature.check_byte_range(&file.bytes) {
+            return Ok(Verification::invalid(reason));
+        }
+
+        let mut hasher = Sha256::new();
+        for range in signature.signed_ranges(&file.bytes) {
+            hasher.update(range);
+        }
+
+        if let Err(reason) = cms::verify(&signature.contents, &hasher.finalize(), &self.certificate)
+        {
+            return Ok(Verification::invalid(reason));
+        }
+
+        Ok(Verification {
+            valid: true,
+            signer: Some(self.certificate.tbs_certificate.subject.to_string()),
+            signed_at: signature.signed_at.clone(),
+            reason: None,
+        })
+    }
+}
+
+impl<S> OptionalFromRequestParts<S> for Signer
+where
+    S: Send + Sync,
+    State: FromRef<S>,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(
+        _parts: &mut Parts,
+        state: &S,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        let state = State::from_ref(state);
+        Ok(state.signer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn read_attachment(filename: &str) -> InvoiceAttachment {
+        InvoiceAttachment {
+            filename: filename.to_string(),
+            bytes: fs::read(filename).expect("Failed to read PDF file"),
+        }
+    }
+
+    fn test_signer(name: &str) -> Signer {
+        let mut params =
+            rcgen::CertificateParams::new(vec![]).expect("Failed to create certificate params");
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, name);
+        let key = rcgen::KeyPair::generate().expect("Failed to generate key");
+        let certificate = params
+            .self_signed(&key)
+            .expect("Failed to sign certificate");
+
+        Signer::from_pem(&certificate.pem(), &key.serialize_pem()).expect("Failed to create signer")
+    }
+
+    fn signed_pdf(signer: &Signer) -> InvoiceAttachment {
+        let pdf = crate::merge::merge_pdf(vec![read_attachment("testdata/test.pdf").into()])
+            .expect("Failed to merge PDF");
+
+        InvoiceAttachment {
+            filename: "signed.pdf".to_string(),
+            bytes: signer.sign_pdf(pdf).expect("Failed to sign PDF"),
+        }
+    }
+
+    #[test]
+    fn test_signed_pdf_is_valid() {
+        let signer = test_signer("Laskugeneraattori");
+        let signed = signed_pdf(&signer);
+
+        let document = lopdf::Document::load_mem(&signed.bytes).expect("Failed to load signed PDF");
+        assert_eq!(document.get_pages().len(), 1);
+
+        let verification = signer.verify_pdf(&signed).expect("Failed to verify PDF");
+        assert!(verification.valid, "{:?}", verification.reason);
+        assert_eq!(verification.signer.as_deref(), Some("CN=Laskugeneraattori"));
+        assert!(verification.signed_at.is_some());
+        assert!(verification.reason.is_none());
+    }
+
+    #[test]
+    fn test_modified_pdf_is_invalid() {
+        let signer = test_signer("Laskugeneraattori");
+        let mut signed = signed_pdf(&signer);
+
+        // Change a byte of the page content, which is covered by the signature
+        let position = signed
+            .bytes
+            .windows(b"/MediaBox".len())
+            .position(|window| window == b"/MediaBox")
+            .expect("Failed to find the media box");
+        signed.bytes[position + 1] = b'm';
+
+        let verification = signer.verify_pdf(&signed).expect("Failed to verify PDF");
+        assert!(!verification.valid);
+        assert_eq!(
+            verification.reason.as_deref(),
+            Some("The PDF has been modified after signing")
+        );
+    }
+
+    #[test]
+    fn test_appended_pdf_is_invalid() {
+        let signer = test_signer("Laskugeneraattori");
+        let mut signed = signed_pdf(&signer);
+        signed
+            .bytes
+            .extend_from_slice(b"\n% appended after signing\n");
+
+        let verification = signer.verify_pdf(&signed).expect("Failed to verify PDF");
+        assert!(!verification.valid);
+        assert_eq!(
+            verification.reason.as_deref(),
+            Some("The signature doesn't cover the whole PDF")
+        );
+    }
+
+    #[test]
+    fn test_pdf_signed_with_other_certificate_is_invalid() {
+        let signed = signed_pdf(&test_signer("Someone else"));
+
+        let verification = test_signer("Laskugeneraattori")
+            .verify_pdf(&signed)
+            .expect("Failed to verify PDF");
+        assert!(!verification.valid);
+        assert_eq!(
+            verification.reason.as_deref(),
+            Some("The PDF is signed with a different certificate")
+        );
+    }
+
+    #[test]
+    fn test_unsigned_pdf_is_invalid() {
+        let verification = test_signer("Laskugeneraattori")
+            .verify_pdf(&read_attachment("testdata/test.pdf"))
+            .expect("Failed to verify PDF");
+        assert!(!verification.valid);
+        assert_eq!(
+            verification.reason.as_deref(),
+            Some("The PDF is not signed")
+        );
+    }
+
+    #[test]
+    fn test_mismatched_key_is_rejected() {
+        let certificate = rcgen::generate_simple_self_signed(vec![])
+            .expect("Failed to generate certificate")
+            .cert;
+        let key = rcgen::KeyPair::generate().expect("Failed to generate key");
+
+        assert!(Signer::from_pem(&certificate.pem(), &key.serialize_pem()).is_err());
+    }
+}
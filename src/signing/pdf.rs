@@ -0,0 +1,281 @@
+use lopdf::{
+    dictionary, text_string, Dictionary, Document, Object, ObjectId, Stream, StringFormat,
+};
+use time::format_description::well_known::Rfc3339;
+
+/// The number of bytes reserved for the CMS signature
+const SIGNATURE_SIZE: usize = 8192;
+
+/// A byte range wide enough for any offset, replaced once the offsets are known
+const BYTE_RANGE_PLACEHOLDER: i64 = 9_999_999_999;
+
+/// The serialized signature dictionary from the start of the byte range to the start of the
+/// contents, as written by lopdf
+const PLACEHOLDER: &[u8] = b"/ByteRange[0 9999999999 9999999999 9999999999]/Contents<";
+
+/// A PDF with space reserved for a signature that covers everything else in the file
+pub(super) struct PreparedPdf {
+    bytes: Vec<u8>,
+    /// The offset of the `<` that starts the hexadecimal signature
+    contents_start: usize,
+    /// The offset right after the `>` that ends the hexadecimal signature
+    contents_end: usize,
+}
+
+impl PreparedPdf {
+    pub fn signed_ranges(&self) -> [&[u8]; 2] {
+        [
+            &self.bytes[..self.contents_start],
+            &self.bytes[self.contents_end..],
+        ]
+    }
+
+    pub fn insert_signature(&mut self, signature: &[u8]) -> Result<(), String> {
+        if signature.len() > SIGNATURE_SIZE {
+            return Err(format!(
+                "the signature is {} bytes, only {SIGNATURE_SIZE} bytes are reserved",
+                signature.len()
+            ));
+        }
+
+        let hex = signature
+            .iter()
+            .map(|byte| format!("{byte:02X}"))
+            .collect::<String>();
+        let start = self.contents_start + 1;
+        self.bytes[start..start + hex.len()].copy_from_slice(hex.as_bytes());
+        Ok(())
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Returns the id of the array in `key` of a dictionary, or inserts a new array in it
+fn array_in(document: &mut Document, dict_id: ObjectId, key: &[u8]) -> Result<ObjectId, String> {
+    let existing = document
+        .get_dictionary(dict_id)
+        .and_then(|dict| dict.get(key))
+        .cloned();
+
+    let array = match existing {
+        Ok(Object::Reference(id)) if document.get_object(id).and_then(Object::as_array).is_ok() => {
+            return Ok(id)
+        }
+        Ok(Object::Array(array)) => array,
+        _ => vec![],
+    };
+
+    let array_id = document.add_object(array);
+    document
+        .get_dictionary_mut(dict_id)
+        .map_err(|e| e.to_string())?
+        .set(key, array_id);
+    Ok(array_id)
+}
+
+fn push_to(document: &mut Document, array_id: ObjectId, object: impl Into<Object>) {
+    if let Ok(array) = document
+        .get_object_mut(array_id)
+        .and_then(Object::as_array_mut)
+    {
+        array.push(object.into());
+    }
+}
+
+/// Adds an invisible signature field with placeholders for the byte range and the signature
+/// to the first page of the PDF and serializes it
+pub(super) fn prepare(pdf: &[u8]) -> Result<PreparedPdf, String> {
+    let mut document =
+        Document::load_mem(pdf).map_err(|e| format!("failed to parse generated PDF: {e}"))?;
+
+    let catalog_id = document
+        .trailer
+        .get(b"Root")
+        .and_then(Object::as_reference)
+        .map_err(|e| format!("failed to find catalog: {e}"))?;
+    let page_id = *document
+        .get_pages()
+        .values()
+        .next()
+        .ok_or("the PDF has no pages")?;
+
+    let signature_id = document.add_object(dictionary! {
+        "Type" => "Sig",
+        "Filter" => "Adobe.PPKLite",
+        "SubFilter" => "ETSI.CAdES.detached",
+        "ByteRange" => vec![0.into(), BYTE_RANGE_PLACEHOLDER.into(), BYTE_RANGE_PLACEHOLDER.into(), BYTE_RANGE_PLACEHOLDER.into()],
+        "Contents" => Object::String(vec![0; SIGNATURE_SIZE], StringFormat::Hexadecimal),
+        "M" => Object::from(time::OffsetDateTime::now_utc()),
+    });
+
+    // The field is merged with its widget annotation, which has no size and is always printed
+    // and locked as PDF/A requires. PDF/A also requires an appearance, so it gets an empty one.
+    let appearance_id = document.add_object(Stream::new(
+        dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Form",
+            "BBox" => vec![0.into(), 0.into(), 0.into(), 0.into()],
+        },
+        vec![],
+    ));
+    let field_id = document.add_object(dictionary! {
+        "Type" => "Annot",
+        "Subtype" => "Widget",
+        "FT" => "Sig",
+        "T" => text_string("Allekirjoitus"),
+        "V" => signature_id,
+        "Rect" => vec![0.into(), 0.into(), 0.into(), 0.into()],
+        "F" => 132,
+        "P" => page_id,
+        "AP" => dictionary! { "N" => appearance_id },
+    });
+
+    let annots_id = array_in(&mut document, page_id, b"Annots")?;
+    push_to(&mut document, annots_id, field_id);
+
+    let acro_form = document
+        .get_dictionary(catalog_id)
+        .and_then(|catalog| catalog.get(b"AcroForm"))
+        .and_then(|form| document.dereference(form))
+        .and_then(|(_, form)| form.as_dict())
+        .cloned()
+        .unwrap_or_else(|_| Dictionary::new());
+    let acro_form_id = document.add_object(acro_form);
+    document
+        .get_dictionary_mut(catalog_id)
+        .map_err(|e| e.to_string())?
+        .set("AcroForm", acro_form_id);
+
+    let fields_id = array_in(&mut document, acro_form_id, b"Fields")?;
+    push_to(&mut document, fields_id, field_id);
+    // SignaturesExist | AppendOnly
+    document
+        .get_dictionary_mut(acro_form_id)
+        .map_err(|e| e.to_string())?
+        .set("SigFlags", 3);
+
+    let mut bytes = Vec::new();
+    document
+        .save_to(&mut bytes)
+        .map_err(|e| format!("failed to write PDF: {e}"))?;
+
+    let placeholder = bytes
+        .windows(PLACEHOLDER.len())
+        .position(|window| window == PLACEHOLDER)
+        .ok_or("failed to find the signature placeholder")?;
+
+    let contents_start = placeholder + PLACEHOLDER.len() - 1;
+    let contents_end = contents_start + 2 * SIGNATURE_SIZE + 2;
+    if bytes.get(contents_end - 1) != Some(&b'>') {
+        return Err("failed to find the end of the signature placeholder".to_string());
+    }
+
+    // Fill in the byte range, padded with spaces to keep the offsets intact
+    let byte_range_start = placeholder + b"/ByteRange".len();
+    let byte_range_end = contents_start - b"/Contents".len();
+    let byte_range = format!(
+        "[0 {contents_start} {contents_end} {}]",
+        bytes.len() - contents_end
+    );
+    let padded = format!(
+        "{byte_range:<width$}",
+        width = byte_range_end - byte_range_start
+    );
+    bytes[byte_range_start..byte_range_end].copy_from_slice(padded.as_bytes());
+
+    Ok(PreparedPdf {
+        bytes,
+        contents_start,
+        contents_end,
+    })
+}
+
+/// A signature of a PDF, as found in its signature fields
+pub(super) struct Signature {
+    byte_range: Vec<i64>,
+    /// The CMS signature, possibly followed by padding
+    pub contents: Vec<u8>,
+    pub signed_at: Option<String>,
+}
+
+impl Signature {
+    /// Checks that the signature covers the whole file except for the signature itself, i.e.
+    /// that nothing has been appended to the file after signing
+    pub fn check_byte_range(&self, pdf: &[u8]) -> Result<(), String> {
+        let covers_file = match self.byte_range[..] {
+            [0, first_end, second_start, second_len]
+                if 0 < first_end
+                    && first_end < second_start
+                    && second_start.checked_add(second_len) == Some(pdf.len() as i64) =>
+            {
+                // Only the signature itself may be left out
+                let gap = &pdf[first_end as usize..second_start as usize];
+                gap.len() >= 2
+                    && gap[0] == b'<'
+                    && gap[gap.len() - 1] == b'>'
+                    && gap[1..gap.len() - 1].iter().all(u8::is_ascii_hexdigit)
+            }
+            _ => false,
+        };
+
+        match covers_file {
+            true => Ok(()),
+            false => Err("The signature doesn't cover the whole PDF".to_string()),
+        }
+    }
+
+    /// The ranges of the file covered by the signature. The byte range must have been checked.
+    pub fn signed_ranges<'a>(&self, pdf: &'a [u8]) -> [&'a [u8]; 2] {
+        let (first_end, second_start) = (self.byte_range[1] as usize, self.byte_range[2] as usize);
+        [&pdf[..first_end], &pdf[second_start..]]
+    }
+}
+
+/// Returns the signatures of the signature fields of the PDF
+pub(super) fn signatures(document: &Document) -> Vec<Signature> {
+    let fields = document
+        .catalog()
+        .and_then(|catalog| catalog.get(b"AcroForm"))
+        .and_then(|form| document.dereference(form))
+        .and_then(|(_, form)| form.as_dict())
+        .and_then(|form| form.get(b"Fields"))
+        .and_then(|fields| document.dereference(fields))
+        .and_then(|(_, fields)| fields.as_array())
+        .map(|fields| fields.as_slice())
+        .unwrap_or_default();
+
+    fields
+        .iter()
+        .filter_map(|field| document.dereference(field).ok())
+        .filter_map(|(_, field)| field.as_dict().ok())
+        .filter(|field| matches!(field.get(b"FT").and_then(Object::as_name), Ok(b"Sig")))
+        .filter_map(|field| field.get(b"V").ok())
+        .filter_map(|value| document.dereference(value).ok())
+        .filter_map(|(_, value)| value.as_dict().ok())
+        .filter_map(|value| {
+            let byte_range = value
+                .get(b"ByteRange")
+                .and_then(Object::as_array)
+                .ok()?
+                .iter()
+                .map(Object::as_i64)
+                .collect::<Result<Vec<_>, _>>()
+                .ok()?;
+            let contents = value.get(b"Contents").and_then(Object::as_str).ok()?;
+            let signed_at = value
+                .get(b"M")
+                .ok()
+                .and_then(Object::as_datetime)
+                .and_then(|date| time::OffsetDateTime::try_from(date).ok())
+                .and_then(|date| date.format(&Rfc3339).ok());
+
+            Some(Signature {
+                byte_range,
+                contents: contents.to_vec(),
+                signed_at,
+            })
+        })
+        .collect()
+}
@@ -1,23 +1,57 @@
+use crate::idempotency::IdempotencyStore;
+use crate::jobs::{worker::Worker, JobQueue, VoucherNumbering};
 use crate::mailgun::MailgunClient;
+use crate::outbound::OutboundInvoices;
+use crate::signing::Signer;
+use crate::webhooks::Webhooks;
 
 use axum::extract::FromRef;
+use std::time::Duration;
 
 #[derive(FromRef, Clone)]
 pub struct State {
     pub mailgun_client: Option<MailgunClient>,
+    pub signer: Option<Signer>,
+    pub idempotency: IdempotencyStore,
+    pub jobs: JobQueue,
+    pub webhooks: Option<Webhooks>,
+    pub outbound: Option<OutboundInvoices>,
     pub for_garde: (),
 }
 
 pub async fn new() -> State {
     dotenv::dotenv().ok();
 
-    State {
+    let state = State {
         mailgun_client: match crate::CONFIG.mailgun.clone().try_into() {
             Err(e) if !crate::CONFIG.mailgun.disable => {
                 panic!("failed to initialize mailgun client: {e}")
             }
             res => res.ok(),
         },
+        signer: match crate::CONFIG.signing.clone().try_into() {
+            Err(e) if crate::CONFIG.signing.certificate.is_some() => {
+                panic!("failed to initialize signer: {e}")
+            }
+            res => res.ok(),
+        },
+        idempotency: IdempotencyStore::new(Duration::from_secs(
+            crate::CONFIG.idempotency_window_secs,
+        )),
+        jobs: JobQueue::new(crate::CONFIG.job_dir.clone())
+            .numbering(VoucherNumbering::from_config()),
+        webhooks: Webhooks::from_config(),
+        outbound: OutboundInvoices::from_config(),
         for_garde: (),
-    }
+    };
+
+    Worker::new(
+        state.jobs.clone(),
+        state.mailgun_client.clone(),
+        state.signer.clone(),
+    )
+    .outbound(state.outbound.clone())
+    .spawn();
+
+    state
 }
@@ -0,0 +1,84 @@
+use crate::error::Error;
+
+use hmac::{Hmac, Mac};
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256};
+use std::{fs, io::ErrorKind, path::Path};
+
+/// Returns the bytes as lowercase hexadecimal
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Returns the hexadecimal SHA-256 digest of the bytes
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    hex(&Sha256::digest(bytes))
+}
+
+/// Returns the hexadecimal HMAC-SHA256 of the parts concatenated
+pub fn hmac_sha256_hex(key: &[u8], parts: &[&[u8]]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    for part in parts {
+        mac.update(part);
+    }
+
+    hex(&mac.finalize().into_bytes())
+}
+
+/// Returns the lines of the file that aren't blank, or none if the file doesn't exist
+pub fn read_lines(path: &Path) -> Result<Vec<String>, Error> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok(contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Returns the values of a file with a JSON value per line
+pub fn read_jsonl<T: DeserializeOwned>(path: &Path) -> Result<Vec<T>, Error> {
+    read_lines(path)?
+        .iter()
+        .map(|line| Ok(serde_json::from_str(line)?))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(hex(&[0x00, 0x0f, 0xab, 0xff]), "000fabff");
+        assert_eq!(
+            sha256_hex(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+
+    #[test]
+    fn test_hmac_of_parts() {
+        assert_eq!(
+            hmac_sha256_hex(
+                b"key",
+                &[b"The quick brown fox ", b"jumps over the lazy dog"]
+            ),
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+
+    #[test]
+    fn test_blank_lines_are_skipped() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("values.jsonl");
+        assert!(read_lines(&path).unwrap().is_empty());
+
+        fs::write(&path, "1\n\n  \n2\n").unwrap();
+        assert_eq!(read_jsonl::<u32>(&path).unwrap(), vec![1, 2]);
+    }
+}
@@ -0,0 +1,373 @@
+use crate::api::invoices::Invoice;
+use crate::error::Error;
+use crate::jobs::{retry_delay, Approval, InvoiceState};
+use crate::state::State;
+use crate::util::{hmac_sha256_hex, read_jsonl};
+use crate::CONFIG;
+
+use axum::{
+    extract::{FromRef, OptionalFromRequestParts},
+    http::request::Parts,
+};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::PathBuf,
+    sync::{Arc, LazyLock, Mutex, PoisonError},
+    time::Duration,
+};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use utoipa::ToSchema;
+
+/// The attempts to deliver the webhooks, stored in the file given by `WEBHOOK_LOG`
+pub static DELIVERIES: LazyLock<DeliveryLog> =
+    LazyLock::new(|| DeliveryLog::new(CONFIG.webhook_log.clone()));
+
+/// How long a webhook endpoint has to respond
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The header with the signature of the payload
+pub const SIGNATURE_HEADER: &str = "x-laskugeneraattori-signature";
+
+/// What happened to an invoice
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum WebhookEvent {
+    #[serde(rename = "invoice.submitted")]
+    Submitted,
+    #[serde(rename = "invoice.approved")]
+    Approved,
+    #[serde(rename = "invoice.paid")]
+    Paid,
+    #[serde(rename = "invoice.rejected")]
+    Rejected,
+}
+
+impl WebhookEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            WebhookEvent::Submitted => "invoice.submitted",
+            WebhookEvent::Approved => "invoice.approved",
+            WebhookEvent::Paid => "invoice.paid",
+            WebhookEvent::Rejected => "invoice.rejected",
+        }
+    }
+}
+
+impl From<&InvoiceState> for WebhookEvent {
+    fn from(state: &InvoiceState) -> Self {
+        match state {
+            InvoiceState::Submitted => WebhookEvent::Submitted,
+            InvoiceState::Approved { .. } => WebhookEvent::Approved,
+            InvoiceState::Paid { .. } => WebhookEvent::Paid,
+            InvoiceState::Rejected { .. } => WebhookEvent::Rejected,
+        }
+    }
+}
+
+/// The invoice as sent to the webhook endpoints. The submitter's contact and bank details are
+/// left out, as the endpoints are run by third parties.
+#[derive(Serialize)]
+struct InvoiceSummary<'a> {
+    id: &'a str,
+    /// The name of the submitter
+    submitter: &'a str,
+    /// The sum of the rows in cents
+    amount: i64,
+}
+
+impl<'a> From<&'a Invoice> for InvoiceSummary<'a> {
+    fn from(invoice: &'a Invoice) -> Self {
+        Self {
+            id: &invoice.id,
+            submitter: &invoice.recipient_name,
+            amount: invoice
+                .rows
+                .iter()
+                .map(|row| i64::from(row.unit_price))
+                .sum(),
+        }
+    }
+}
+
+/// The JSON body sent to the webhook endpoints
+#[derive(Serialize)]
+struct Payload<'a> {
+    /// The ID of the delivery, the same for every attempt
+    id: &'a str,
+    event: WebhookEvent,
+    /// The time of the event in RFC 3339 format
+    timestamp: String,
+    invoice: InvoiceSummary<'a>,
+    state: &'a InvoiceState,
+    /// The treasurer's markings, including the voucher number, once the invoice is approved
+    #[serde(skip_serializing_if = "Option::is_none")]
+    approval: Option<&'a Approval>,
+}
+
+/// Returns the hexadecimal HMAC-SHA256 of the payload, sent as `sha256=<signature>`
+pub fn signature(secret: &str, payload: &[u8]) -> String {
+    hmac_sha256_hex(secret.as_bytes(), &[payload])
+}
+
+/// An attempt to deliver a webhook
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Delivery {
+    /// The ID of the delivery, the same for every attempt
+    pub id: String,
+    pub event: WebhookEvent,
+    pub invoice_id: String,
+    pub url: String,
+    /// The number of the attempt, starting from 1
+    pub attempt: u32,
+    /// The time of the attempt in RFC 3339 format
+    pub timestamp: String,
+    /// The HTTP status the endpoint responded with
+    pub status: Option<u16>,
+    /// Why the attempt failed
+    pub error: Option<String>,
+    pub delivered: bool,
+}
+
+/// A log of the delivery attempts, one JSON object per line
+pub struct DeliveryLog {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl DeliveryLog {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn append(&self, delivery: &Delivery) -> Result<(), Error> {
+        let mut line = serde_json::to_vec(delivery)?;
+        line.push(b'\n');
+
+        let _lock = self.lock.lock().unwrap_or_else(PoisonError::into_inner);
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(&line)?;
+        Ok(())
+    }
+
+    /// Returns the delivery attempts, oldest first
+    pub fn entries(&self) -> Result<Vec<Delivery>, Error> {
+        let _lock = self.lock.lock().unwrap_or_else(PoisonError::into_inner);
+        read_jsonl(&self.path)
+    }
+}
+
+/// Sends the events of the invoices to the endpoints given by `WEBHOOK_URLS`
+#[derive(Clone)]
+pub struct Webhooks {
+    client: reqwest::Client,
+    urls: Arc<[String]>,
+    secret: Arc<str>,
+}
+
+impl Webhooks {
+    /// Returns `None` if no endpoints are configured
+    pub fn from_config() -> Option<Self> {
+        let secret = CONFIG.webhook_secret.as_ref()?;
+        if CONFIG.webhook_urls.is_empty() {
+            return None;
+        }
+
+        let client = reqwest::Client::builder()
+            .timeout(TIMEOUT)
+            .build()
+            .expect("failed to build the webhook client");
+
+        Some(Self {
+            client,
+            urls: CONFIG.webhook_urls.clone().into(),
+            secret: secret.as_str().into(),
+        })
+    }
+
+    /// Delivers the event to every endpoint in the background, retrying failed deliveries
+    pub fn send(&self, invoice: &Invoice, state: &InvoiceState, approval: Option<&Approval>) {
+        let id = uuid::Uuid::new_v4().to_string();
+        let event = WebhookEvent::from(state);
+        let payload = Payload {
+            id: &id,
+            event,
+            timestamp: OffsetDateTime::now_utc()
+                .format(&Rfc3339)
+                .unwrap_or_default(),
+            invoice: InvoiceSummary::from(invoice),
+            state,
+            approval,
+        };
+        let body = match serde_json::to_vec(&payload) {
+            Ok(body) => body,
+            Err(e) => {
+                error!("Failed to serialize webhook {id}: {e}");
+                return;
+            }
+        };
+        let signature = format!("sha256={}", signature(&self.secret, &body));
+
+        for url in self.urls.iter() {
+            let delivery = Delivery {
+                id: id.clone(),
+                event,
+                invoice_id: invoice.id.clone(),
+                url: url.clone(),
+                attempt: 0,
+                timestamp: String::new(),
+                status: None,
+                error: None,
+                delivered: false,
+            };
+            let webhooks = self.clone();
+            let body = body.clone();
+            let signature = signature.clone();
+
+            tokio::spawn(async move { webhooks.deliver(delivery, body, signature).await });
+        }
+    }
+
+    async fn deliver(&self, mut delivery: Delivery, body: Vec<u8>, signature: String) {
+        loop {
+            delivery.attempt += 1;
+            delivery.timestamp = OffsetDateTime::now_utc()
+                .format(&Rfc3339)
+                .unwrap_or_default();
+
+            let response = self
+                .client
+                .post(&delivery.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header("x-laskugeneraattori-event", delivery.event.name())
+                .header("x-laskugeneraattori-delivery", &delivery.id)
+                .header(SIGNATURE_HEADER, &signature)
+                .body(body.clone())
+                .send()
+                .await;
+
+            (delivery.status, delivery.error) = match response {
+                Ok(response) if response.status().is_success() => {
+                    (Some(response.status().as_u16()), None)
+                }
+                Ok(response) => (
+                    Some(response.status().as_u16()),
+                    Some(format!("The endpoint responded with {}", response.status())),
+                ),
+                Err(e) => (None, Some(e.to_string())),
+            };
+            delivery.delivered = delivery.error.is_none();
+
+            let entry = delivery.clone();
+            match tokio::task::spawn_blocking(move || DELIVERIES.append(&entry)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => error!("Failed to log the delivery of webhook {}: {e}", delivery.id),
+                Err(e) => error!("Failed to log the delivery of webhook {}: {e}", delivery.id),
+            }
+
+            let Some(e) = &delivery.error else {
+                return;
+            };
+            if delivery.attempt >= CONFIG.webhook_max_attempts {
+                error!(
+                    "Giving up on webhook {} to {} after {} attempts: {e}",
+                    delivery.id, delivery.url, delivery.attempt
+                );
+                return;
+            }
+
+            let delay = retry_delay(
+                Duration::from_secs(CONFIG.webhook_retry_base_secs),
+                delivery.attempt,
+            );
+            warn!(
+                "Attempt {} of webhook {} to {} failed, retrying in {delay:?}: {e}",
+                delivery.attempt, delivery.id, delivery.url
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+impl<S> OptionalFromRequestParts<S> for Webhooks
+where
+    S: Send + Sync,
+    State: FromRef<S>,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(
+        _parts: &mut Parts,
+        state: &S,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        let state = State::from_ref(state);
+        Ok(state.webhooks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signature() {
+        assert_eq!(
+            signature("key", b"The quick brown fox jumps over the lazy dog"),
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+
+    #[test]
+    fn test_event_names() {
+        for event in [
+            WebhookEvent::Submitted,
+            WebhookEvent::Approved,
+            WebhookEvent::Paid,
+            WebhookEvent::Rejected,
+        ] {
+            assert_eq!(
+                serde_json::to_value(event).unwrap(),
+                serde_json::Value::from(event.name())
+            );
+        }
+    }
+
+    #[test]
+    fn test_delivery_log() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let log = DeliveryLog::new(dir.path().join("webhooks.jsonl"));
+        assert!(log.entries().unwrap().is_empty());
+
+        let delivery = Delivery {
+            id: "delivery-id".to_string(),
+            event: WebhookEvent::Paid,
+            invoice_id: "invoice-id".to_string(),
+            url: "http://localhost/hook".to_string(),
+            attempt: 1,
+            timestamp: "2025-05-20T12:00:00Z".to_string(),
+            status: Some(500),
+            error: Some("The endpoint responded with 500".to_string()),
+            delivered: false,
+        };
+        log.append(&delivery).unwrap();
+        log.append(&Delivery {
+            attempt: 2,
+            status: Some(200),
+            error: None,
+            delivered: true,
+            ..delivery.clone()
+        })
+        .unwrap();
+
+        let entries = log.entries().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0], delivery);
+        assert!(entries[1].delivered);
+    }
+}
@@ -0,0 +1,33 @@
+#import "/common.typ": price
+
+#let title = "Hyväksyttävät laskut"
+
+#set document(title: title)
+#set page(
+  footer: [
+    #align(right)[Laskugeneraattori #VERSION #link("https://github.com/Tietokilta/laskugeneraattori/commit/" + COMMIT_HASH)[#COMMIT_HASH.slice(0, 7)]]
+  ],
+)
+#set text(lang: "fi")
+
+= #title
+
+#if data.meeting != none [
+  *Kokous*: #data.meeting \
+]
+*Päivämäärä*: #datetime.today().display("[day padding:none].[month padding:none].[year]") \
+*Laskuja*: #data.invoices.len()
+
+#table(columns: (auto, 1fr, 2fr, 1fr, auto),
+  align: (right, left, left, left, right),
+  table.header([*\#*], [*Lähettäjä*], [*Aihe*], [*Kategoria*], [*Summa*]),
+  ..data.invoices.enumerate(start: 1).map(((i, it)) => (
+    [#i],
+    [#it.submitter],
+    [#it.subject \ #text(size: 0.8em, fill: gray.darken(40%), it.id)],
+    [#it.categories.join(", ")],
+    [#price(it.total) €],
+  )).flatten(),
+  table.cell(colspan: 4)[*Yhteensä*],
+  [*#price(data.invoices.map(it => it.total).sum(default: 0)) €*],
+)
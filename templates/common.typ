@@ -0,0 +1,24 @@
+// Formats an amount of cents as euros without the currency, e.g. 1234 as 12,34
+#let price(number) = {
+  let num_as_str = str(number)
+  let whole_nums="0"
+  if num_as_str.len() > 2 {
+    whole_nums = num_as_str.slice(0, -2)
+  }
+  let rem = "00"
+  if num_as_str.len() == 1 {
+    rem = "0" + num_as_str
+  } else if num_as_str.len() >= 2 {
+    rem = num_as_str.slice(-2)
+  }
+  whole_nums+","+rem
+}
+
+// The bank barcode of the invoice, or a note that foreign accounts have none
+#let barcode_line(data) = {
+  if data.at("barcode", default: none) != none [
+    *Pankkiviivakoodi*: #data.barcode \
+  ] else if data.at("foreign_account", default: false) [
+    *Pankkiviivakoodi*: ei saatavilla ulkomaiselle tilille (barcode not available for foreign account) \
+  ]
+}
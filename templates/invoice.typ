@@ -1,17 +1,13 @@
-#let price(number) = {
-  let num_as_str = str(number)
-  let whole_nums="0"
-  if num_as_str.len() > 2 {
-    whole_nums = num_as_str.slice(0, -2)
-  }
-  let rem = "00"
-  if num_as_str.len() == 1 {
-    rem = "0" + num_as_str
-  } else if num_as_str.len() >= 2 {
-    rem = num_as_str.slice(-2)
-  }
-  whole_nums+","+rem
-}
+#import "/common.typ": barcode_line, price
+
+// The description tells what the invoice is for, which is what the subject of a document means
+// in its metadata
+#set document(
+  title: data.subject,
+  author: data.recipient_name,
+  description: if data.description.trim() == "" { data.subject } else { data.description },
+  keywords: data.keywords,
+)
 
 #set page(
   background: [
@@ -29,27 +25,40 @@
 )
 #set text(lang: "fi")
 
+// The date the invoice was submitted in Finland, which the service dates the document by
+#let issued = datetime.today()
+#let date(it) = it.display("[day padding:none].[month padding:none].[year]")
+
 #let writeline(length) = {
   line(length: length, start: (0pt, 1em))
 }
 
+// The treasurer's markings, filled in when the invoice is approved with them
+#let approval = data.at("approval", default: none)
+#let stamp(key) = if approval == none { none } else { approval.at(key) }
+#let field(value) = if value == none {
+  writeline(5em)
+} else {
+  box(width: 5em, stroke: (bottom: 0.5pt), align(center, strong(str(value))))
+}
+
 #move(dx: -10%, dy: -5%, box(
   width: 120%,
   inset: 1em,
   stroke: black,
 )[
-  #let year = datetime.today().year()
+  #let year = if approval == none { issued.year() } else { approval.year }
   == Rahastonhoitajan merkintöjä:
   #stack(dir: ltr)[Hyväksytty][
-    #writeline(5em)
+    #field(stamp("day"))
   ][.][
-    #writeline(5em)
+    #field(stamp("month"))
   ][.#year][
     #h(1em) TiKH:n kokouksessa
   ][
-    #writeline(5em)
+    #field(stamp("meeting"))
   ][/#year kohdistettavaksi tilille][
-    #writeline(5em)
+    #field(stamp("account"))
   ]
   #stack(dir: ltr)[Maksettu][
     #writeline(5em)
@@ -60,7 +69,7 @@
   ][Käteinen][
     #writeline(5em)
   ][#h(2em) TOSITE][
-    #writeline(5em)
+    #field(stamp("voucher"))
   ]
 ])
 
@@ -70,10 +79,16 @@
 *Postinumero ja -toimipaikka*: #data.address.zip #data.address.city \
 *Puhelin*: #link("tel:" + data.phone_number) \
 *E-mail*: #link("mailto:" + data.recipient_email) \
+#if data.at("business_id", default: none) != none [*Y-tunnus*: #data.business_id \ ]
+#if data.at("vat_number", default: none) != none [*ALV-tunniste*: #data.vat_number \ ]
 
 #colbreak()
 = LASKU
-*Päivämäärä*: #datetime.today().display() \
+*Päivämäärä*: #issued.display() \
+#if data.at("due_date", default: none) != none {
+  let (year, month, day) = data.due_date.split("-").map(int)
+  [*Eräpäivä*: #date(datetime(year: year, month: month, day: day)) \ ]
+}
 ]
 
 == Tietokilta
@@ -91,8 +106,9 @@
 )
 
 *IBAN-tilinumero*: #data.bank_account_number \
+#if data.at("bic", default: none) != none [*BIC*: #data.bic \ ]
 
-*Pankkiviivakoodi*: #data.barcode \
+#barcode_line(data)
 
 
 === LIITTEET
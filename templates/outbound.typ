@@ -0,0 +1,60 @@
+#import "/common.typ": barcode_line, price
+
+#set document(
+  title: "Lasku " + data.number + ": " + data.subject,
+  author: data.issuer.name,
+)
+#set page(
+  footer: [
+    #align(right)[Laskugeneraattori #VERSION #link("https://github.com/Tietokilta/laskugeneraattori/commit/" + COMMIT_HASH)[#COMMIT_HASH.slice(0, 7)]]
+  ],
+)
+#set text(lang: "fi")
+
+// The date the invoice was issued in Finland, which the service dates the document by
+#let issued = datetime.today()
+#let (year, month, day) = data.due_date.split("-").map(int)
+#let due = datetime(year: year, month: month, day: day)
+#let date(it) = it.display("[day padding:none].[month padding:none].[year]")
+
+#columns(2)[
+*#data.issuer.name* \
+#if data.issuer.address != none [#data.issuer.address \ ]
+#if data.issuer.business_id != none [Y-tunnus: #data.issuer.business_id \ ]
+#if data.issuer.email != none [#link("mailto:" + data.issuer.email) \ ]
+
+#colbreak()
+= LASKU
+*Laskun numero*: #data.number \
+*Päivämäärä*: #date(issued) \
+*Eräpäivä*: #date(due) \
+*Viitenumero*: #data.reference \
+]
+
+#v(1em)
+*#data.customer.name* \
+#if data.customer.contact != none [#data.customer.contact \ ]
+#data.customer.address.street \
+#data.customer.address.zip #data.customer.address.city \
+#if data.customer.business_id != none [Y-tunnus: #data.customer.business_id \ ]
+
+#v(1em)
+*Aihe*: #data.subject \
+#if data.description != "" [#data.description \ ]
+
+#table(columns: (75%, 25%),
+  align: (left, right),
+  table.header([*Tuote*], [*Summa*]),
+  ..data.rows.map(it => ([#it.product], [#price(it.unit_price) €])).flatten(),
+  [*Yhteensä*], [*#price(data.total) €*],
+)
+
+=== Maksutiedot
+*Saaja*: #data.issuer.name \
+*IBAN-tilinumero*: #data.issuer.bank_account_number \
+#if data.issuer.at("bic", default: none) != none [*BIC*: #data.issuer.bic \ ]
+*Viitenumero*: #data.reference \
+*Eräpäivä*: #date(due) \
+*Summa*: #price(data.total) € \
+
+#barcode_line(data)
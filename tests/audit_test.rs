@@ -0,0 +1,140 @@
+mod common;
+
+use axum::http::StatusCode;
+use axum_test::TestServer;
+use common::{
+    create_invoice_form, create_test_server, fixtures::valid_invoice_json, test_audit_log,
+    wait_for_job, TEST_IP, TEST_IP_HEADER,
+};
+use serde_json::Value;
+
+const ADMIN_TOKEN: &str = "test-admin-token";
+
+async fn create_admin_server() -> TestServer {
+    std::env::set_var("ADMIN_TOKEN", ADMIN_TOKEN);
+    create_test_server().await
+}
+
+/// Submits an invoice and waits for it to be sent
+async fn submit_invoice(server: &TestServer) -> String {
+    let response = server
+        .post("/invoices")
+        .add_header(TEST_IP_HEADER, TEST_IP)
+        .multipart(create_invoice_form(&valid_invoice_json()))
+        .await;
+
+    response.assert_status(StatusCode::ACCEPTED);
+    let response_json: Value = response.json();
+    let id = response_json["id"].as_str().unwrap().to_string();
+    wait_for_job(server, &id).await;
+    id
+}
+
+#[tokio::test]
+async fn submission_is_recorded_in_audit_log() {
+    let server = create_admin_server().await;
+    let id = submit_invoice(&server).await;
+
+    let response = server
+        .get("/admin/audit-log")
+        .add_query_param("invoice_id", &id)
+        .authorization_bearer(ADMIN_TOKEN)
+        .await;
+
+    response.assert_status_ok();
+    let entries: Vec<Value> = response.json();
+    assert_eq!(entries.len(), 2);
+
+    // The submission is recorded when the invoice is queued and the PDF once it has been rendered
+    let submission = &entries[0];
+    assert_eq!(submission["event"], "submission");
+    assert_eq!(submission["actor"], "Test User <test@example.com>");
+    assert_eq!(submission["ip"], TEST_IP);
+    assert_eq!(submission["invoice_id"], id.as_str());
+    assert_eq!(submission["pdf_sha256"], Value::Null);
+    assert_eq!(submission["hash"].as_str().unwrap().len(), 64);
+
+    let rendered = &entries[1];
+    assert_eq!(rendered["event"], "rendered");
+    assert_eq!(rendered["actor"], "Test User <test@example.com>");
+    assert_eq!(rendered["ip"], TEST_IP);
+    assert_eq!(rendered["pdf_sha256"].as_str().unwrap().len(), 64);
+}
+
+#[tokio::test]
+async fn audit_log_query_is_recorded() {
+    let server = create_admin_server().await;
+
+    server
+        .get("/admin/audit-log")
+        .authorization_bearer(ADMIN_TOKEN)
+        .await
+        .assert_status_ok();
+
+    let response = server
+        .get("/admin/audit-log")
+        .add_query_param("event", "admin_action")
+        .authorization_bearer(ADMIN_TOKEN)
+        .await;
+
+    response.assert_status_ok();
+    let entries: Vec<Value> = response.json();
+    assert!(!entries.is_empty());
+    assert!(entries.iter().all(|entry| entry["actor"] == "admin"));
+}
+
+#[tokio::test]
+async fn failed_admin_action_is_recorded() {
+    let server = create_admin_server().await;
+    let id = submit_invoice(&server).await;
+
+    // The invoice was sent, so there is nothing to resend
+    server
+        .post(&format!("/admin/outbox/{id}/resend"))
+        .authorization_bearer(ADMIN_TOKEN)
+        .await
+        .assert_status(StatusCode::CONFLICT);
+
+    let response = server
+        .get("/admin/audit-log")
+        .add_query_param("invoice_id", &id)
+        .authorization_bearer(ADMIN_TOKEN)
+        .await;
+
+    response.assert_status_ok();
+    let entries: Vec<Value> = response.json();
+    let resend = entries.last().unwrap();
+    assert_eq!(resend["event"], "admin_action_failed");
+    assert_eq!(resend["actor"], "admin");
+    assert!(resend["details"]
+        .as_str()
+        .unwrap()
+        .starts_with("Resent an invoice from the outbox: "));
+    assert!(entries.iter().all(|entry| entry["event"] != "admin_action"));
+}
+
+#[tokio::test]
+async fn audit_log_requires_admin_token() {
+    let server = create_admin_server().await;
+
+    server
+        .get("/admin/audit-log")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    server
+        .get("/admin/audit-log")
+        .authorization_bearer("wrong-token")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn audit_log_chain_is_intact() {
+    let server = create_admin_server().await;
+    submit_invoice(&server).await;
+    submit_invoice(&server).await;
+
+    let count = laskugeneraattori::audit::verify(&test_audit_log()).unwrap();
+    assert!(count >= 2);
+}
@@ -86,3 +86,23 @@ pub fn invoice_with_attachment_descriptions(descriptions: Vec<&str>) -> Value {
     invoice["attachment_descriptions"] = json!(descriptions);
     invoice
 }
+
+pub fn invoice_with_due_date(due_date: &str) -> Value {
+    let mut invoice = valid_invoice_json();
+    invoice["due_date"] = json!(due_date);
+    invoice
+}
+
+pub fn invoice_from_company(business_id: &str, vat_number: &str) -> Value {
+    let mut invoice = valid_invoice_json();
+    invoice["business_id"] = json!(business_id);
+    invoice["vat_number"] = json!(vat_number);
+    invoice
+}
+
+pub fn invoice_with_account(bank_account_number: &str, bic: Option<&str>) -> Value {
+    let mut invoice = valid_invoice_json();
+    invoice["bank_account_number"] = json!(bank_account_number);
+    invoice["bic"] = json!(bic);
+    invoice
+}
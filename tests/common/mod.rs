@@ -1,8 +1,9 @@
 #[allow(dead_code)]
 pub mod fixtures;
 
+use axum::http::StatusCode;
 use axum_test::multipart::{MultipartForm, Part};
-use axum_test::TestServer;
+use axum_test::{TestResponse, TestServer};
 use laskugeneraattori::{api::app, state};
 use serde_json::Value;
 use std::path::Path;
@@ -19,6 +20,27 @@ pub fn setup_test_env() {
     std::env::set_var("RATE_LIMIT_PERIOD_SECS", "1");
     std::env::set_var("RATE_LIMIT_BURST_SIZE", "100");
     std::env::set_var("IP_EXTRACTOR_HEADER", TEST_IP_HEADER);
+    std::env::set_var("AUDIT_LOG", test_audit_log());
+    std::env::set_var(
+        "RECEIPT_INDEX",
+        std::env::temp_dir().join(format!(
+            "laskugeneraattori-receipts-{}.jsonl",
+            std::process::id()
+        )),
+    );
+    std::env::set_var(
+        "JOB_DIR",
+        std::env::temp_dir().join(format!("laskugeneraattori-jobs-{}", std::process::id())),
+    );
+}
+
+/// The audit log of the test binary, which is shared by all of its tests
+#[allow(dead_code)]
+pub fn test_audit_log() -> std::path::PathBuf {
+    std::env::temp_dir().join(format!(
+        "laskugeneraattori-audit-{}.jsonl",
+        std::process::id()
+    ))
 }
 
 #[allow(dead_code)]
@@ -29,6 +51,31 @@ pub async fn create_test_server() -> TestServer {
     TestServer::new(app).unwrap()
 }
 
+/// Polls the job of a submitted invoice until it has finished and returns its status
+#[allow(dead_code)]
+pub async fn wait_for_job(server: &TestServer, id: &str) -> Value {
+    for _ in 0..600 {
+        let job: Value = server.get(&format!("/jobs/{id}")).await.json();
+        if job["status"] == "completed" || job["status"] == "failed" {
+            return job;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+
+    panic!("Job {id} did not finish");
+}
+
+/// Asserts that the invoice was accepted and waits for it to be rendered and sent, returning the
+/// status of its job
+#[allow(dead_code)]
+pub async fn assert_job_completed(server: &TestServer, response: &TestResponse) -> Value {
+    response.assert_status(StatusCode::ACCEPTED);
+    let invoice: Value = response.json();
+    let job = wait_for_job(server, invoice["id"].as_str().unwrap()).await;
+    assert_eq!(job["status"], "completed", "{}", job["last_error"]);
+    job
+}
+
 #[allow(dead_code)]
 pub fn load_test_file(filename: &str) -> Vec<u8> {
     let path = Path::new(env!("CARGO_MANIFEST_DIR"))
@@ -0,0 +1,101 @@
+mod common;
+
+use axum::http::StatusCode;
+use axum_test::TestServer;
+use common::{
+    create_invoice_form, create_invoice_form_with_file, create_test_server,
+    fixtures::{invoice_with_attachment_descriptions, valid_invoice_json},
+    load_test_file, wait_for_job, TEST_IP, TEST_IP_HEADER,
+};
+use serde_json::Value;
+
+/// Submits an invoice and returns the response, which lists the possible duplicates
+async fn submit(server: &TestServer, form: axum_test::multipart::MultipartForm) -> Value {
+    let response = server
+        .post("/invoices")
+        .add_header(TEST_IP_HEADER, TEST_IP)
+        .multipart(form)
+        .await;
+
+    response.assert_status(StatusCode::ACCEPTED);
+    response.json()
+}
+
+fn duplicates_of<'a>(response: &'a Value, previous: &Value) -> Vec<&'a Value> {
+    response["possible_duplicates"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|duplicate| duplicate["previous_invoice_id"] == previous["id"])
+        .collect()
+}
+
+#[tokio::test]
+async fn resubmitted_receipt_is_flagged() {
+    let server = create_test_server().await;
+    let invoice = invoice_with_attachment_descriptions(vec!["Receipt"]);
+
+    let first = submit(
+        &server,
+        create_invoice_form_with_file(&invoice, "receipt.jpg", load_test_file("test.jpg")),
+    )
+    .await;
+
+    let mut other = invoice.clone();
+    other["subject"] = "Another invoice with the same receipt".into();
+    let second = submit(
+        &server,
+        create_invoice_form_with_file(&other, "kuitti.jpg", load_test_file("test.jpg")),
+    )
+    .await;
+
+    let duplicates = duplicates_of(&second, &first);
+    assert_eq!(duplicates.len(), 1);
+    assert_eq!(duplicates[0]["reason"], "identical_attachment");
+    assert_eq!(duplicates[0]["filename"], "kuitti.jpg");
+    assert_eq!(duplicates[0]["previous_filename"], "receipt.jpg");
+}
+
+#[tokio::test]
+async fn resubmitted_details_are_flagged() {
+    let server = create_test_server().await;
+    let mut invoice = valid_invoice_json();
+    invoice["subject"] = "Invoice submitted twice".into();
+
+    let first = submit(&server, create_invoice_form(&invoice)).await;
+
+    let second = submit(&server, create_invoice_form(&invoice)).await;
+    let duplicates = duplicates_of(&second, &first);
+    assert_eq!(duplicates.len(), 1);
+    assert_eq!(duplicates[0]["reason"], "same_details");
+}
+
+#[tokio::test]
+async fn duplicates_are_kept_with_job() {
+    let server = create_test_server().await;
+    let mut invoice = valid_invoice_json();
+    invoice["subject"] = "Invoice checked from the job".into();
+
+    let first = submit(&server, create_invoice_form(&invoice)).await;
+    let second = submit(&server, create_invoice_form(&invoice)).await;
+
+    let job = wait_for_job(&server, second["id"].as_str().unwrap()).await;
+    assert_eq!(job["status"], "completed");
+    assert_eq!(duplicates_of(&job["invoice"], &first).len(), 1);
+}
+
+#[tokio::test]
+async fn simultaneous_submissions_are_flagged() {
+    let server = create_test_server().await;
+    let mut invoice = valid_invoice_json();
+    invoice["subject"] = "Invoice submitted twice at once".into();
+
+    let (first, second) = tokio::join!(
+        submit(&server, create_invoice_form(&invoice)),
+        submit(&server, create_invoice_form(&invoice))
+    );
+
+    // Whichever was checked later is flagged as a duplicate of the other
+    let flagged = duplicates_of(&first, &second).len() + duplicates_of(&second, &first).len();
+    assert_eq!(flagged, 1);
+}
@@ -0,0 +1,93 @@
+mod common;
+
+use axum::http::StatusCode;
+use axum_test::{TestResponse, TestServer};
+use common::{
+    create_invoice_form, create_test_server, fixtures::valid_invoice_json, test_audit_log,
+    wait_for_job, TEST_IP, TEST_IP_HEADER,
+};
+use serde_json::Value;
+
+async fn submit(server: &TestServer, key: &str, invoice: &Value) -> TestResponse {
+    server
+        .post("/invoices")
+        .add_header(TEST_IP_HEADER, TEST_IP)
+        .add_header("Idempotency-Key", key)
+        .multipart(create_invoice_form(invoice))
+        .await
+}
+
+fn submissions_of(id: &str) -> usize {
+    std::fs::read_to_string(test_audit_log())
+        .unwrap()
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter(|entry| entry["event"] == "submission" && entry["invoice_id"] == id)
+        .count()
+}
+
+#[tokio::test]
+async fn retry_with_same_key_returns_original_response() {
+    let server = create_test_server().await;
+    let invoice = valid_invoice_json();
+
+    let first = submit(&server, "retry-same-body", &invoice).await;
+    first.assert_status(StatusCode::ACCEPTED);
+    let first_json: Value = first.json();
+
+    let retry = submit(&server, "retry-same-body", &invoice).await;
+    retry.assert_status(StatusCode::ACCEPTED);
+    let retry_json: Value = retry.json();
+
+    assert_eq!(first_json, retry_json);
+    let id = first_json["id"].as_str().unwrap();
+    wait_for_job(&server, id).await;
+    assert_eq!(submissions_of(id), 1, "The retry should not be processed");
+}
+
+#[tokio::test]
+async fn retry_with_same_key_and_different_body_fails() {
+    let server = create_test_server().await;
+    let invoice = valid_invoice_json();
+    let mut other = valid_invoice_json();
+    other["subject"] = "Other Invoice".into();
+
+    submit(&server, "retry-other-body", &invoice)
+        .await
+        .assert_status(StatusCode::ACCEPTED);
+
+    submit(&server, "retry-other-body", &other)
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn requests_with_different_keys_are_processed() {
+    let server = create_test_server().await;
+    let invoice = valid_invoice_json();
+
+    let first: Value = submit(&server, "first-key", &invoice).await.json();
+    let second: Value = submit(&server, "second-key", &invoice).await.json();
+
+    assert_ne!(first["id"], second["id"]);
+}
+
+#[tokio::test]
+async fn requests_without_key_are_processed() {
+    let server = create_test_server().await;
+    let invoice = valid_invoice_json();
+
+    let mut ids = vec![];
+    for _ in 0..2 {
+        let response = server
+            .post("/invoices")
+            .add_header(TEST_IP_HEADER, TEST_IP)
+            .multipart(create_invoice_form(&invoice))
+            .await;
+        response.assert_status(StatusCode::ACCEPTED);
+        let response_json: Value = response.json();
+        ids.push(response_json["id"].clone());
+    }
+
+    assert_ne!(ids[0], ids[1]);
+}
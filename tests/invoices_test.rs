@@ -3,16 +3,18 @@ mod common;
 use axum::http::StatusCode;
 use axum_test::multipart::{MultipartForm, Part};
 use common::{
-    create_invoice_form, create_invoice_form_with_file, create_invoice_form_with_files,
-    create_test_server,
+    assert_job_completed, create_invoice_form, create_invoice_form_with_file,
+    create_invoice_form_with_files, create_test_server,
     fixtures::{
-        invoice_with_attachment_descriptions, invoice_with_empty_rows, invoice_with_empty_subject,
+        invoice_from_company, invoice_with_account, invoice_with_attachment_descriptions,
+        invoice_with_due_date, invoice_with_empty_rows, invoice_with_empty_subject,
         invoice_with_invalid_iban, invoice_with_invalid_phone, invoice_with_long_subject,
         invoice_with_multiple_rows, invoice_with_negative_price, invoice_with_zero_price,
         valid_invoice_json,
     },
     load_test_file, TEST_IP, TEST_IP_HEADER,
 };
+use laskugeneraattori::local_time;
 use serde_json::Value;
 
 #[tokio::test]
@@ -27,7 +29,7 @@ async fn create_invoice_without_attachments_succeeds() {
         .multipart(form)
         .await;
 
-    response.assert_status(StatusCode::CREATED);
+    assert_job_completed(&server, &response).await;
 }
 
 #[tokio::test]
@@ -43,7 +45,7 @@ async fn create_invoice_with_jpg_attachment_succeeds() {
         .multipart(form)
         .await;
 
-    response.assert_status(StatusCode::CREATED);
+    assert_job_completed(&server, &response).await;
 }
 
 #[tokio::test]
@@ -59,7 +61,7 @@ async fn create_invoice_with_png_attachment_succeeds() {
         .multipart(form)
         .await;
 
-    response.assert_status(StatusCode::CREATED);
+    assert_job_completed(&server, &response).await;
 }
 
 #[tokio::test]
@@ -75,7 +77,7 @@ async fn create_invoice_with_pdf_attachment_succeeds() {
         .multipart(form)
         .await;
 
-    response.assert_status(StatusCode::CREATED);
+    assert_job_completed(&server, &response).await;
 }
 
 #[tokio::test]
@@ -95,7 +97,7 @@ async fn create_invoice_with_multiple_attachments_succeeds() {
         .multipart(form)
         .await;
 
-    response.assert_status(StatusCode::CREATED);
+    assert_job_completed(&server, &response).await;
 }
 
 #[tokio::test]
@@ -110,7 +112,7 @@ async fn create_invoice_with_multiple_rows_succeeds() {
         .multipart(form)
         .await;
 
-    response.assert_status(StatusCode::CREATED);
+    assert_job_completed(&server, &response).await;
 }
 
 #[tokio::test]
@@ -172,7 +174,212 @@ async fn allow_phone_number_without_contry_code() {
         .multipart(form)
         .await;
 
-    response.assert_status(StatusCode::CREATED);
+    assert_job_completed(&server, &response).await;
+}
+
+#[tokio::test]
+async fn bic_is_derived_for_finnish_account() {
+    let server = create_test_server().await;
+    let form = create_invoice_form(&valid_invoice_json());
+
+    let response = server
+        .post("/invoices")
+        .add_header(TEST_IP_HEADER, TEST_IP)
+        .multipart(form)
+        .await;
+
+    assert_job_completed(&server, &response).await;
+    let body: Value = response.json();
+    assert_eq!(body["bic"], "NDEAFIHH");
+    assert_eq!(body["barcode_note"], Value::Null);
+}
+
+#[tokio::test]
+async fn foreign_account_has_no_barcode() {
+    let server = create_test_server().await;
+    let form = create_invoice_form(&invoice_with_account("DE89 3704 0044 0532 0130 00", None));
+
+    let response = server
+        .post("/invoices")
+        .add_header(TEST_IP_HEADER, TEST_IP)
+        .multipart(form)
+        .await;
+
+    assert_job_completed(&server, &response).await;
+    let body: Value = response.json();
+    assert_eq!(body["bic"], Value::Null);
+    assert_eq!(
+        body["barcode_note"],
+        "barcode not available for foreign account"
+    );
+}
+
+#[tokio::test]
+async fn reject_non_sepa_account_without_bic() {
+    let server = create_test_server().await;
+    let form = create_invoice_form(&invoice_with_account(
+        "TR33 0006 1005 1978 6457 8413 26",
+        None,
+    ));
+
+    let response = server
+        .post("/invoices")
+        .add_header(TEST_IP_HEADER, TEST_IP)
+        .multipart(form)
+        .await;
+
+    response.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+    let body: Value = response.json();
+    let expected: Value = serde_json::json!({
+        "errors": [[
+            [["key", "data"], ["key", "bic"]],
+            { "message": "the BIC is required for accounts outside SEPA" }
+        ]]
+    });
+    assert_eq!(body, expected);
+}
+
+#[tokio::test]
+async fn non_sepa_account_with_bic_succeeds() {
+    let server = create_test_server().await;
+    let form = create_invoice_form(&invoice_with_account(
+        "TR33 0006 1005 1978 6457 8413 26",
+        Some("TGBATRIS"),
+    ));
+
+    let response = server
+        .post("/invoices")
+        .add_header(TEST_IP_HEADER, TEST_IP)
+        .multipart(form)
+        .await;
+
+    assert_job_completed(&server, &response).await;
+    let body: Value = response.json();
+    assert_eq!(body["bic"], "TGBATRIS");
+}
+
+#[tokio::test]
+async fn company_invoice_keeps_business_id_and_vat_number() {
+    let server = create_test_server().await;
+    let form = create_invoice_form(&invoice_from_company("0112038-9", "FI 0112 0389"));
+
+    let response = server
+        .post("/invoices")
+        .add_header(TEST_IP_HEADER, TEST_IP)
+        .multipart(form)
+        .await;
+
+    assert_job_completed(&server, &response).await;
+    let body: Value = response.json();
+    assert_eq!(body["business_id"], "0112038-9");
+    assert_eq!(body["vat_number"], "FI 0112 0389");
+}
+
+#[tokio::test]
+async fn reject_invalid_business_id() {
+    let server = create_test_server().await;
+    let form = create_invoice_form(&invoice_from_company("0112038-8", "DE123456789"));
+
+    let response = server
+        .post("/invoices")
+        .add_header(TEST_IP_HEADER, TEST_IP)
+        .multipart(form)
+        .await;
+
+    response.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+    let body: Value = response.json();
+    let expected: Value = serde_json::json!({
+        "errors": [[
+            [["key", "data"], ["key", "business_id"]],
+            { "message": "the check digit of the business ID is invalid" }
+        ]]
+    });
+    assert_eq!(body, expected);
+}
+
+#[tokio::test]
+async fn reject_invalid_vat_number() {
+    let server = create_test_server().await;
+    let form = create_invoice_form(&invoice_from_company("0112038-9", "GB123456789"));
+
+    let response = server
+        .post("/invoices")
+        .add_header(TEST_IP_HEADER, TEST_IP)
+        .multipart(form)
+        .await;
+
+    response.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+    let body: Value = response.json();
+    let expected: Value = serde_json::json!({
+        "errors": [[
+            [["key", "data"], ["key", "vat_number"]],
+            { "message": "not a valid EU VAT number, e.g. FI12345671" }
+        ]]
+    });
+    assert_eq!(body, expected);
+}
+
+#[tokio::test]
+async fn due_date_defaults_to_payment_terms() {
+    let server = create_test_server().await;
+    let form = create_invoice_form(&valid_invoice_json());
+
+    let response = server
+        .post("/invoices")
+        .add_header(TEST_IP_HEADER, TEST_IP)
+        .multipart(form)
+        .await;
+
+    assert_job_completed(&server, &response).await;
+    let body: Value = response.json();
+    let issued_at = chrono::DateTime::parse_from_rfc3339(body["issued_at"].as_str().unwrap())
+        .expect("The invoice is not dated");
+    // The invoice is dated in Helsinki time, whatever the time zone of the server
+    let helsinki = local_time::at(issued_at.to_utc());
+    assert_eq!(issued_at.offset(), helsinki.offset());
+    let expected = issued_at.date_naive() + chrono::Days::new(14);
+    assert_eq!(body["due_date"], expected.format("%Y-%m-%d").to_string());
+}
+
+#[tokio::test]
+async fn requested_due_date_is_kept() {
+    let server = create_test_server().await;
+    let due_date = (local_time::today() + chrono::Days::new(30))
+        .format("%Y-%m-%d")
+        .to_string();
+    let form = create_invoice_form(&invoice_with_due_date(&due_date));
+
+    let response = server
+        .post("/invoices")
+        .add_header(TEST_IP_HEADER, TEST_IP)
+        .multipart(form)
+        .await;
+
+    assert_job_completed(&server, &response).await;
+    let body: Value = response.json();
+    assert_eq!(body["due_date"], due_date);
+}
+
+#[tokio::test]
+async fn reject_past_due_date() {
+    let server = create_test_server().await;
+    let form = create_invoice_form(&invoice_with_due_date("2020-01-01"));
+
+    let response = server
+        .post("/invoices")
+        .add_header(TEST_IP_HEADER, TEST_IP)
+        .multipart(form)
+        .await;
+
+    response.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+    let body: Value = response.json();
+    let expected: Value = serde_json::json!({
+        "errors": [[
+            [["key", "data"], ["key", "due_date"]],
+            { "message": "the due date is in the past" }
+        ]]
+    });
+    assert_eq!(body, expected);
 }
 
 #[tokio::test]
@@ -344,7 +551,7 @@ async fn accept_uppercase_file_extensions() {
         .multipart(form)
         .await;
 
-    response.assert_status(StatusCode::CREATED);
+    assert_job_completed(&server, &response).await;
 }
 
 #[tokio::test]
@@ -0,0 +1,128 @@
+mod common;
+
+use axum::http::StatusCode;
+use axum_test::TestServer;
+use common::{
+    create_invoice_form, create_test_server, fixtures::valid_invoice_json, wait_for_job, TEST_IP,
+    TEST_IP_HEADER,
+};
+use serde_json::Value;
+
+const ADMIN_TOKEN: &str = "test-admin-token";
+
+// The configuration is read only once, so every test sets the admin token
+async fn create_server() -> TestServer {
+    std::env::set_var("ADMIN_TOKEN", ADMIN_TOKEN);
+    create_test_server().await
+}
+
+#[tokio::test]
+async fn submitted_invoice_is_processed_by_job() {
+    let server = create_server().await;
+
+    let response = server
+        .post("/invoices")
+        .add_header(TEST_IP_HEADER, TEST_IP)
+        .multipart(create_invoice_form(&valid_invoice_json()))
+        .await;
+
+    response.assert_status(StatusCode::ACCEPTED);
+    let response_json: Value = response.json();
+    let id = response_json["id"].as_str().unwrap();
+    assert_eq!(response.header("location"), format!("/jobs/{id}").as_str());
+
+    let job = wait_for_job(&server, id).await;
+    assert_eq!(job["id"], id);
+    assert_eq!(job["status"], "completed");
+    assert_eq!(job["attempts"], 1);
+    assert!(job["last_error"].is_null());
+    assert_eq!(job["invoice"]["subject"], "Test Invoice");
+}
+
+#[tokio::test]
+async fn unknown_job_is_not_found() {
+    let server = create_server().await;
+
+    server
+        .get("/jobs/00000000-0000-4000-8000-000000000000")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    server
+        .get("/jobs/not-a-job")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn outbox_requires_admin_token() {
+    let server = create_server().await;
+
+    server
+        .get("/admin/outbox")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    server
+        .post("/admin/outbox/00000000-0000-4000-8000-000000000000/resend")
+        .authorization_bearer("wrong-token")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    server
+        .get("/admin/outbox/mail")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn unknown_email_is_not_resent() {
+    let server = create_server().await;
+
+    let response = server
+        .get("/admin/outbox/mail")
+        .authorization_bearer(ADMIN_TOKEN)
+        .await;
+    response.assert_status_ok();
+    let _: Vec<Value> = response.json();
+
+    server
+        .post("/admin/outbox/mail/00000000-0000-4000-8000-000000000000/resend")
+        .authorization_bearer(ADMIN_TOKEN)
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn sent_invoice_is_not_in_outbox() {
+    let server = create_server().await;
+
+    let response = server
+        .post("/invoices")
+        .add_header(TEST_IP_HEADER, TEST_IP)
+        .multipart(create_invoice_form(&valid_invoice_json()))
+        .await;
+    let response_json: Value = response.json();
+    let id = response_json["id"].as_str().unwrap();
+    wait_for_job(&server, id).await;
+
+    let response = server
+        .get("/admin/outbox")
+        .authorization_bearer(ADMIN_TOKEN)
+        .await;
+    response.assert_status_ok();
+    let outbox: Vec<Value> = response.json();
+    assert!(outbox.iter().all(|job| job["id"] != id));
+
+    server
+        .post(&format!("/admin/outbox/{id}/resend"))
+        .authorization_bearer(ADMIN_TOKEN)
+        .await
+        .assert_status(StatusCode::CONFLICT);
+
+    server
+        .post("/admin/outbox/00000000-0000-4000-8000-000000000000/resend")
+        .authorization_bearer(ADMIN_TOKEN)
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
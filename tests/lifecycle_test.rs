@@ -0,0 +1,273 @@
+mod common;
+
+use axum::http::StatusCode;
+use axum_test::TestServer;
+use common::{
+    create_invoice_form, create_test_server, fixtures::valid_invoice_json, wait_for_job, TEST_IP,
+    TEST_IP_HEADER,
+};
+use laskugeneraattori::notifications::{unsubscribe_token, UNSUBSCRIBED};
+use serde_json::{json, Value};
+
+const ADMIN_TOKEN: &str = "test-admin-token";
+
+// The configuration is read only once, so every test sets the same variables
+async fn create_server() -> TestServer {
+    std::env::set_var("ADMIN_TOKEN", ADMIN_TOKEN);
+    std::env::set_var("PUBLIC_URL", "https://laskut.example.com");
+    std::env::set_var("NOTIFICATION_SECRET", "test-notification-secret");
+    std::env::set_var(
+        "UNSUBSCRIBE_LIST",
+        std::env::temp_dir().join(format!(
+            "laskugeneraattori-unsubscribed-{}.txt",
+            std::process::id()
+        )),
+    );
+    create_test_server().await
+}
+
+async fn submit_invoice(server: &TestServer) -> String {
+    let response = server
+        .post("/invoices")
+        .add_header(TEST_IP_HEADER, TEST_IP)
+        .multipart(create_invoice_form(&valid_invoice_json()))
+        .await;
+    let response_json: Value = response.json();
+    let id = response_json["id"].as_str().unwrap().to_string();
+    wait_for_job(server, &id).await;
+    id
+}
+
+#[tokio::test]
+async fn invoice_is_approved_and_paid() {
+    let server = create_server().await;
+    let id = submit_invoice(&server).await;
+
+    let job: Value = server.get(&format!("/jobs/{id}")).await.json();
+    assert_eq!(job["state"], json!({ "status": "submitted" }));
+
+    // Invoices are only approved with a voucher
+    server
+        .post(&format!("/admin/invoices/{id}/state"))
+        .authorization_bearer(ADMIN_TOKEN)
+        .json(&json!({ "status": "approved", "meeting": "Hallituksen kokous 5/2025" }))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+    let job: Value = server.get(&format!("/jobs/{id}")).await.json();
+    assert_eq!(job["state"], json!({ "status": "submitted" }));
+
+    let response = server
+        .post("/admin/invoices/approve")
+        .authorization_bearer(ADMIN_TOKEN)
+        .json(&json!({
+            "date": "2025-05-20",
+            "meeting": 5,
+            "invoices": [{ "id": id, "account": "4000" }]
+        }))
+        .await;
+    response.assert_status_ok();
+    let job = &response.json::<Value>()["approved"][0];
+    assert_eq!(job["state"]["status"], "approved");
+    assert_eq!(job["state"]["meeting"], "Hallituksen kokous 5/2025");
+
+    let response = server
+        .post(&format!("/admin/invoices/{id}/state"))
+        .authorization_bearer(ADMIN_TOKEN)
+        .json(&json!({ "status": "paid", "date": "2025-05-20" }))
+        .await;
+    response.assert_status_ok();
+
+    let job: Value = server.get(&format!("/jobs/{id}")).await.json();
+    assert_eq!(
+        job["state"],
+        json!({ "status": "paid", "date": "2025-05-20" })
+    );
+}
+
+#[tokio::test]
+async fn invoice_is_not_paid_before_approval() {
+    let server = create_server().await;
+    let id = submit_invoice(&server).await;
+
+    server
+        .post(&format!("/admin/invoices/{id}/state"))
+        .authorization_bearer(ADMIN_TOKEN)
+        .json(&json!({ "status": "paid", "date": "2025-05-20" }))
+        .await
+        .assert_status(StatusCode::CONFLICT);
+
+    server
+        .post(&format!("/admin/invoices/{id}/state"))
+        .authorization_bearer(ADMIN_TOKEN)
+        .json(&json!({ "status": "rejected", "reason": "" }))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    server
+        .post(&format!("/admin/invoices/{id}/state"))
+        .json(&json!({ "status": "rejected", "reason": "Duplicate" }))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn unsubscribe_link_stops_notifications() {
+    let server = create_server().await;
+    let email = "unsubscribe-test@example.com";
+
+    server
+        .get("/unsubscribe")
+        .add_query_param("email", email)
+        .add_query_param("token", "not-the-token")
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+    server
+        .post("/unsubscribe")
+        .add_query_param("email", email)
+        .add_query_param("token", "not-the-token")
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    // Opening the link only asks for a confirmation
+    let response = server
+        .get("/unsubscribe")
+        .add_query_param("email", email)
+        .add_query_param("token", unsubscribe_token(email).unwrap())
+        .await;
+    response.assert_status_ok();
+    assert!(response.text().contains("<form method=\"post\">"));
+    assert!(!UNSUBSCRIBED.contains(email).unwrap());
+
+    server
+        .post("/unsubscribe")
+        .add_query_param("email", email)
+        .add_query_param("token", unsubscribe_token(email).unwrap())
+        .await
+        .assert_status_ok();
+    assert!(UNSUBSCRIBED.contains(email).unwrap());
+}
+
+#[tokio::test]
+async fn one_click_unsubscribe_stops_notifications() {
+    let server = create_server().await;
+    let email = "one-click-test@example.com";
+
+    server
+        .post("/unsubscribe")
+        .add_query_param("email", email)
+        .add_query_param("token", unsubscribe_token(email).unwrap())
+        .form(&[("List-Unsubscribe", "One-Click")])
+        .await
+        .assert_status_ok();
+    assert!(UNSUBSCRIBED.contains(email).unwrap());
+}
+
+#[tokio::test]
+async fn agenda_is_rendered_for_admin() {
+    let server = create_server().await;
+    submit_invoice(&server).await;
+
+    server
+        .get("/admin/agenda")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    let response = server
+        .get("/admin/agenda")
+        .authorization_bearer(ADMIN_TOKEN)
+        .add_query_param("meeting", "Hallituksen kokous 5/2025")
+        .await;
+    response.assert_status_ok();
+    assert_eq!(response.header("content-type"), "application/pdf");
+    assert!(response.as_bytes().starts_with(b"%PDF-"));
+}
+
+#[tokio::test]
+async fn approved_invoices_are_stamped() {
+    let server = create_server().await;
+    let id = submit_invoice(&server).await;
+    let unknown = "00000000-0000-4000-8000-000000000000";
+
+    let approval = json!({
+        "date": "2025-05-20",
+        "meeting": 5,
+        "invoices": [
+            { "id": id, "account": "4000" },
+            { "id": unknown, "account": "4000" }
+        ]
+    });
+    let response = server
+        .post("/admin/invoices/approve")
+        .authorization_bearer(ADMIN_TOKEN)
+        .json(&approval)
+        .await;
+    response.assert_status_ok();
+    let result: Value = response.json();
+    assert_eq!(result["approved"][0]["id"], id.as_str());
+    assert_eq!(
+        result["approved"][0]["state"],
+        json!({ "status": "approved", "meeting": "Hallituksen kokous 5/2025" })
+    );
+    assert!(result["approved"][0]["approval"]["voucher"].is_string());
+    assert_eq!(result["failed"][0]["id"], unknown);
+
+    let response = server
+        .get(&format!("/admin/invoices/{id}/voucher"))
+        .authorization_bearer(ADMIN_TOKEN)
+        .await;
+    response.assert_status_ok();
+    assert_eq!(response.header("content-type"), "application/pdf");
+    assert!(response.as_bytes().starts_with(b"%PDF-"));
+
+    // The invoice has already been approved
+    let response = server
+        .post("/admin/invoices/approve")
+        .authorization_bearer(ADMIN_TOKEN)
+        .json(&approval)
+        .await;
+    let result: Value = response.json();
+    assert_eq!(result["approved"], json!([]));
+    assert_eq!(result["failed"].as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn invalid_approval_is_rejected() {
+    let server = create_server().await;
+    let id = submit_invoice(&server).await;
+
+    server
+        .post("/admin/invoices/approve")
+        .authorization_bearer(ADMIN_TOKEN)
+        .json(&json!({
+            "date": "20.5.2025",
+            "meeting": 5,
+            "invoices": [{ "id": id, "account": "4000" }]
+        }))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    server
+        .get(&format!("/admin/invoices/{id}/voucher"))
+        .authorization_bearer(ADMIN_TOKEN)
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn invoice_is_not_overdue_before_due_date() {
+    let server = create_server().await;
+    let id = submit_invoice(&server).await;
+
+    server
+        .get("/admin/invoices/overdue")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    let response = server
+        .get("/admin/invoices/overdue")
+        .authorization_bearer(ADMIN_TOKEN)
+        .await;
+    response.assert_status_ok();
+    let overdue: Vec<Value> = response.json();
+    assert!(overdue.iter().all(|invoice| invoice["id"] != id.as_str()));
+}
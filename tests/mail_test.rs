@@ -0,0 +1,224 @@
+mod common;
+
+use axum::{body::Bytes, http::StatusCode, routing::post, Router};
+use axum_test::TestServer;
+use common::{
+    create_invoice_form, create_test_server, fixtures::valid_invoice_json, setup_test_env,
+    wait_for_job, TEST_IP, TEST_IP_HEADER,
+};
+use serde_json::{json, Value};
+use std::sync::LazyLock;
+
+const ADMIN_TOKEN: &str = "test-admin-token";
+
+/// A stand-in for Mailgun that accepts the invoices but rejects the confirmations and the emails
+/// to `rejected.example.com`. The configuration is read only once, so every test sends through
+/// the same mock, which runs on its own thread to outlive the runtime of any single test.
+static MAILGUN_URL: LazyLock<String> = LazyLock::new(|| {
+    let app = Router::new().route(
+        "/messages",
+        post(|body: Bytes| async move {
+            let body = String::from_utf8_lossy(&body);
+            if body.contains("Lasku vastaanotettu") || body.contains("@rejected.example.com") {
+                (
+                    StatusCode::BAD_REQUEST,
+                    "'to' parameter is not a valid address",
+                )
+            } else {
+                (StatusCode::OK, "Queued. Thank you.")
+            }
+        }),
+    );
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let addr = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(async move {
+                let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+                axum::serve(listener, app).await.unwrap()
+            })
+    });
+
+    format!("http://{addr}/messages")
+});
+
+async fn create_server() -> TestServer {
+    setup_test_env();
+    std::env::set_var("MAILGUN_DISABLE", "false");
+    std::env::set_var("MAILGUN_URL", &*MAILGUN_URL);
+    std::env::set_var("MAILGUN_USER", "api");
+    std::env::set_var("MAILGUN_PASSWORD", "key");
+    std::env::set_var("MAILGUN_TO", "Treasurer <treasurer@example.com>");
+    std::env::set_var("MAILGUN_FROM", "Laskugeneraattori <laskut@example.com>");
+    std::env::set_var("ADMIN_TOKEN", ADMIN_TOKEN);
+    std::env::set_var("ISSUER_IBAN", "FI21 1234 5600 0007 85");
+    std::env::set_var(
+        "OUTBOUND_DIR",
+        std::env::temp_dir().join(format!("laskugeneraattori-outbound-{}", std::process::id())),
+    );
+    create_test_server().await
+}
+
+fn outbound_invoice_json(email: &str) -> Value {
+    json!({
+        "customer": {
+            "name": "Yritys Oy",
+            "address": { "street": "Testikatu 1", "city": "Helsinki", "zip": "00100" },
+            "email": email
+        },
+        "subject": "Sponsorointi",
+        "rows": [{ "product": "Pääyhteistyökumppanuus", "unit_price": 150000 }]
+    })
+}
+
+async fn issue(server: &TestServer, email: &str) -> String {
+    let response = server
+        .post("/admin/outbound-invoices")
+        .authorization_bearer(ADMIN_TOKEN)
+        .json(&outbound_invoice_json(email))
+        .await;
+    response.assert_status(StatusCode::CREATED);
+    response.json::<Value>()["number"]
+        .as_str()
+        .unwrap()
+        .to_string()
+}
+
+/// Returns the outbox entry of the first email matching the filter once it has failed
+async fn wait_for_failed_mail(server: &TestServer, filter: impl Fn(&Value) -> bool) -> Value {
+    for _ in 0..600 {
+        let outbox: Vec<Value> = server
+            .get("/admin/outbox/mail")
+            .authorization_bearer(ADMIN_TOKEN)
+            .await
+            .json();
+        if let Some(mail) = outbox.into_iter().find(|mail| filter(&mail["mail"])) {
+            return mail;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+
+    panic!("The email did not fail");
+}
+
+#[tokio::test]
+async fn rejected_confirmation_does_not_fail_invoice() {
+    let server = create_server().await;
+    let mut invoice = valid_invoice_json();
+    invoice["subject"] = "Invoice with a rejected confirmation".into();
+
+    let response = server
+        .post("/invoices")
+        .add_header(TEST_IP_HEADER, TEST_IP)
+        .multipart(create_invoice_form(&invoice))
+        .await;
+    response.assert_status(StatusCode::ACCEPTED);
+    let id = response.json::<Value>()["id"].as_str().unwrap().to_string();
+
+    // The invoice was delivered to the treasurer even though the confirmation wasn't
+    let job = wait_for_job(&server, &id).await;
+    assert_eq!(job["status"], "completed", "{}", job["last_error"]);
+    assert_eq!(job["attempts"], 1);
+
+    // Only the confirmation is kept in the outbox, as a rejected email isn't retried
+    let mail = wait_for_failed_mail(&server, |mail| mail["invoice_id"] == id.as_str()).await;
+    assert_eq!(mail["mail"]["kind"], "confirmation");
+    assert_eq!(mail["status"], "failed");
+    assert!(mail["last_error"]
+        .as_str()
+        .unwrap()
+        .contains("not a valid address"));
+
+    let outbox: Vec<Value> = server
+        .get("/admin/outbox")
+        .authorization_bearer(ADMIN_TOKEN)
+        .await
+        .json();
+    assert!(outbox.iter().all(|job| job["id"] != id.as_str()));
+
+    let entries: Vec<Value> = server
+        .get("/admin/audit-log")
+        .add_query_param("invoice_id", &id)
+        .authorization_bearer(ADMIN_TOKEN)
+        .await
+        .json();
+    let events: Vec<_> = entries
+        .iter()
+        .map(|entry| (entry["event"].as_str().unwrap(), &entry["details"]))
+        .collect();
+    assert!(events.contains(&("email_sent", &Value::Null)));
+    assert!(events
+        .iter()
+        .any(|(event, details)| *event == "email_failed"
+            && details
+                .as_str()
+                .unwrap()
+                .starts_with("Confirmation to the submitter")));
+
+    // The receipts of the invoice were recorded when it was submitted
+    let response = server
+        .post("/invoices")
+        .add_header(TEST_IP_HEADER, TEST_IP)
+        .multipart(create_invoice_form(&invoice))
+        .await;
+    response.assert_status(StatusCode::ACCEPTED);
+    let second = wait_for_job(&server, response.json::<Value>()["id"].as_str().unwrap()).await;
+    assert!(second["invoice"]["possible_duplicates"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|duplicate| duplicate["previous_invoice_id"] == id.as_str()));
+}
+
+#[tokio::test]
+async fn outbound_invoice_is_sent_by_worker() {
+    let server = create_server().await;
+    let number = issue(&server, "laskut@example.com").await;
+
+    for _ in 0..600 {
+        let invoices: Vec<Value> = server
+            .get("/admin/outbound-invoices")
+            .authorization_bearer(ADMIN_TOKEN)
+            .await
+            .json();
+        let invoice = invoices
+            .iter()
+            .find(|invoice| invoice["number"] == number.as_str());
+        if !invoice.unwrap()["sent_at"].is_null() {
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+
+    panic!("Outbound invoice {number} was not sent");
+}
+
+#[tokio::test]
+async fn rejected_outbound_invoice_is_kept_in_outbox() {
+    let server = create_server().await;
+
+    // The invoice is issued even though it can't be delivered
+    let number = issue(&server, "laskut@rejected.example.com").await;
+    let mail = wait_for_failed_mail(&server, |mail| mail["number"] == number.as_str()).await;
+    assert_eq!(mail["mail"]["kind"], "outbound");
+
+    let invoices: Vec<Value> = server
+        .get("/admin/outbound-invoices")
+        .authorization_bearer(ADMIN_TOKEN)
+        .await
+        .json();
+    let invoice = invoices
+        .iter()
+        .find(|invoice| invoice["number"] == number.as_str())
+        .unwrap();
+    assert_eq!(invoice["sent_at"], Value::Null);
+
+    // Sending it again queues another attempt
+    server
+        .post(&format!("/admin/outbound-invoices/{number}/send"))
+        .authorization_bearer(ADMIN_TOKEN)
+        .await
+        .assert_status(StatusCode::ACCEPTED);
+}
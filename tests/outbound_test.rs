@@ -0,0 +1,180 @@
+mod common;
+
+use axum::http::StatusCode;
+use axum_test::TestServer;
+use common::create_test_server;
+use serde_json::{json, Value};
+
+const ADMIN_TOKEN: &str = "test-admin-token";
+
+// The configuration is read only once, so every test sets the same variables. The tests share
+// the numbering, so they don't expect particular numbers.
+async fn create_server() -> TestServer {
+    std::env::set_var("ADMIN_TOKEN", ADMIN_TOKEN);
+    std::env::set_var("ISSUER_IBAN", "FI21 1234 5600 0007 85");
+    std::env::set_var("ISSUER_BUSINESS_ID", "1234567-1");
+    std::env::set_var("ISSUER_EMAIL", "rahastonhoitaja@tietokilta.fi");
+    std::env::set_var(
+        "OUTBOUND_DIR",
+        std::env::temp_dir().join(format!("laskugeneraattori-outbound-{}", std::process::id())),
+    );
+    create_test_server().await
+}
+
+fn outbound_invoice_json() -> Value {
+    json!({
+        "customer": {
+            "name": "Yritys Oy",
+            "business_id": "7654321-2",
+            "contact": "Maija Meikäläinen",
+            "address": { "street": "Testikatu 1", "city": "Helsinki", "zip": "00100" },
+            "email": "laskut@example.com"
+        },
+        "subject": "Sponsorointi",
+        "description": "Vuosijuhlien yhteistyö",
+        "rows": [
+            { "product": "Pääyhteistyökumppanuus", "unit_price": 150000 },
+            { "product": "Logo haalarimerkissä", "unit_price": 25000 }
+        ]
+    })
+}
+
+async fn issue(server: &TestServer, invoice: &Value) -> Value {
+    let response = server
+        .post("/admin/outbound-invoices")
+        .authorization_bearer(ADMIN_TOKEN)
+        .json(invoice)
+        .await;
+    response.assert_status(StatusCode::CREATED);
+    response.json()
+}
+
+#[tokio::test]
+async fn outbound_invoice_is_issued() {
+    let server = create_server().await;
+    let invoice = issue(&server, &outbound_invoice_json()).await;
+
+    let number = invoice["number"].as_str().unwrap();
+    assert!(number.parse::<u32>().unwrap() >= 1000);
+    assert!(!invoice["reference"].as_str().unwrap().is_empty());
+    assert_eq!(
+        invoice["issuer"]["bank_account_number"],
+        "FI21 1234 5600 0007 85"
+    );
+    assert_eq!(invoice["issuer"]["business_id"], "1234567-1");
+    assert_eq!(invoice["customer"]["name"], "Yritys Oy");
+    // Mailgun is disabled in the tests, so the invoice is left unsent
+    assert_eq!(invoice["sent_at"], Value::Null);
+
+    let issued = chrono::DateTime::parse_from_rfc3339(invoice["issued_at"].as_str().unwrap())
+        .unwrap()
+        .date_naive();
+    let expected_due = (issued + chrono::Days::new(14))
+        .format("%Y-%m-%d")
+        .to_string();
+    assert_eq!(invoice["due_date"], expected_due);
+
+    let response = server
+        .get(&format!("/admin/outbound-invoices/{number}/pdf"))
+        .authorization_bearer(ADMIN_TOKEN)
+        .await;
+    response.assert_status_ok();
+    assert!(response.as_bytes().starts_with(b"%PDF-"));
+
+    let invoices: Vec<Value> = server
+        .get("/admin/outbound-invoices")
+        .authorization_bearer(ADMIN_TOKEN)
+        .await
+        .json();
+    assert!(invoices.iter().any(|listed| listed["number"] == number));
+}
+
+#[tokio::test]
+async fn issued_invoices_are_numbered_in_order() {
+    let server = create_server().await;
+    let first = issue(&server, &outbound_invoice_json()).await;
+    let second = issue(&server, &outbound_invoice_json()).await;
+
+    let first: u32 = first["number"].as_str().unwrap().parse().unwrap();
+    let second: u32 = second["number"].as_str().unwrap().parse().unwrap();
+    assert!(second > first);
+}
+
+#[tokio::test]
+async fn requested_due_date_is_kept() {
+    let server = create_server().await;
+    let due_date = (chrono::Local::now().date_naive() + chrono::Days::new(30))
+        .format("%Y-%m-%d")
+        .to_string();
+    let mut request = outbound_invoice_json();
+    request["due_date"] = json!(due_date);
+
+    let invoice = issue(&server, &request).await;
+    assert_eq!(invoice["due_date"], due_date);
+}
+
+#[tokio::test]
+async fn reject_invalid_outbound_invoice() {
+    let server = create_server().await;
+    let mut request = outbound_invoice_json();
+    request["rows"] = json!([]);
+
+    let response = server
+        .post("/admin/outbound-invoices")
+        .authorization_bearer(ADMIN_TOKEN)
+        .json(&request)
+        .await;
+    response.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn reject_invalid_customer_email() {
+    let server = create_server().await;
+    let mut request = outbound_invoice_json();
+    request["customer"]["email"] = json!("laskut.example.com");
+
+    let response = server
+        .post("/admin/outbound-invoices")
+        .authorization_bearer(ADMIN_TOKEN)
+        .json(&request)
+        .await;
+    response.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn outbound_invoices_require_admin_token() {
+    let server = create_server().await;
+
+    let response = server
+        .post("/admin/outbound-invoices")
+        .json(&outbound_invoice_json())
+        .await;
+    response.assert_status(StatusCode::UNAUTHORIZED);
+
+    let response = server.get("/admin/outbound-invoices").await;
+    response.assert_status(StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn unknown_outbound_invoice_is_not_found() {
+    let server = create_server().await;
+
+    let response = server
+        .get("/admin/outbound-invoices/999999/pdf")
+        .authorization_bearer(ADMIN_TOKEN)
+        .await;
+    response.assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn sending_requires_mailgun() {
+    let server = create_server().await;
+    let invoice = issue(&server, &outbound_invoice_json()).await;
+    let number = invoice["number"].as_str().unwrap();
+
+    let response = server
+        .post(&format!("/admin/outbound-invoices/{number}/send"))
+        .authorization_bearer(ADMIN_TOKEN)
+        .await;
+    response.assert_status(StatusCode::NOT_IMPLEMENTED);
+}
@@ -2,8 +2,8 @@ mod common;
 
 use axum::http::StatusCode;
 use common::{
-    create_invoice_form, create_invoice_form_with_file, create_invoice_form_with_files,
-    create_test_server,
+    assert_job_completed, create_invoice_form, create_invoice_form_with_file,
+    create_invoice_form_with_files, create_test_server,
     fixtures::{invoice_with_attachment_descriptions, valid_invoice_json},
     load_test_file, TEST_IP, TEST_IP_HEADER,
 };
@@ -21,7 +21,7 @@ async fn invoice_creation_returns_valid_json_response() {
         .multipart(form)
         .await;
 
-    response.assert_status(StatusCode::CREATED);
+    assert_job_completed(&server, &response).await;
     let response_json: Value = response.json();
     assert_eq!(response_json["recipient_name"], "Test User");
     assert_eq!(response_json["subject"], "Test Invoice");
@@ -40,7 +40,7 @@ async fn invoice_with_image_returns_attachment_info() {
         .multipart(form)
         .await;
 
-    response.assert_status(StatusCode::CREATED);
+    assert_job_completed(&server, &response).await;
     let response_json: Value = response.json();
     let attachments = response_json["attachments"].as_array().unwrap();
     assert_eq!(attachments.len(), 1);
@@ -60,7 +60,7 @@ async fn invoice_with_pdf_attachment_returns_attachment_info() {
         .multipart(form)
         .await;
 
-    response.assert_status(StatusCode::CREATED);
+    assert_job_completed(&server, &response).await;
     let response_json: Value = response.json();
     let attachments = response_json["attachments"].as_array().unwrap();
     assert_eq!(attachments.len(), 1);
@@ -84,7 +84,7 @@ async fn invoice_with_multiple_attachments_returns_all_attachment_info() {
         .multipart(form)
         .await;
 
-    response.assert_status(StatusCode::CREATED);
+    assert_job_completed(&server, &response).await;
     let response_json: Value = response.json();
     let attachments = response_json["attachments"].as_array().unwrap();
     assert_eq!(attachments.len(), 2);
@@ -106,7 +106,7 @@ async fn invoice_rows_are_preserved_in_response() {
         .multipart(form)
         .await;
 
-    response.assert_status(StatusCode::CREATED);
+    assert_job_completed(&server, &response).await;
     let response_json: Value = response.json();
     let rows = response_json["rows"].as_array().unwrap();
     assert_eq!(rows.len(), 2);
@@ -115,3 +115,106 @@ async fn invoice_rows_are_preserved_in_response() {
     assert_eq!(rows[1]["product"], "Product B");
     assert_eq!(rows[1]["unit_price"], 2500);
 }
+
+#[tokio::test]
+async fn create_invoice_with_rotated_photos_succeeds() {
+    let server = create_test_server().await;
+    let invoice = invoice_with_attachment_descriptions(vec!["Upside down", "Sideways", "Sideways"]);
+    let form = create_invoice_form_with_files(
+        &invoice,
+        vec![
+            (
+                "rotated-180.jpg",
+                load_test_file("regression/exif-orientation-3.jpg"),
+            ),
+            (
+                "rotated-90.jpg",
+                load_test_file("regression/exif-orientation-6.jpg"),
+            ),
+            (
+                "rotated-270.jpg",
+                load_test_file("regression/exif-orientation-8.jpg"),
+            ),
+        ],
+    );
+
+    let response = server
+        .post("/invoices")
+        .add_header(TEST_IP_HEADER, TEST_IP)
+        .multipart(form)
+        .await;
+
+    assert_job_completed(&server, &response).await;
+    let response_json: Value = response.json();
+    let attachments = response_json["attachments"].as_array().unwrap();
+    assert_eq!(attachments.len(), 3);
+}
+
+#[tokio::test]
+async fn reject_corrupt_pdf_attachment() {
+    let server = create_test_server().await;
+    let invoice = invoice_with_attachment_descriptions(vec!["Receipt", "Broken receipt"]);
+    let form = create_invoice_form_with_files(
+        &invoice,
+        vec![
+            ("receipt.pdf", load_test_file("test.pdf")),
+            ("broken.pdf", load_test_file("regression/corrupt.pdf")),
+        ],
+    );
+
+    let response = server
+        .post("/invoices")
+        .add_header(TEST_IP_HEADER, TEST_IP)
+        .multipart(form)
+        .await;
+
+    response.assert_status(StatusCode::BAD_REQUEST);
+    let body: Value = response.json();
+    let expected: Value = serde_json::json!({
+        "error": "Attachment broken.pdf is not a valid PDF file"
+    });
+    assert_eq!(body, expected);
+}
+
+#[tokio::test]
+async fn create_invoice_with_owner_password_pdf_succeeds() {
+    let server = create_test_server().await;
+    let invoice = invoice_with_attachment_descriptions(vec!["Webshop receipt"]);
+    let form = create_invoice_form_with_file(
+        &invoice,
+        "receipt.pdf",
+        load_test_file("regression/encrypted-owner-password.pdf"),
+    );
+
+    let response = server
+        .post("/invoices")
+        .add_header(TEST_IP_HEADER, TEST_IP)
+        .multipart(form)
+        .await;
+
+    assert_job_completed(&server, &response).await;
+}
+
+#[tokio::test]
+async fn reject_password_protected_pdf_attachment() {
+    let server = create_test_server().await;
+    let invoice = invoice_with_attachment_descriptions(vec!["Bank statement"]);
+    let form = create_invoice_form_with_file(
+        &invoice,
+        "statement.pdf",
+        load_test_file("regression/encrypted-user-password.pdf"),
+    );
+
+    let response = server
+        .post("/invoices")
+        .add_header(TEST_IP_HEADER, TEST_IP)
+        .multipart(form)
+        .await;
+
+    response.assert_status(StatusCode::BAD_REQUEST);
+    let body: Value = response.json();
+    let expected: Value = serde_json::json!({
+        "error": "Attachment statement.pdf is password protected, remove the password and try again"
+    });
+    assert_eq!(body, expected);
+}
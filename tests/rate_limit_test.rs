@@ -31,7 +31,7 @@ async fn rate_limiting_blocks_after_burst_exceeded() {
             .await;
         assert_eq!(
             response.status_code(),
-            StatusCode::CREATED,
+            StatusCode::ACCEPTED,
             "Request {} should succeed within burst limit",
             i + 1
         );
@@ -0,0 +1,140 @@
+mod common;
+
+use axum::http::StatusCode;
+use axum_test::multipart::{MultipartForm, Part};
+use axum_test::TestServer;
+use common::{
+    create_invoice_form, create_test_server, fixtures::valid_invoice_json, load_test_file,
+    wait_for_job, TEST_IP, TEST_IP_HEADER,
+};
+use laskugeneraattori::{api::invoices::InvoiceAttachment, merge::merge_pdf, signing::Signer};
+use serde_json::Value;
+use std::sync::LazyLock;
+use tempfile::TempDir;
+
+// The configuration is read only once, so every test uses the same certificate
+static CERTIFICATE_DIR: LazyLock<TempDir> = LazyLock::new(|| {
+    let key = rcgen::KeyPair::generate().unwrap();
+    let mut params = rcgen::CertificateParams::new(vec![]).unwrap();
+    params
+        .distinguished_name
+        .push(rcgen::DnType::CommonName, "Laskugeneraattori");
+    let certificate = params.self_signed(&key).unwrap();
+
+    let dir = TempDir::new().unwrap();
+    std::fs::write(dir.path().join("certificate.pem"), certificate.pem()).unwrap();
+    std::fs::write(dir.path().join("key.pem"), key.serialize_pem()).unwrap();
+    dir
+});
+
+async fn create_signing_server() -> TestServer {
+    let dir = CERTIFICATE_DIR.path();
+    std::env::set_var("SIGNING_CERTIFICATE", dir.join("certificate.pem"));
+    std::env::set_var("SIGNING_KEY", dir.join("key.pem"));
+    create_test_server().await
+}
+
+fn service_signer() -> Signer {
+    let dir = CERTIFICATE_DIR.path();
+    Signer::from_pem(
+        &std::fs::read_to_string(dir.join("certificate.pem")).unwrap(),
+        &std::fs::read_to_string(dir.join("key.pem")).unwrap(),
+    )
+    .unwrap()
+}
+
+fn signed_pdf() -> Vec<u8> {
+    let pdf = merge_pdf(vec![InvoiceAttachment {
+        filename: "test.pdf".to_string(),
+        bytes: load_test_file("test.pdf"),
+    }
+    .into()])
+    .unwrap();
+
+    service_signer().sign_pdf(pdf).unwrap()
+}
+
+fn verification_form(filename: &str, content: Vec<u8>) -> MultipartForm {
+    MultipartForm::new().add_part("file", Part::bytes(content).file_name(filename))
+}
+
+#[tokio::test]
+async fn create_invoice_with_signing_succeeds() {
+    let server = create_signing_server().await;
+    let form = create_invoice_form(&valid_invoice_json());
+
+    let response = server
+        .post("/invoices")
+        .add_header(TEST_IP_HEADER, TEST_IP)
+        .multipart(form)
+        .await;
+
+    response.assert_status(StatusCode::ACCEPTED);
+    let response_json: Value = response.json();
+    let job = wait_for_job(&server, response_json["id"].as_str().unwrap()).await;
+    assert_eq!(job["status"], "completed");
+}
+
+#[tokio::test]
+async fn verify_signed_pdf_is_valid() {
+    let server = create_signing_server().await;
+
+    let response = server
+        .post("/signatures/verify")
+        .add_header(TEST_IP_HEADER, TEST_IP)
+        .multipart(verification_form("invoice.pdf", signed_pdf()))
+        .await;
+
+    response.assert_status_ok();
+    let response_json: Value = response.json();
+    assert_eq!(response_json["valid"], true);
+    assert_eq!(response_json["signer"], "CN=Laskugeneraattori");
+    assert!(response_json["signed_at"].is_string());
+}
+
+#[tokio::test]
+async fn verify_modified_pdf_is_invalid() {
+    let server = create_signing_server().await;
+    let mut pdf = signed_pdf();
+    pdf.extend_from_slice(b"\n% appended after signing\n");
+
+    let response = server
+        .post("/signatures/verify")
+        .add_header(TEST_IP_HEADER, TEST_IP)
+        .multipart(verification_form("invoice.pdf", pdf))
+        .await;
+
+    response.assert_status_ok();
+    let response_json: Value = response.json();
+    assert_eq!(response_json["valid"], false);
+    assert!(response_json["reason"].is_string());
+}
+
+#[tokio::test]
+async fn verify_unsigned_pdf_is_invalid() {
+    let server = create_signing_server().await;
+
+    let response = server
+        .post("/signatures/verify")
+        .add_header(TEST_IP_HEADER, TEST_IP)
+        .multipart(verification_form("test.pdf", load_test_file("test.pdf")))
+        .await;
+
+    response.assert_status_ok();
+    let response_json: Value = response.json();
+    assert_eq!(response_json["valid"], false);
+    assert_eq!(response_json["reason"], "The PDF is not signed");
+}
+
+#[tokio::test]
+async fn verify_invalid_file_fails() {
+    let server = create_signing_server().await;
+
+    let response = server
+        .post("/signatures/verify")
+        .add_header(TEST_IP_HEADER, TEST_IP)
+        .multipart(verification_form("test.jpg", load_test_file("test.jpg")))
+        .await;
+
+    response.assert_status(StatusCode::BAD_REQUEST);
+}
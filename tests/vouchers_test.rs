@@ -0,0 +1,97 @@
+mod common;
+
+use axum_test::TestServer;
+use common::{
+    create_invoice_form, create_test_server, fixtures::valid_invoice_json, wait_for_job, TEST_IP,
+    TEST_IP_HEADER,
+};
+use futures::future::join_all;
+use serde_json::{json, Value};
+
+const ADMIN_TOKEN: &str = "test-admin-token";
+
+// The configuration is read only once, so every test sets the same variables. Each test
+// approves its invoices in a fiscal year of its own, so that the tests don't share numbers.
+async fn create_server() -> TestServer {
+    std::env::set_var("ADMIN_TOKEN", ADMIN_TOKEN);
+    std::env::set_var("VOUCHER_PREFIX", "{year}-");
+    std::env::set_var("VOUCHER_START", "100");
+    create_test_server().await
+}
+
+async fn submit_invoice(server: &TestServer) -> String {
+    let response = server
+        .post("/invoices")
+        .add_header(TEST_IP_HEADER, TEST_IP)
+        .multipart(create_invoice_form(&valid_invoice_json()))
+        .await;
+    let response_json: Value = response.json();
+    let id = response_json["id"].as_str().unwrap().to_string();
+    wait_for_job(server, &id).await;
+    id
+}
+
+async fn approve(server: &TestServer, date: &str, ids: &[String]) -> Vec<String> {
+    let invoices: Vec<Value> = ids
+        .iter()
+        .map(|id| json!({ "id": id, "account": "4000" }))
+        .collect();
+
+    let response = server
+        .post("/admin/invoices/approve")
+        .authorization_bearer(ADMIN_TOKEN)
+        .json(&json!({ "date": date, "meeting": 5, "invoices": invoices }))
+        .await;
+    response.assert_status_ok();
+    let result: Value = response.json();
+    assert_eq!(result["failed"], json!([]));
+
+    result["approved"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|job| job["approval"]["voucher"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn vouchers_are_numbered_in_order() {
+    let server = create_server().await;
+    let mut ids = vec![];
+    for _ in 0..3 {
+        ids.push(submit_invoice(&server).await);
+    }
+
+    assert_eq!(
+        approve(&server, "2024-05-20", &ids).await,
+        ["2024-100", "2024-101", "2024-102"]
+    );
+}
+
+#[tokio::test]
+async fn parallel_approvals_have_no_gaps() {
+    let server = create_server().await;
+    let ids = join_all((0..10).map(|_| submit_invoice(&server))).await;
+
+    let approvals = ids.chunks(1).map(|ids| approve(&server, "2025-05-20", ids));
+    let mut vouchers: Vec<String> = join_all(approvals).await.concat();
+    vouchers.sort();
+
+    let expected: Vec<String> = (100..110).map(|n| format!("2025-{n}")).collect();
+    assert_eq!(vouchers, expected);
+}
+
+#[tokio::test]
+async fn numbering_resets_for_new_fiscal_year() {
+    let server = create_server().await;
+    let ids = [submit_invoice(&server).await, submit_invoice(&server).await];
+
+    assert_eq!(
+        approve(&server, "2026-12-15", &ids[..1]).await,
+        ["2026-100"]
+    );
+    assert_eq!(
+        approve(&server, "2027-01-10", &ids[1..]).await,
+        ["2027-100"]
+    );
+}
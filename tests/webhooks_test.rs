@@ -0,0 +1,246 @@
+mod common;
+
+use axum::{
+    http::{HeaderMap, StatusCode},
+    routing::post,
+    Router,
+};
+use axum_test::TestServer;
+use common::{
+    create_invoice_form, create_test_server, fixtures::valid_invoice_json, wait_for_job, TEST_IP,
+    TEST_IP_HEADER,
+};
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::{
+    collections::HashSet,
+    net::SocketAddr,
+    sync::{Arc, LazyLock, Mutex},
+    time::Duration,
+};
+
+const ADMIN_TOKEN: &str = "test-admin-token";
+const WEBHOOK_SECRET: &str = "test-webhook-secret";
+
+/// A webhook request received by the test endpoints
+#[derive(Clone, Debug)]
+struct Received {
+    path: &'static str,
+    event: String,
+    signature: String,
+    body: String,
+}
+
+/// The webhook endpoints, running on their own runtime so that they outlive the tests
+struct Receiver {
+    addr: SocketAddr,
+    received: Arc<Mutex<Vec<Received>>>,
+}
+
+static RECEIVER: LazyLock<Receiver> = LazyLock::new(|| {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let received = Arc::new(Mutex::new(Vec::new()));
+    // Fails the first attempt of every delivery
+    let failed_deliveries = Arc::new(Mutex::new(HashSet::new()));
+
+    let record = |path: &'static str, received: Arc<Mutex<Vec<Received>>>| {
+        move |headers: HeaderMap, body: String| {
+            let header = |name: &str| {
+                headers
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .unwrap_or_default()
+                    .to_string()
+            };
+            received.lock().unwrap().push(Received {
+                path,
+                event: header("x-laskugeneraattori-event"),
+                signature: header("x-laskugeneraattori-signature"),
+                body,
+            });
+            header("x-laskugeneraattori-delivery")
+        }
+    };
+    let hook = record("/hook", received.clone());
+    let flaky = record("/flaky", received.clone());
+
+    let app = Router::new()
+        .route(
+            "/hook",
+            post(move |headers: HeaderMap, body: String| {
+                hook(headers, body);
+                async { StatusCode::NO_CONTENT }
+            }),
+        )
+        .route(
+            "/flaky",
+            post(move |headers: HeaderMap, body: String| {
+                let delivery = flaky(headers, body);
+                let first_attempt = failed_deliveries.lock().unwrap().insert(delivery);
+                async move {
+                    if first_attempt {
+                        StatusCode::SERVICE_UNAVAILABLE
+                    } else {
+                        StatusCode::OK
+                    }
+                }
+            }),
+        );
+
+    std::thread::spawn(move || {
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(async move {
+                let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+                axum::serve(listener, app).await.unwrap();
+            })
+    });
+
+    Receiver { addr, received }
+});
+
+// The configuration is read only once, so every test sets the same variables
+async fn create_server() -> TestServer {
+    let addr = RECEIVER.addr;
+    std::env::set_var("ADMIN_TOKEN", ADMIN_TOKEN);
+    std::env::set_var(
+        "WEBHOOK_URLS",
+        format!("http://{addr}/hook,http://{addr}/flaky"),
+    );
+    std::env::set_var("WEBHOOK_SECRET", WEBHOOK_SECRET);
+    std::env::set_var("WEBHOOK_RETRY_BASE_SECS", "0");
+    std::env::set_var(
+        "WEBHOOK_LOG",
+        std::env::temp_dir().join(format!(
+            "laskugeneraattori-webhooks-{}.jsonl",
+            std::process::id()
+        )),
+    );
+    create_test_server().await
+}
+
+async fn submit_invoice(server: &TestServer) -> String {
+    let response = server
+        .post("/invoices")
+        .add_header(TEST_IP_HEADER, TEST_IP)
+        .multipart(create_invoice_form(&valid_invoice_json()))
+        .await;
+    response.assert_status(StatusCode::ACCEPTED);
+    let response_json: Value = response.json();
+    response_json["id"].as_str().unwrap().to_string()
+}
+
+/// Waits for the event of the invoice to arrive at `/hook` and returns its payload
+async fn wait_for_webhook(invoice_id: &str, event: &str) -> (Received, Value) {
+    for _ in 0..100 {
+        let received = RECEIVER.received.lock().unwrap().clone();
+        for request in received.into_iter().filter(|r| r.path == "/hook") {
+            let payload: Value = serde_json::from_str(&request.body).unwrap();
+            if payload["invoice"]["id"] == invoice_id && payload["event"] == event {
+                return (request, payload);
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    panic!("Webhook {event} of {invoice_id} was not delivered");
+}
+
+#[tokio::test]
+async fn submitted_invoice_is_delivered_signed() {
+    let server = create_server().await;
+    let id = submit_invoice(&server).await;
+
+    let (request, payload) = wait_for_webhook(&id, "invoice.submitted").await;
+    assert_eq!(request.event, "invoice.submitted");
+    assert_eq!(payload["state"], json!({ "status": "submitted" }));
+    assert_eq!(
+        payload["invoice"],
+        json!({ "id": id, "submitter": "Test User", "amount": 1000 })
+    );
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(WEBHOOK_SECRET.as_bytes()).unwrap();
+    mac.update(request.body.as_bytes());
+    let expected: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    assert_eq!(request.signature, format!("sha256={expected}"));
+}
+
+#[tokio::test]
+async fn state_changes_are_delivered() {
+    let server = create_server().await;
+    let id = submit_invoice(&server).await;
+    wait_for_job(&server, &id).await;
+
+    server
+        .post(&format!("/admin/invoices/{id}/state"))
+        .authorization_bearer(ADMIN_TOKEN)
+        .json(&json!({ "status": "rejected", "reason": "No receipt" }))
+        .await
+        .assert_status_ok();
+
+    let (_, payload) = wait_for_webhook(&id, "invoice.rejected").await;
+    assert_eq!(
+        payload["state"],
+        json!({ "status": "rejected", "reason": "No receipt" })
+    );
+}
+
+#[tokio::test]
+async fn failed_delivery_is_retried_and_logged() {
+    let server = create_server().await;
+    let id = submit_invoice(&server).await;
+
+    let flaky_url = format!("http://{}/flaky", RECEIVER.addr);
+    for _ in 0..100 {
+        let deliveries: Vec<Value> = server
+            .get("/admin/webhooks/deliveries")
+            .authorization_bearer(ADMIN_TOKEN)
+            .add_query_param("invoice_id", &id)
+            .await
+            .json();
+        let attempts: Vec<_> = deliveries
+            .iter()
+            .filter(|delivery| delivery["url"] == flaky_url.as_str())
+            .map(|delivery| {
+                (
+                    delivery["attempt"].clone(),
+                    delivery["status"].clone(),
+                    delivery["delivered"].clone(),
+                )
+            })
+            .collect();
+
+        if attempts.len() == 2 {
+            assert_eq!(
+                attempts,
+                vec![
+                    (json!(1), json!(503), json!(false)),
+                    (json!(2), json!(200), json!(true))
+                ]
+            );
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    panic!("The delivery to {flaky_url} was not retried");
+}
+
+#[tokio::test]
+async fn webhook_deliveries_require_admin_token() {
+    let server = create_server().await;
+
+    server
+        .get("/admin/webhooks/deliveries")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+}